@@ -1,14 +1,54 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use tangle::parser::Parser;
+use tangle::Preprocessor;
 
-fn parse() {
-    let input = include_str!("../example.c");
-    let mut parser = Parser::new(input);
-    let _ = parser.read_global_statements();
+fn parse(input: &str) {
+    let mut preprocessor = Preprocessor::new(input);
+    preprocessor.preprocess().unwrap();
+}
+
+/// Translation unit of many macro definitions, invocations and declarations.
+fn large_input() -> String {
+    let mut input = String::new();
+
+    for i in 0..2000 {
+        input.push_str(&format!(
+            "#define CONSTANT_{i} ({i} + 1)\n#define CALL_{i}(a, b) ((a) * (b) + CONSTANT_{i})\n"
+        ));
+    }
+
+    for i in 0..2000 {
+        input.push_str(&format!(
+            "/* declaration {i} */\nstatic int value_{i} = CALL_{i}(CONSTANT_{i}, value_{i} + 1);\n"
+        ));
+    }
+
+    input
+}
+
+/// Source dominated by indentation, comments and long identifiers, as in
+/// system headers.
+fn commented_input() -> String {
+    let mut input = String::new();
+
+    for i in 0..2000 {
+        input.push_str(&format!(
+            "/*\n * Returns the value of configuration entry {i}, which is computed once\n * and cached by the caller.\n */\nstatic inline unsigned long configuration_entry_value_{i}(void)\n{{\n        return CONFIGURATION_ENTRY_DEFAULT_VALUE_{i}; // fallback\n}}\n"
+        ));
+    }
+
+    input
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("lexer", |b| b.iter(|| parse()));
+    let input = include_str!("../example.c");
+    c.bench_function("lexer", |b| b.iter(|| parse(input)));
+
+    let input = large_input();
+    c.bench_function("lexer large input", |b| b.iter(|| parse(&input)));
+
+    // Without directives, so scanning dominates
+    let input = commented_input();
+    c.bench_function("lexer commented input", |b| b.iter(|| parse(&input)));
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,223 @@
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use tangle::{
+    language::{Dialect, LanguageOptions, Standard},
+    target::Target,
+    Diagnostic, Preprocessor, PreprocessorOptions,
+};
+
+const USAGE: &str = "\
+Usage: tangle [options] [file...]
+
+Preprocesses each file, or standard input if none or `-` is given.
+
+Options:
+  -E               Preprocess only, which is the only mode
+  -o <file>        Write output to <file> instead of standard output
+  -D <name>[=val]  Define <name> as <val>, or 1 if not given
+  -U <name>        Undefine <name>
+  -I <dir>         Add <dir> to include search paths
+  -include <file>  Include <file> before each input file
+  -imacros <file>  Read macros of <file> before each input file
+  -std=<standard>  Preprocess as <standard>, one of c89, c99, c11, c17, c23
+                   and their gnu variants, with aliases c90, c18 and c2x
+  --target=<arch>  Predefine macros of <arch>, one of x86_64, aarch64, armv7,
+                   riscv32 and riscv64, instead of the host
+  -P               Omit linemarkers
+  -dM              Print `#define` directives of macros defined at the end
+                   instead of output
+  -h, --help       Print this help";
+
+/// Parsed command line
+struct Args {
+    options: PreprocessorOptions,
+    inputs: Vec<String>,
+    output: Option<PathBuf>,
+    language: LanguageOptions,
+    target: Target,
+    linemarkers: bool,
+    dump_macros: bool,
+}
+
+/// Failure which ends the process with exit code 1, or 2 for usage errors.
+enum Failure {
+    Usage(String),
+    Error(String),
+    /// Error already printed as diagnostic
+    Diagnosed,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, Failure> {
+    let mut parsed = Args {
+        options: PreprocessorOptions::new(),
+        inputs: vec![],
+        output: None,
+        language: LanguageOptions::default(),
+        target: Target::default(),
+        linemarkers: true,
+        dump_macros: false,
+    };
+
+    while let Some(arg) = args.next() {
+        // Options taking a value accept it either attached or as next argument
+        let mut value = |option: &str| match arg[option.len()..].is_empty() {
+            true => args
+                .next()
+                .ok_or_else(|| Failure::Usage(format!("missing argument to '{option}'"))),
+            false => Ok(arg[option.len()..].to_string()),
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-E" => {}
+            "-P" => parsed.linemarkers = false,
+            "-dM" => parsed.dump_macros = true,
+            "-" => parsed.inputs.push(arg),
+            "-include" => parsed.options = parsed.options.include(value("-include")?),
+            "-imacros" => parsed.options = parsed.options.imacros(value("-imacros")?),
+            _ if arg.starts_with("-std=") => {
+                parsed.language = parse_standard(&arg["-std=".len()..])
+                    .ok_or_else(|| Failure::Usage(format!("unrecognized standard in '{arg}'")))?;
+            }
+            _ if arg.starts_with("--target=") => {
+                parsed.target = parse_target(&arg["--target=".len()..])
+                    .ok_or_else(|| Failure::Usage(format!("unrecognized target in '{arg}'")))?;
+            }
+            _ if arg.starts_with("-o") => parsed.output = Some(value("-o")?.into()),
+            _ if arg.starts_with("-D") => parsed.options = parsed.options.define(&value("-D")?),
+            _ if arg.starts_with("-U") => parsed.options = parsed.options.undefine(&value("-U")?),
+            _ if arg.starts_with("-I") => {
+                parsed.options = parsed.options.include_path(value("-I")?)
+            }
+            _ if arg.starts_with('-') => {
+                return Err(Failure::Usage(format!("unrecognized option '{arg}'")))
+            }
+            _ => parsed.inputs.push(arg),
+        }
+    }
+
+    if parsed.inputs.is_empty() {
+        parsed.inputs.push("-".to_string());
+    }
+
+    Ok(Some(parsed))
+}
+
+/// Parses standard named as by `-std=` of GCC.
+fn parse_standard(name: &str) -> Option<LanguageOptions> {
+    let (dialect, version) = match name.strip_prefix("gnu") {
+        Some(version) => (Dialect::Gnu, version),
+        None => (Dialect::Iso, name.strip_prefix('c')?),
+    };
+    let standard = match version {
+        "89" | "90" => Standard::C89,
+        "99" => Standard::C99,
+        "11" => Standard::C11,
+        "17" | "18" => Standard::C17,
+        "23" | "2x" => Standard::C23,
+        _ => return None,
+    };
+
+    Some(LanguageOptions::new(standard, dialect))
+}
+
+/// Parses target named by its architecture, as the first component of
+/// target triple.
+fn parse_target(name: &str) -> Option<Target> {
+    match name.split('-').next().unwrap() {
+        "x86_64" | "amd64" => Some(Target::x86_64()),
+        "aarch64" | "arm64" => Some(Target::aarch64()),
+        "armv7" | "arm" => Some(Target::armv7()),
+        "riscv32" => Some(Target::riscv32()),
+        "riscv64" => Some(Target::riscv64()),
+        _ => None,
+    }
+}
+
+fn print_diagnostic(diagnostic: &Diagnostic) {
+    eprintln!("{diagnostic}\n{}", diagnostic.snippet());
+}
+
+/// Preprocesses input at `path`, where `-` is standard input.
+fn preprocess(path: &str, args: &Args) -> Result<String, Failure> {
+    let mut preprocessor = if path == "-" {
+        let mut source = String::new();
+        io::stdin()
+            .read_to_string(&mut source)
+            .map_err(|err| Failure::Error(format!("<stdin>: {err}")))?;
+
+        Preprocessor::new(&source)
+    } else {
+        Preprocessor::from_file(path).map_err(|err| Failure::Error(format!("{path}: {err}")))?
+    };
+
+    preprocessor.set_language(args.language);
+    preprocessor.set_target(args.target.clone());
+    preprocessor.set_options(&args.options);
+    preprocessor.set_linemarkers(args.linemarkers);
+
+    let result = preprocessor.preprocess();
+
+    for warning in preprocessor.warnings() {
+        print_diagnostic(warning);
+    }
+
+    let output = result.map_err(|error| {
+        print_diagnostic(&error);
+        Failure::Diagnosed
+    })?;
+
+    if !args.dump_macros {
+        return Ok(output);
+    }
+
+    Ok(preprocessor
+        .macros()
+        .iter()
+        .filter_map(|info| info.to_directive())
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn run() -> Result<(), Failure> {
+    let Some(args) = parse_args(env::args().skip(1))? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+
+    let mut output = String::new();
+
+    for path in &args.inputs {
+        output.push_str(&preprocess(path, &args)?);
+
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+    }
+
+    match &args.output {
+        Some(path) => fs::write(path, output),
+        None => io::stdout().write_all(output.as_bytes()),
+    }
+    .map_err(|err| Failure::Error(format!("cannot write output: {err}")))
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(msg)) => {
+            eprintln!("tangle: {msg}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Failure::Error(msg)) => {
+            eprintln!("tangle: {msg}");
+            ExitCode::FAILURE
+        }
+        Err(Failure::Diagnosed) => ExitCode::FAILURE,
+    }
+}
@@ -1,40 +1,203 @@
+use std::{cell::Cell, path::PathBuf, rc::Rc};
+
+use crate::{
+    lexer::{PresumedLocation, Token},
+    source::FileId,
+};
+
 #[derive(Debug, Clone)]
 pub struct Alias {
     pub alias: String,
-    pub replacement: String,
-    pub disabled: bool,
+    pub replacement: Rc<[Token]>,
+    /// Location of the name in `#define`, `None` for predefined macros
+    pub location: Option<PresumedLocation>,
+    /// Whether the macro is expanded or tested by `defined`
+    pub used: Cell<bool>,
 }
 
 impl Alias {
-    pub fn new(alias: String, replacement: String) -> Self {
+    pub fn new(
+        alias: String,
+        replacement: Rc<[Token]>,
+        location: Option<PresumedLocation>,
+    ) -> Self {
         Self {
             alias,
             replacement,
-            disabled: false,
+            location,
+            used: Cell::new(false),
         }
     }
 }
 
-#[derive(Debug)]
+/// Element of function-like macro replacement list, parameters are resolved
+/// into their indices at definition
+#[derive(Debug, Clone)]
+pub enum ReplacementToken {
+    Token(Token),
+    /// Parameter replaced by its fully macro expanded argument
+    Parameter {
+        index: usize,
+        spaced: bool,
+    },
+    /// Parameter following `#`, replaced by its unexpanded argument spelled
+    /// as a string literal
+    Stringized {
+        index: usize,
+        spaced: bool,
+    },
+    /// Parameter operand of `##`, replaced by its unexpanded argument, or by
+    /// a placemarker if the argument is empty
+    Operand {
+        index: usize,
+        spaced: bool,
+    },
+    /// `##` pasting the last token of the preceding operand with the first
+    /// token of the following operand
+    Paste {
+        spaced: bool,
+    },
+    /// Operand of `__VA_OPT__`, kept only if the variadic argument is present
+    VaOpt {
+        content: Vec<ReplacementToken>,
+        spaced: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
 pub struct Macro {
     pub name: String,
-    pub parameters: Vec<Alias>,
+    pub parameters: Vec<String>,
     pub is_variadic: bool,
-    pub source_span: String,
+    pub replacement: Rc<[ReplacementToken]>,
+    /// Location of the name in `#define`, `None` for predefined macros
+    pub location: Option<PresumedLocation>,
+    /// Whether the macro is expanded or tested by `defined`
+    pub used: Cell<bool>,
 }
 
 impl Macro {
     pub fn new(
         name: String,
-        parameters: Vec<Alias>,
+        parameters: Vec<String>,
         is_variadic: bool,
-        source_span: String,
+        replacement: Vec<ReplacementToken>,
+        location: Option<PresumedLocation>,
     ) -> Self {
         Self {
             name,
             parameters,
             is_variadic,
-            source_span,
+            replacement: replacement.into(),
+            location,
+            used: Cell::new(false),
         }
     }
 }
+
+/// Definition of object-like or function-like macro
+#[derive(Debug, Clone)]
+pub enum Definition {
+    Alias(Alias),
+    Macro(Macro),
+}
+
+impl Definition {
+    pub fn used(&self) -> &Cell<bool> {
+        match self {
+            Definition::Alias(alias) => &alias.used,
+            Definition::Macro(mac) => &mac.used,
+        }
+    }
+}
+
+/// Conditional group opened by `#if`, `#ifdef` or `#ifndef`
+#[derive(Debug)]
+pub struct Conditional {
+    /// Whether a branch of the group has been taken
+    pub taken: bool,
+    pub has_else: bool,
+    pub pos: usize,
+}
+
+impl Conditional {
+    pub fn new(taken: bool, pos: usize) -> Self {
+        Self {
+            taken,
+            has_else: false,
+            pos,
+        }
+    }
+}
+
+/// Source file read by the lexer, either main file or file entered by
+/// `#include`
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// File loaded by the source manager, `None` if source is given directly
+    pub source: Option<FileId>,
+    /// Whether `#pragma once` appeared in the file
+    pub pragma_once: bool,
+    /// Macro guarding whole file by `#ifndef`, detected when the file ends
+    pub guard: Option<String>,
+}
+
+impl SourceFile {
+    pub fn new(path: PathBuf, source: Option<FileId>) -> Self {
+        Self {
+            path,
+            source,
+            pragma_once: false,
+            guard: None,
+        }
+    }
+}
+
+/// Progress of include guard detection of the file being included
+#[derive(Debug)]
+pub enum IncludeGuard {
+    /// Nothing but whitespaces and comments are read
+    Unknown,
+    /// Inside group of `#ifndef` opening the file
+    Open(String),
+    /// Group of `#ifndef` is closed by `#endif`
+    Closed(String),
+    /// Tokens or directives appear outside of the group
+    Unguarded,
+}
+
+/// File entered by `#include`, with depth of conditional stack at entrance
+#[derive(Debug)]
+pub struct Include {
+    pub file: usize,
+    pub conditional_depth: usize,
+    pub guard: IncludeGuard,
+    /// Whether the file holds directives of command-line options
+    pub command_line: bool,
+    /// Whether output is discarded until the file ends, as by `-imacros`
+    pub discard_output: bool,
+}
+
+impl Include {
+    pub fn new(file: usize, conditional_depth: usize) -> Self {
+        Self {
+            file,
+            conditional_depth,
+            guard: IncludeGuard::Unknown,
+            command_line: false,
+            discard_output: false,
+        }
+    }
+}
+
+/// Parameters of `#embed` directive and `__has_embed` operator
+#[derive(Debug, Default)]
+pub struct EmbedParameters {
+    pub limit: Option<u64>,
+    pub prefix: Vec<Token>,
+    pub suffix: Vec<Token>,
+    pub if_empty: Vec<Token>,
+    /// Whether all parameters are supported
+    pub supported: bool,
+}
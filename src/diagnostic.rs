@@ -0,0 +1,71 @@
+use std::{error, fmt};
+
+use crate::lexer::PresumedLocation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    /// Error which stops preprocessing
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Message reported at a location of source, which is displayed as
+/// `file:line:column: severity: message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: PresumedLocation,
+    /// Line of source containing the location
+    pub source_line: String,
+    /// Byte offset of the location in `source_line`
+    pub offset: usize,
+}
+
+impl Diagnostic {
+    /// Creates diagnostic at `pos` of `source`, whose line is kept for
+    /// [`Diagnostic::snippet`].
+    pub fn new(
+        severity: Severity,
+        message: &str,
+        location: PresumedLocation,
+        source: &str,
+        pos: usize,
+    ) -> Self {
+        let pos = pos.min(source.len());
+        let start_idx = source[..pos].rfind('\n').map_or(0, |idx| idx + 1);
+        let end_idx = source[pos..]
+            .find('\n')
+            .map_or(source.len(), |idx| pos + idx);
+
+        Self {
+            severity,
+            message: message.to_string(),
+            location,
+            source_line: source[start_idx..end_idx].to_string(),
+            offset: pos - start_idx,
+        }
+    }
+
+    /// Source line followed by a caret under the location.
+    pub fn snippet(&self) -> String {
+        format!("{}\n{}^", self.source_line, " ".repeat(self.offset))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.location, self.severity, self.message)
+    }
+}
+
+impl error::Error for Diagnostic {}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+/// Feature-test operator usable in `#if`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureTest {
+    /// `__has_attribute`, tests GNU style attributes
+    Attribute,
+    /// `__has_c_attribute`, tests attributes of `[[...]]` syntax
+    CAttribute,
+    /// `__has_builtin`
+    Builtin,
+    /// `__has_feature`
+    Feature,
+}
+
+/// Names of feature test operators, each of which is defined as a builtin
+/// macro if it is provided
+pub const FEATURE_OPERATORS: [&str; 4] = [
+    "__has_attribute",
+    "__has_c_attribute",
+    "__has_builtin",
+    "__has_feature",
+];
+
+impl FeatureTest {
+    pub fn from_operator(name: &str) -> Option<Self> {
+        match name {
+            "__has_attribute" => Some(Self::Attribute),
+            "__has_c_attribute" => Some(Self::CAttribute),
+            "__has_builtin" => Some(Self::Builtin),
+            "__has_feature" => Some(Self::Feature),
+            _ => None,
+        }
+    }
+}
+
+/// GNU attributes shared by GCC and Clang
+const GNU_ATTRIBUTES: &[&str] = &[
+    "alias",
+    "aligned",
+    "alloc_align",
+    "alloc_size",
+    "always_inline",
+    "artificial",
+    "assume_aligned",
+    "cleanup",
+    "cold",
+    "common",
+    "const",
+    "constructor",
+    "deprecated",
+    "destructor",
+    "error",
+    "fallthrough",
+    "flatten",
+    "format",
+    "format_arg",
+    "gnu_inline",
+    "hot",
+    "ifunc",
+    "leaf",
+    "malloc",
+    "may_alias",
+    "mode",
+    "no_instrument_function",
+    "no_sanitize",
+    "no_stack_protector",
+    "noinline",
+    "nonnull",
+    "noreturn",
+    "nothrow",
+    "packed",
+    "pure",
+    "retain",
+    "returns_nonnull",
+    "returns_twice",
+    "section",
+    "sentinel",
+    "target",
+    "target_clones",
+    "tls_model",
+    "transparent_union",
+    "unavailable",
+    "unused",
+    "used",
+    "vector_size",
+    "visibility",
+    "warn_unused_result",
+    "warning",
+    "weak",
+    "weakref",
+];
+
+const GCC_ATTRIBUTES: &[&str] = &[
+    "access",
+    "copy",
+    "designated_init",
+    "externally_visible",
+    "no_reorder",
+    "noclone",
+    "noinit",
+    "noipa",
+    "nonstring",
+    "noplt",
+    "optimize",
+    "patchable_function_entry",
+    "persistent",
+    "scalar_storage_order",
+    "simd",
+    "symver",
+    "warn_if_not_aligned",
+    "zero_call_used_regs",
+];
+
+const CLANG_ATTRIBUTES: &[&str] = &[
+    "availability",
+    "diagnose_if",
+    "enable_if",
+    "musttail",
+    "nodebug",
+    "overloadable",
+    "swift_name",
+];
+
+/// Standard attributes with the version of C standard adopting them
+const C2X_ATTRIBUTES: &[(&str, u64)] = &[
+    ("deprecated", 201904),
+    ("fallthrough", 201904),
+    ("maybe_unused", 201904),
+    ("nodiscard", 202003),
+];
+
+const C23_ATTRIBUTES: &[(&str, u64)] = &[("noreturn", 202202), ("_Noreturn", 202202)];
+
+const GNU_BUILTINS: &[&str] = &[
+    "__builtin_abort",
+    "__builtin_add_overflow",
+    "__builtin_alloca",
+    "__builtin_assume_aligned",
+    "__builtin_bswap16",
+    "__builtin_bswap32",
+    "__builtin_bswap64",
+    "__builtin_choose_expr",
+    "__builtin_clz",
+    "__builtin_constant_p",
+    "__builtin_convertvector",
+    "__builtin_ctz",
+    "__builtin_expect",
+    "__builtin_ffs",
+    "__builtin_memcmp",
+    "__builtin_memcpy",
+    "__builtin_memset",
+    "__builtin_mul_overflow",
+    "__builtin_offsetof",
+    "__builtin_popcount",
+    "__builtin_prefetch",
+    "__builtin_shufflevector",
+    "__builtin_strlen",
+    "__builtin_sub_overflow",
+    "__builtin_trap",
+    "__builtin_types_compatible_p",
+    "__builtin_unreachable",
+    "__builtin_va_arg",
+    "__builtin_va_copy",
+    "__builtin_va_end",
+    "__builtin_va_start",
+];
+
+const GCC_BUILTINS: &[&str] = &[
+    "__builtin_has_attribute",
+    "__builtin_speculation_safe_value",
+];
+
+const CLANG_BUILTINS: &[&str] = &[
+    "__builtin_assume",
+    "__builtin_bitreverse32",
+    "__builtin_dump_struct",
+    "__builtin_elementwise_abs",
+];
+
+const CLANG_FEATURES: &[&str] = &[
+    "attribute_availability",
+    "attribute_deprecated_with_message",
+    "attribute_unavailable_with_message",
+    "c_alignas",
+    "c_alignof",
+    "c_atomic",
+    "c_generic_selections",
+    "c_static_assert",
+    "c_thread_local",
+    "enumerator_attributes",
+];
+
+/// Capability table answering feature-test operators, tables are keyed by
+/// names without surrounding double underscores, scoped attributes are keyed
+/// as `scope::name`.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub attributes: HashMap<String, u64>,
+    pub c_attributes: HashMap<String, u64>,
+    pub builtins: HashMap<String, u64>,
+    /// `None` if `__has_feature` is not provided, as in GCC
+    pub features: Option<HashMap<String, u64>>,
+}
+
+impl Capabilities {
+    /// Profile of GCC 12 compiling C.
+    pub fn gcc() -> Self {
+        let mut capabilities = Self::default();
+        capabilities.add_standard_attributes(C2X_ATTRIBUTES);
+
+        for name in GNU_ATTRIBUTES.iter().chain(GCC_ATTRIBUTES) {
+            capabilities.add_vendor_attribute("gnu", name);
+        }
+
+        for name in GNU_BUILTINS.iter().chain(GCC_BUILTINS) {
+            capabilities.builtins.insert(name.to_string(), 1);
+        }
+
+        capabilities
+    }
+
+    /// Profile of Clang 17 compiling C.
+    pub fn clang() -> Self {
+        let mut capabilities = Self::default();
+        capabilities.add_standard_attributes(C2X_ATTRIBUTES);
+
+        for name in GNU_ATTRIBUTES {
+            capabilities.add_vendor_attribute("gnu", name);
+        }
+
+        for name in CLANG_ATTRIBUTES {
+            capabilities.add_vendor_attribute("clang", name);
+        }
+
+        // C23 attributes are spelled as GNU style attributes of the same name
+        capabilities.add_standard_attributes(C23_ATTRIBUTES);
+
+        for name in GNU_BUILTINS.iter().chain(CLANG_BUILTINS) {
+            capabilities.builtins.insert(name.to_string(), 1);
+        }
+
+        capabilities.features = Some(
+            CLANG_FEATURES
+                .iter()
+                .map(|name| (name.to_string(), 1))
+                .collect(),
+        );
+        capabilities
+    }
+
+    /// Adds vendor attribute, which is available in GNU style and in `[[...]]`
+    /// syntax scoped by vendor. Previously added GNU style attribute of the
+    /// same name keeps its value.
+    pub fn add_vendor_attribute(&mut self, vendor: &str, name: &str) {
+        let scoped = format!("{vendor}::{name}");
+
+        self.attributes.entry(name.to_string()).or_insert(1);
+        self.attributes.insert(scoped.clone(), 1);
+        self.c_attributes.insert(scoped, 1);
+    }
+
+    /// Adds standard attributes, whose feature-test values are the dates of
+    /// their adoption. Previously added GNU style attributes of the same name
+    /// keep their values.
+    pub fn add_standard_attributes(&mut self, attributes: &[(&str, u64)]) {
+        for (name, date) in attributes {
+            self.attributes.entry(name.to_string()).or_insert(*date);
+            self.c_attributes.insert(name.to_string(), *date);
+        }
+    }
+
+    /// Whether operator of feature test is provided.
+    pub fn provides(&self, test: FeatureTest) -> bool {
+        test != FeatureTest::Feature || self.features.is_some()
+    }
+
+    /// Evaluates feature test of `name`, where components of scoped attribute
+    /// names may be surrounded by double underscores.
+    pub fn test(&self, test: FeatureTest, name: &str) -> u64 {
+        let table = match test {
+            FeatureTest::Attribute => &self.attributes,
+            FeatureTest::CAttribute => &self.c_attributes,
+            FeatureTest::Builtin => &self.builtins,
+            FeatureTest::Feature => match &self.features {
+                Some(features) => features,
+                None => return 0,
+            },
+        };
+        let name = match test {
+            FeatureTest::Attribute | FeatureTest::CAttribute => name
+                .split("::")
+                .map(|component| {
+                    component
+                        .strip_prefix("__")
+                        .and_then(|component| component.strip_suffix("__"))
+                        .filter(|component| !component.is_empty())
+                        .unwrap_or(component)
+                })
+                .collect::<Vec<_>>()
+                .join("::"),
+            _ => name.to_string(),
+        };
+
+        table.get(&name).copied().unwrap_or(0)
+    }
+}
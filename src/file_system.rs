@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+/// Status of file or directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStatus {
+    /// Size in bytes, which is 0 for directories
+    pub size: u64,
+    pub is_directory: bool,
+    /// Device and inode numbers, if the file system has them
+    pub inode: Option<(u64, u64)>,
+}
+
+/// File system which include resolution and `#embed` read through.
+pub trait FileSystem {
+    /// Opens file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+    /// Canonical path of `path`, which is the same for every path reaching
+    /// the same file.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn stat(&self, path: &Path) -> io::Result<FileStatus>;
+
+    fn is_directory(&self, path: &Path) -> bool {
+        self.stat(path).is_ok_and(|status| status.is_directory)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.stat(path).is_ok_and(|status| !status.is_directory)
+    }
+}
+
+/// File system on disk
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<FileStatus> {
+        let metadata = fs::metadata(path)?;
+
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+
+            Some((metadata.dev(), metadata.ino()))
+        };
+        #[cfg(not(unix))]
+        let inode = None;
+
+        Ok(FileStatus {
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            is_directory: metadata.is_dir(),
+            inode,
+        })
+    }
+}
+
+/// In-memory files layered over another file system, or over nothing so that
+/// disk is never touched. Directories are implied by paths of the files.
+#[derive(Default)]
+pub struct OverlayFileSystem {
+    /// Contents of in-memory files by their normalized paths
+    files: HashMap<PathBuf, Rc<[u8]>>,
+    base: Option<Box<dyn FileSystem>>,
+}
+
+impl OverlayFileSystem {
+    /// Creates file system of in-memory files only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates file system whose in-memory files shadow files of `base`.
+    pub fn over(base: impl FileSystem + 'static) -> Self {
+        Self {
+            files: HashMap::new(),
+            base: Some(Box::new(base)),
+        }
+    }
+
+    /// Adds in-memory file at `path`. Paths are compared after `.` and `..`
+    /// components are resolved.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        self.files
+            .insert(normalize(path.as_ref()), contents.into().into());
+    }
+
+    fn is_memory_directory(&self, path: &Path) -> bool {
+        let path = normalize(path);
+
+        self.files
+            .keys()
+            .any(|file| file != &path && file.starts_with(&path))
+    }
+
+    fn base(&self, path: &Path) -> io::Result<&dyn FileSystem> {
+        self.base.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{}: No such file or directory", path.display()),
+            )
+        })
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => self.base(path)?.open(path),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let normalized = normalize(path);
+
+        if self.files.contains_key(&normalized) || self.is_memory_directory(path) {
+            return Ok(normalized);
+        }
+
+        self.base(path)?.canonicalize(path)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<FileStatus> {
+        if let Some(contents) = self.files.get(&normalize(path)) {
+            return Ok(FileStatus {
+                size: contents.len() as u64,
+                is_directory: false,
+                inode: None,
+            });
+        }
+
+        if self.is_memory_directory(path) {
+            return Ok(FileStatus {
+                size: 0,
+                is_directory: true,
+                inode: None,
+            });
+        }
+
+        self.base(path)?.stat(path)
+    }
+}
+
+/// Resolves `.` and `..` components of path without accessing disk.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
@@ -1,72 +1,29 @@
-use std::backtrace::Backtrace;
-use std::process::abort;
-use std::sync::OnceLock;
-
 use std::{
-    fs::File,
-    io::{BufRead, BufReader, Error},
-    path::PathBuf,
+    io::{Error, Read},
+    path::Path,
 };
 
-pub static SOURCE: OnceLock<String> = OnceLock::new();
-
-fn read_source_file(file_path: impl Into<PathBuf>) -> Result<String, Error> {
-    let file_path: PathBuf = file_path.into();
-    let file = File::open(file_path.clone())?;
-    let mut builder = String::with_capacity(file.metadata()?.len() as usize);
-    let reader = BufReader::new(file);
-
-    for line in reader.lines() {
-        let line = line?;
-
-        if line.starts_with("#include \"") {
-            let mut include_path = file_path.clone();
-            include_path.pop();
-            let path = line
-                .bytes()
-                .skip(10)
-                .take_while(|c| *c as char != '"')
-                .map(|c| c as char)
-                .collect::<String>();
-            include_path.push(path);
+use crate::file_system::FileSystem;
 
-            let sub_file_content = read_source_file(include_path)?;
+/// Reads source file, bytes which are not valid UTF-8 are replaced.
+pub fn read_source_file(file_system: &dyn FileSystem, file_path: &Path) -> Result<String, Error> {
+    let mut bytes = vec![];
+    file_system.open(file_path)?.read_to_end(&mut bytes)?;
 
-            builder.push_str(&sub_file_content);
-        } else {
-            builder.push_str(&line);
-        }
-
-        builder.push('\n');
-    }
-
-    Ok(builder)
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
 }
 
-pub fn get_source() -> &'static str {
-    SOURCE.get_or_init(|| read_source_file("shecc/src/main.c").unwrap())
-}
-
-pub fn error<'src, 'msg>(source: &'src str, msg: &'msg str, pos: usize) -> ! {
-    let mut offset = pos.clamp(0, source.len() - 1);
-    let start_idx: usize;
-    let end_idx: usize;
-
-    while offset > 0 && source.as_bytes()[offset] != b'\n' {
-        offset -= 1;
-    }
-
-    start_idx = offset;
-    offset = pos.clamp(0, source.len() - 1);
-
-    while offset < source.len() && source.as_bytes()[offset] != b'\n' {
-        offset += 1;
-    }
-
-    end_idx = offset;
-
-    println!("{}", &source[start_idx..end_idx]);
-    println!("{}^ {msg}", " ".repeat(pos - start_idx));
-    println!("Backtrace: {}", Backtrace::capture());
-    abort()
+/// Reads at most `limit` bytes of binary file.
+pub fn read_binary_file(
+    file_system: &dyn FileSystem,
+    file_path: &Path,
+    limit: Option<u64>,
+) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    file_system
+        .open(file_path)?
+        .take(limit.unwrap_or(u64::MAX))
+        .read_to_end(&mut data)?;
+
+    Ok(data)
 }
@@ -0,0 +1,212 @@
+/// Revision of the C standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Standard {
+    C89,
+    C99,
+    C11,
+    C17,
+    C23,
+}
+
+impl Standard {
+    /// Value of `__STDC_VERSION__`, which is not defined by C89.
+    pub fn version(self) -> Option<&'static str> {
+        match self {
+            Self::C89 => None,
+            Self::C99 => Some("199901L"),
+            Self::C11 => Some("201112L"),
+            Self::C17 => Some("201710L"),
+            Self::C23 => Some("202311L"),
+        }
+    }
+
+    /// Name used by diagnostics about features of later revisions.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::C89 => "C89",
+            Self::C99 => "C99",
+            Self::C11 => "C11",
+            Self::C17 => "C17",
+            Self::C23 => "C23",
+        }
+    }
+}
+
+/// Dialect extending the standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Strict ISO C, as `-std=c99`
+    Iso,
+    /// GNU extensions, as `-std=gnu99`
+    Gnu,
+    /// Microsoft extensions
+    Msvc,
+}
+
+const C89_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "int", "long", "register", "return", "short",
+    "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while",
+];
+
+const C99_KEYWORDS: &[&str] = &["inline", "restrict", "_Bool", "_Complex", "_Imaginary"];
+
+const C11_KEYWORDS: &[&str] = &[
+    "_Alignas",
+    "_Alignof",
+    "_Atomic",
+    "_Generic",
+    "_Noreturn",
+    "_Static_assert",
+    "_Thread_local",
+];
+
+const C23_KEYWORDS: &[&str] = &[
+    "alignas",
+    "alignof",
+    "bool",
+    "constexpr",
+    "false",
+    "nullptr",
+    "static_assert",
+    "thread_local",
+    "true",
+    "typeof",
+    "typeof_unqual",
+    "_BitInt",
+    "_Decimal128",
+    "_Decimal32",
+    "_Decimal64",
+];
+
+const GNU_KEYWORDS: &[&str] = &["asm", "typeof"];
+
+/// Language accepted by the preprocessor. Presets enable what compilers accept
+/// for each standard and dialect, while `pedantic` reports the accepted
+/// features which are extensions to the standard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageOptions {
+    pub standard: Standard,
+    pub dialect: Dialect,
+    /// `//` comments
+    pub line_comments: bool,
+    /// `<:`, `:>`, `<%`, `%>`, `%:` and `%:%:`
+    pub digraphs: bool,
+    /// `$` in identifiers
+    pub dollar_identifiers: bool,
+    /// `__VA_OPT__` in replacement of variadic macros
+    pub va_opt: bool,
+    /// `#warning` directive
+    pub warning_directive: bool,
+    /// `#include_next` directive
+    pub include_next: bool,
+    /// Warns about extensions to the standard
+    pub pedantic: bool,
+}
+
+impl Default for LanguageOptions {
+    /// GNU C17, the default of GCC.
+    fn default() -> Self {
+        Self::new(Standard::C17, Dialect::Gnu)
+    }
+}
+
+impl LanguageOptions {
+    pub fn new(standard: Standard, dialect: Dialect) -> Self {
+        let extended = dialect != Dialect::Iso;
+        let gnu = dialect != Dialect::Msvc;
+
+        Self {
+            standard,
+            dialect,
+            line_comments: standard >= Standard::C99 || extended,
+            digraphs: standard >= Standard::C99 || extended,
+            dollar_identifiers: true,
+            va_opt: standard >= Standard::C23 || gnu,
+            warning_directive: standard >= Standard::C23 || gnu,
+            include_next: gnu,
+            pedantic: false,
+        }
+    }
+
+    pub fn c89() -> Self {
+        Self::new(Standard::C89, Dialect::Iso)
+    }
+
+    pub fn c99() -> Self {
+        Self::new(Standard::C99, Dialect::Iso)
+    }
+
+    pub fn c11() -> Self {
+        Self::new(Standard::C11, Dialect::Iso)
+    }
+
+    pub fn c17() -> Self {
+        Self::new(Standard::C17, Dialect::Iso)
+    }
+
+    pub fn c23() -> Self {
+        Self::new(Standard::C23, Dialect::Iso)
+    }
+
+    pub fn gnu(standard: Standard) -> Self {
+        Self::new(standard, Dialect::Gnu)
+    }
+
+    pub fn msvc(standard: Standard) -> Self {
+        Self::new(standard, Dialect::Msvc)
+    }
+
+    /// Enables warnings about extensions to the standard.
+    pub fn pedantic(self) -> Self {
+        Self {
+            pedantic: true,
+            ..self
+        }
+    }
+
+    /// Whether `name` is a keyword of the language.
+    pub fn is_keyword(&self, name: &str) -> bool {
+        let standard = self.standard;
+
+        C89_KEYWORDS.contains(&name)
+            || standard >= Standard::C99 && C99_KEYWORDS.contains(&name)
+            || standard >= Standard::C11 && C11_KEYWORDS.contains(&name)
+            || standard >= Standard::C23 && C23_KEYWORDS.contains(&name)
+            || self.dialect == Dialect::Gnu && GNU_KEYWORDS.contains(&name)
+    }
+
+    /// Macros predefined for the language.
+    pub fn predefined_macros(&self) -> Vec<(&'static str, String)> {
+        let mut macros = vec![];
+
+        if self.dialect != Dialect::Msvc {
+            macros.push(("__STDC__", "1".to_string()));
+        }
+
+        macros.push(("__STDC_HOSTED__", "1".to_string()));
+
+        if let Some(version) = self.standard.version() {
+            macros.push(("__STDC_VERSION__", version.to_string()));
+        }
+
+        if self.dialect == Dialect::Iso {
+            macros.push(("__STRICT_ANSI__", "1".to_string()));
+        }
+
+        macros
+    }
+
+    /// Extension message if feature introduced by `standard` is used in
+    /// pedantic mode of an earlier standard.
+    pub fn extension(&self, feature: &str, standard: Standard) -> Option<String> {
+        (self.pedantic && self.standard < standard).then(|| {
+            format!(
+                "{feature} is a {} feature used as an extension in {}",
+                standard.name(),
+                self.standard.name()
+            )
+        })
+    }
+}
@@ -1,11 +1,35 @@
-use std::collections::VecDeque;
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use crate::{
-    defs::{Alias, Macro},
-    globals::error,
+    defs::{Alias, Definition, Macro, ReplacementToken, SourceFile},
+    diagnostic::{Diagnostic, Severity},
+    feature::{Capabilities, FeatureTest, FEATURE_OPERATORS},
+    interner::{Interner, Symbol},
+    language::{LanguageOptions, Standard},
+    limits::Limits,
+    macros::{replacement_tokens, MacroInfo, MacroKind},
+    observer::{PreprocessorObserver, SourceRange},
+    pragma::{
+        GccPragma, MessagePragma, OncePragma, PopMacroPragma, Pragma, PragmaAction, PragmaContext,
+        PragmaHandler, PushMacroPragma, StdcPragma,
+    },
+    scanner::{
+        count_matching, count_until, identifier_bytes, is_alnum, whitespace_bytes, CharClass,
+        CHAR_CLASSES,
+    },
+    source::SourceManager,
+    target::Target,
+    trace::{ExpansionTrace, NodeId, TraceStep},
 };
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     TStart, /* FIXME: it was intended to start the state machine. */
@@ -71,6 +95,8 @@ pub enum TokenType {
     TContinue,
     /* C pre-processor directives */
     TCppdInclude,
+    TCppdIncludeNext,
+    TCppdEmbed,
     TCppdDefine,
     TCppdUndef,
     TCppdError,
@@ -79,318 +105,2281 @@ pub enum TokenType {
     TCppdElse,
     TCppdEndif,
     TCppdIfdef,
-    TCppdStringify, /* # */
+    TCppdIfndef,
+    TCppdWarning,
+    TCppdPragma,
+    TCppdLine,
+    TCppdNull,         /* # followed by newline */
+    TCppdNonDirective, /* # followed by unknown name */
+    TCppdStringify,    /* # */
+    TCppdConcat,       /* ## */
+    TOther,            /* character which is not part of other tokens */
     /* hints */
     TBackslash,
     TNewline,
 }
 
+/// Records bytes of the physical source removed by translation phases 1 and 2,
+/// `pos` is the offset in spliced source where removal happened, `removed` and
+/// `lines` are running totals of removed bytes and removed line breaks.
+#[derive(Debug, Clone, Copy)]
+pub struct Splice {
+    pos: usize,
+    removed: usize,
+    lines: usize,
+}
+
+/// Performs translation phases 1 and 2: strips UTF-8 BOM, normalizes CRLF and
+/// lone CR into LF, then splices every backslash-newline pair.
+pub fn splice_lines(source: &str) -> (String, Vec<Splice>) {
+    let bytes = source.as_bytes();
+    let mut spliced = Vec::with_capacity(bytes.len());
+    let mut splices = vec![];
+    let mut removed = 0;
+    let mut lines = 0;
+    let mut i = 0;
+
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        i = 3;
+        removed = 3;
+        splices.push(Splice {
+            pos: 0,
+            removed,
+            lines,
+        });
+    }
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if bytes.get(i + 1) == Some(&b'\n') || bytes.get(i + 1) == Some(&b'\r') => {
+                let newline_len = if bytes[i + 1..].starts_with(b"\r\n") {
+                    2
+                } else {
+                    1
+                };
+
+                i += 1 + newline_len;
+                removed += 1 + newline_len;
+                lines += 1;
+                splices.push(Splice {
+                    pos: spliced.len(),
+                    removed,
+                    lines,
+                });
+            }
+            b'\r' => {
+                spliced.push(b'\n');
+                i += 1;
+
+                if bytes.get(i) == Some(&b'\n') {
+                    // Removes LF but records removal before normalized LF so
+                    // that it maps back to CR
+                    i += 1;
+                    removed += 1;
+                    splices.push(Splice {
+                        pos: spliced.len() - 1,
+                        removed,
+                        lines,
+                    });
+                }
+            }
+            ch => {
+                spliced.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    (
+        String::from_utf8(spliced).expect("Splicing only removes ASCII bytes"),
+        splices,
+    )
+}
+
+/// Line number and file name given by `#line` directive to the line starting
+/// at `pos`, following lines are numbered consecutively.
+#[derive(Debug, Clone)]
+pub struct LineDirective {
+    pos: usize,
+    line: usize,
+    /// Physical line of `pos`
+    physical_line: usize,
+    file: String,
+}
+
+/// Location remapped by `#line` directives, which is reported by diagnostics
+/// and built-in macros. Nameless source only reports line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresumedLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for PresumedLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Spelling of token as byte range of shared source buffer, so that tokens
+/// are cloned without copying text
+#[derive(Clone, Default)]
+pub struct Spelling {
+    buffer: Rc<str>,
+    range: Range<usize>,
+}
+
+impl Spelling {
+    pub fn new(buffer: Rc<str>, range: Range<usize>) -> Self {
+        Self { buffer, range }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.buffer[self.range.clone()]
+    }
+}
+
+impl Deref for Spelling {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Spelling {
+    fn from(spelling: &str) -> Self {
+        Self::new(spelling.into(), 0..spelling.len())
+    }
+}
+
+impl From<String> for Spelling {
+    fn from(spelling: String) -> Self {
+        Self::from(spelling.as_str())
+    }
+}
+
+impl PartialEq for Spelling {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Spelling {}
+
+impl PartialEq<str> for Spelling {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Spelling {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for Spelling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Spelling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Spelled token, used where tokens outlive the region they are lexed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub token_str: Spelling,
+    pub spaced: bool,
+    /// Whether the identifier names a macro that was being expanded when it
+    /// was read, so it is never expanded again
+    pub no_expand: bool,
+}
+
+/// Spells tokens as written, tokens preceded by whitespaces are separated by
+/// a space.
+pub fn spell_tokens(tokens: &[Token]) -> String {
+    let mut builder = String::new();
+
+    for token in tokens {
+        if !builder.is_empty() && token.spaced {
+            builder.push(' ');
+        }
+
+        builder.push_str(&token.token_str);
+    }
+
+    builder
+}
+
+/// Spells string literal of `content`, quotes and backslashes are escaped.
+pub fn quote(content: &str) -> String {
+    format!("\"{}\"", content.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Macros whose replacements are computed by the lexer
+const BUILTIN_MACROS: [&str; 5] = [
+    "__FILE__",
+    "__LINE__",
+    "__has_embed",
+    "__has_include",
+    "__has_include_next",
+];
+
 pub struct Lexer {
     global_lexer: RegionalLexer,
     regional_lexers: VecDeque<RegionalLexer>,
-    aliases: Vec<Alias>,
-    macros: Vec<Macro>,
-    pub ignore_stringify: bool,
+    region_floor: usize,
+    limits: Limits,
+    /// Tokens lexed so far, bounded by [`Limits::max_steps`]
+    steps: u64,
+    /// Tokens written to output so far
+    output_tokens: usize,
+    /// Tokens written to output by the expansion of the last macro invocation
+    /// read from a file, including those found by rescanning
+    expansion_output_tokens: usize,
+    interner: Interner,
+    /// Object-like and function-like macros by their names
+    definitions: HashMap<Symbol, Definition>,
+    warnings: Vec<Diagnostic>,
+    pragma_handlers: HashMap<String, Box<dyn PragmaHandler>>,
+    observers: Vec<Box<dyn PreprocessorObserver>>,
+    /// Macro expansions recorded so far, `None` unless tracing is enabled
+    tracer: Option<ExpansionTrace>,
+    source_manager: SourceManager,
+    files: Vec<SourceFile>,
+    include_paths: Vec<PathBuf>,
+    capabilities: Capabilities,
+    /// Definitions saved by `#pragma push_macro`, `None` if undefined
+    macro_stacks: HashMap<Symbol, Vec<Option<Definition>>>,
+    poisoned: HashSet<Symbol>,
+    poison_checked: bool,
+    language: LanguageOptions,
+    /// Names of macros defined by [`LanguageOptions::predefined_macros`]
+    language_macros: Vec<&'static str>,
+    target: Target,
+    /// Names of macros defined by [`Target::predefined_macros`]
+    target_macros: Vec<&'static str>,
 }
 
-impl Lexer {
-    pub fn new(source: &str) -> Self {
-        Self {
-            global_lexer: RegionalLexer::new(source.to_owned(), vec![]),
-            regional_lexers: VecDeque::new(),
-            aliases: vec![],
-            macros: vec![],
-            ignore_stringify: false,
+impl Lexer {
+    pub fn new(source: &str) -> Self {
+        let (source, splices) = splice_lines(source);
+        let mut global_lexer = RegionalLexer::new(source.into());
+        global_lexer.splices = splices;
+        global_lexer.directives = true;
+        global_lexer.file = Some(0);
+
+        let mut lexer = Self {
+            global_lexer,
+            regional_lexers: VecDeque::new(),
+            region_floor: 0,
+            limits: Limits::default(),
+            steps: 0,
+            output_tokens: 0,
+            expansion_output_tokens: 0,
+            interner: Interner::new(),
+            definitions: HashMap::new(),
+            warnings: vec![],
+            pragma_handlers: HashMap::new(),
+            observers: vec![],
+            tracer: None,
+            source_manager: SourceManager::new(),
+            files: vec![SourceFile::new(PathBuf::new(), None)],
+            include_paths: vec![],
+            capabilities: Capabilities::gcc(),
+            macro_stacks: HashMap::new(),
+            poisoned: HashSet::new(),
+            poison_checked: true,
+            language: LanguageOptions::default(),
+            language_macros: vec![],
+            target: Target::default(),
+            target_macros: vec![],
+        };
+
+        lexer.language_macros = lexer.predefine(lexer.language.predefined_macros());
+        lexer.target_macros = lexer.predefine(lexer.target.predefined_macros());
+
+        lexer.define("__STDC_EMBED_NOT_FOUND__", "0");
+        lexer.define("__STDC_EMBED_FOUND__", "1");
+        lexer.define("__STDC_EMBED_EMPTY__", "2");
+
+        lexer.register_pragma_handler("once", OncePragma);
+        lexer.register_pragma_handler("push_macro", PushMacroPragma);
+        lexer.register_pragma_handler("pop_macro", PopMacroPragma);
+        lexer.register_pragma_handler("message", MessagePragma);
+        lexer.register_pragma_handler("GCC", GccPragma);
+        lexer.register_pragma_handler("STDC", StdcPragma);
+        lexer
+    }
+
+    /// Creates lexer of main file at `path`, quoted includes are searched
+    /// from its directory.
+    pub fn from_file(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Self::with_source_manager(SourceManager::new(), path)
+    }
+
+    /// Creates lexer of main file at `path` loaded by `source_manager`,
+    /// which may hold in-memory files.
+    pub fn with_source_manager(
+        mut source_manager: SourceManager,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let file = source_manager.load(&path)?;
+        let mut lexer = Self::new(&source_manager.file(file).text);
+
+        lexer.source_manager = source_manager;
+        lexer.global_lexer.file_name = path.display().to_string();
+        lexer.files[0] = SourceFile::new(path, Some(file));
+        Ok(lexer)
+    }
+
+    pub fn source_manager(&self) -> &SourceManager {
+        &self.source_manager
+    }
+
+    fn next_token(&mut self) -> Result<(), Box<Diagnostic>> {
+        let regional_lexer = self
+            .regional_lexers
+            .back_mut()
+            .unwrap_or(&mut self.global_lexer);
+        regional_lexer.lex_token()?;
+        regional_lexer.cur_token_symbol = (regional_lexer.cur_token_type == TokenType::TIdentifier)
+            .then(|| self.interner.intern(&regional_lexer.cur_token_str));
+
+        let extensions = std::mem::take(&mut regional_lexer.extensions);
+
+        for (msg, pos) in extensions {
+            self.warning(&msg, pos);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces language accepted by the lexer, which is GNU C17 by default,
+    /// then redefines macros predefined for the language.
+    pub fn set_language(&mut self, language: LanguageOptions) {
+        self.language = language;
+        self.global_lexer.language = language;
+
+        for regional_lexer in self.regional_lexers.iter_mut() {
+            regional_lexer.language = language;
+        }
+
+        for name in std::mem::take(&mut self.language_macros) {
+            self.undef(name);
+        }
+
+        self.language_macros = self.predefine(self.language.predefined_macros());
+    }
+
+    pub fn language(&self) -> &LanguageOptions {
+        &self.language
+    }
+
+    /// Replaces target machine, which is the host by default, then redefines
+    /// macros predefined for the target.
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+
+        for name in std::mem::take(&mut self.target_macros) {
+            self.undef(name);
+        }
+
+        self.target_macros = self.predefine(self.target.predefined_macros());
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Defines predefined macros, then returns their names.
+    fn predefine(&mut self, macros: Vec<(&'static str, String)>) -> Vec<&'static str> {
+        macros
+            .into_iter()
+            .map(|(name, replacement)| {
+                self.define(name, &replacement);
+                name
+            })
+            .collect()
+    }
+
+    fn new_regional_lexer(&self, source: Rc<str>) -> RegionalLexer {
+        let mut regional_lexer = RegionalLexer::new(source);
+        regional_lexer.language = self.language;
+        regional_lexer
+    }
+
+    pub fn lex_token(&mut self, aliasing: bool) -> Result<TokenType, Box<Diagnostic>> {
+        // Regions are entered and escaped iteratively, so that long chains of
+        // expansions cannot exhaust native stack
+        loop {
+            self.next_token()?;
+            self.steps += 1;
+
+            if let Some(max) = self.limits.max_steps.filter(|max| self.steps > *max) {
+                return Err(self.error(
+                    &format!("Preprocessing exceeds step budget of {max} tokens"),
+                    self.current_token_pos(),
+                ));
+            }
+
+            let token_type = self.current_token_type();
+
+            if token_type == TokenType::TIdentifier && !self.poisoned.is_empty() {
+                self.check_poisoned()?;
+            }
+
+            match token_type {
+                TokenType::TEof
+                    if self.regional_lexers.len() > self.region_floor
+                        && self.current_regional_lexer().file.is_none() =>
+                {
+                    // escapes current region, file regions are escaped by
+                    // parser once the file is finished
+                    self.regional_lexers.pop_back();
+                    continue;
+                }
+                TokenType::TIdentifier if aliasing => {
+                    if self.paint_current_token() {
+                        return Ok(token_type);
+                    }
+
+                    let symbol = self.current_token_symbol().unwrap();
+                    let spaced = self.current_token_spaced();
+
+                    if let Some(alias) = self.find_alias(symbol) {
+                        let mut replacement = alias.replacement.clone();
+                        let mut pastes = vec![];
+                        alias.used.set(true);
+
+                        if replacement
+                            .iter()
+                            .any(|token| token.token_type == TokenType::TCppdConcat)
+                        {
+                            replacement = self.paste_replacement(&replacement, &mut pastes)?.into();
+                        }
+
+                        let location = self.observed_location(self.current_token_pos());
+                        let parent = self.region_trace();
+
+                        if let Some(location) = &location {
+                            let name = self.resolve(symbol).to_string();
+                            let range = SourceRange {
+                                start: location.clone(),
+                                end: location.clone(),
+                            };
+
+                            self.notify(|observer| observer.macro_expanded(&name, None, &range));
+                        }
+
+                        // enter alias region for parsing, alias is disabled
+                        // until region is escaped
+                        self.append_expansion_lexer(symbol, replacement)?;
+                        self.current_mut_regional_lexer().leading_spaced = spaced;
+
+                        if let Some(location) = location {
+                            self.trace_alias_expansion(parent, symbol, location, pastes);
+                        }
+
+                        continue;
+                    }
+
+                    if let Some((token_type, token_str)) =
+                        self.expand_builtin_macro(self.current_token_str())
+                    {
+                        let regional_lexer = self.current_mut_regional_lexer();
+                        regional_lexer.cur_token_type = token_type;
+                        regional_lexer.cur_token_str = token_str.into();
+                        return Ok(token_type);
+                    }
+                }
+                _ => {}
+            }
+
+            return Ok(token_type);
+        }
+    }
+
+    /// Reports current identifier if it is poisoned, unless it is in skipped
+    /// group or comes from macro expansion. Tokens of macro expansions are
+    /// checked when they are lexed from source, so macros defined before the
+    /// poisoning may still use the identifier.
+    fn check_poisoned(&self) -> Result<(), Box<Diagnostic>> {
+        let regional_lexer = self.current_regional_lexer();
+
+        if self.poison_checked
+            && regional_lexer
+                .cur_token_symbol
+                .is_some_and(|symbol| self.poisoned.contains(&symbol))
+            && !regional_lexer.skipping
+            && regional_lexer.tokens.is_none()
+        {
+            return Err(self.error(
+                &format!(
+                    "Attempt to use poisoned \"{}\"",
+                    regional_lexer.cur_token_str
+                ),
+                self.current_token_pos(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Poisons identifier, whose later appearance is an error.
+    pub fn poison(&mut self, name: &str) {
+        let symbol = self.interner.intern(name);
+        self.poisoned.insert(symbol);
+    }
+
+    /// Sets whether poisoned identifiers are reported, which is unset while
+    /// pragma operands are read.
+    pub fn set_poison_checked(&mut self, checked: bool) {
+        self.poison_checked = checked;
+    }
+
+    /// Computes replacement of built-in macro, which is located at current
+    /// token of the file being read.
+    fn expand_builtin_macro(&self, name: &str) -> Option<(TokenType, String)> {
+        match name {
+            "__LINE__" => Some((
+                TokenType::TNumeric,
+                self.presumed_location().line.to_string(),
+            )),
+            "__FILE__" => Some((TokenType::TString, quote(&self.presumed_location().file))),
+            _ => None,
+        }
+    }
+
+    pub fn lex_accept_internal(
+        &mut self,
+        token_type: TokenType,
+        aliasing: bool,
+    ) -> Result<bool, Box<Diagnostic>> {
+        if self.current_token_type() == token_type {
+            self.lex_token(aliasing)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    pub fn lex_accept(
+        &mut self,
+        token_type: TokenType,
+        aliasing: bool,
+    ) -> Result<bool, Box<Diagnostic>> {
+        self.lex_accept_internal(token_type, aliasing)
+    }
+
+    pub fn lex_peek(&mut self, token_type: TokenType) -> bool {
+        self.current_token_type() == token_type
+    }
+
+    /// Peeks type of the token following current one without consuming it,
+    /// exhausted regions are looked through until region floor or end of file
+    /// is reached.
+    pub fn peek_next_token_type(&mut self) -> Result<TokenType, Box<Diagnostic>> {
+        let floor = self.region_floor;
+
+        for regional_lexer in self
+            .regional_lexers
+            .iter_mut()
+            .skip(floor.saturating_sub(1))
+            .rev()
+        {
+            let token_type = regional_lexer.peek_token()?;
+
+            if token_type != TokenType::TEof || regional_lexer.file.is_some() {
+                return Ok(token_type);
+            }
+        }
+
+        if floor == 0 {
+            self.global_lexer.peek_token()
+        } else {
+            Ok(TokenType::TEof)
+        }
+    }
+
+    pub fn lex_expect(
+        &mut self,
+        token_type: TokenType,
+        aliasing: bool,
+    ) -> Result<(), Box<Diagnostic>> {
+        if self.current_token_type() != token_type {
+            return Err(self.error(
+                &format!(
+                    "Unexpected token {:?}, expexts {:?}",
+                    self.current_token_type(),
+                    token_type
+                ),
+                self.pos(),
+            ));
+        }
+
+        self.lex_token(aliasing)?;
+        Ok(())
+    }
+
+    /// Region and position where diagnostics at `pos` of current region are
+    /// reported, tokens of macro expansions are located at the invocation.
+    fn diagnostic_location(&self, pos: usize) -> (&RegionalLexer, usize) {
+        let regional_lexer = self.current_regional_lexer();
+
+        if regional_lexer.tokens.is_none() {
+            return (regional_lexer, pos);
+        }
+
+        let regional_lexer = self.file_regional_lexer();
+        (regional_lexer, regional_lexer.cur_token_pos)
+    }
+
+    /// Error at position of current region, which stops preprocessing once
+    /// returned.
+    pub fn error(&self, msg: &str, pos: usize) -> Box<Diagnostic> {
+        let (regional_lexer, pos) = self.diagnostic_location(pos);
+        regional_lexer.error(msg, pos)
+    }
+
+    /// Records warning at position of current region.
+    pub fn warning(&mut self, msg: &str, pos: usize) {
+        let (regional_lexer, pos) = self.diagnostic_location(pos);
+        let diagnostic = regional_lexer.diagnostic(Severity::Warning, msg, pos);
+
+        self.warnings.push(diagnostic);
+    }
+
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Region of the file which current region belongs to.
+    pub fn file_regional_lexer(&self) -> &RegionalLexer {
+        self.regional_lexers
+            .iter()
+            .rev()
+            .find(|regional_lexer| regional_lexer.file.is_some())
+            .unwrap_or(&self.global_lexer)
+    }
+
+    /// Presumed location of current token of the file being read, where
+    /// tokens expanded from macros are located.
+    pub fn presumed_location(&self) -> PresumedLocation {
+        let regional_lexer = self.file_regional_lexer();
+
+        regional_lexer.presumed_location(regional_lexer.cur_token_pos)
+    }
+
+    /// Presumed location following current token of the file being read,
+    /// which begins the next line once a directive is finished.
+    pub fn presumed_location_after(&self) -> PresumedLocation {
+        let regional_lexer = self.file_regional_lexer();
+
+        regional_lexer.presumed_location(regional_lexer.pos)
+    }
+
+    pub fn current_regional_lexer(&self) -> &RegionalLexer {
+        self.regional_lexers.back().unwrap_or(&self.global_lexer)
+    }
+
+    pub fn current_mut_regional_lexer(&mut self) -> &mut RegionalLexer {
+        self.regional_lexers.back_mut().unwrap_or(&mut self.global_lexer)
+    }
+
+    pub fn current_token_type(&self) -> TokenType {
+        self.current_regional_lexer().cur_token_type
+    }
+
+    pub fn current_token_str(&self) -> &str {
+        &self.current_regional_lexer().cur_token_str
+    }
+
+    /// Returns symbol of current token if it is an identifier.
+    pub fn current_token_symbol(&self) -> Option<Symbol> {
+        self.current_regional_lexer().cur_token_symbol
+    }
+
+    pub fn current_token_pos(&self) -> usize {
+        self.current_regional_lexer().cur_token_pos
+    }
+
+    pub fn current_token(&self) -> Token {
+        Token {
+            token_type: self.current_token_type(),
+            token_str: self.current_regional_lexer().cur_token_str.clone(),
+            spaced: self.current_token_spaced(),
+            no_expand: self.current_token_no_expand(),
+        }
+    }
+
+    /// Returns whether current token is preceded by whitespace or comments.
+    pub fn current_token_spaced(&self) -> bool {
+        self.current_regional_lexer().cur_token_spaced
+    }
+
+    /// Returns whether current identifier must not be expanded.
+    pub fn current_token_no_expand(&self) -> bool {
+        self.current_regional_lexer().cur_token_no_expand
+    }
+
+    pub fn pos(&self) -> usize {
+        self.current_regional_lexer().pos
+    }
+
+    pub fn append_regional_lexer(&mut self, source: Rc<str>) -> Result<(), Box<Diagnostic>> {
+        self.push_region(self.new_regional_lexer(source))
+    }
+
+    /// Appends region reading already lexed tokens.
+    pub fn append_token_lexer(&mut self, tokens: Rc<[Token]>) -> Result<(), Box<Diagnostic>> {
+        self.push_token_lexer(RegionalLexer::from_tokens(tokens))
+    }
+
+    /// Appends region holding replacement tokens of macro `symbol`, which
+    /// cannot be expanded again until the region is escaped.
+    pub fn append_expansion_lexer(
+        &mut self,
+        symbol: Symbol,
+        tokens: Rc<[Token]>,
+    ) -> Result<(), Box<Diagnostic>> {
+        if self.current_regional_lexer().tokens.is_none() {
+            self.expansion_output_tokens = 0;
+        }
+
+        let mut regional_lexer = RegionalLexer::from_tokens(tokens);
+        regional_lexer.expansion = Some(symbol);
+        self.push_token_lexer(regional_lexer)
+    }
+
+    /// Pushes region reading tokens of macro expansion or argument, unless
+    /// such regions are nested deeper than the maximum expansion depth.
+    fn push_token_lexer(&mut self, regional_lexer: RegionalLexer) -> Result<(), Box<Diagnostic>> {
+        let depth = self
+            .regional_lexers
+            .iter()
+            .filter(|regional_lexer| regional_lexer.tokens.is_some())
+            .count();
+
+        if depth >= self.limits.max_expansion_depth {
+            return Err(self.error(
+                &format!(
+                    "Macro expansion too deep, exceeding maximum depth of {}",
+                    self.limits.max_expansion_depth
+                ),
+                self.current_token_pos(),
+            ));
+        }
+
+        self.push_region(regional_lexer)
+    }
+
+    /// Pushes region onto the region stack, unless the stack is as deep as
+    /// the maximum region depth.
+    fn push_region(&mut self, regional_lexer: RegionalLexer) -> Result<(), Box<Diagnostic>> {
+        if let Some(max) = self
+            .limits
+            .max_region_depth
+            .filter(|max| self.regional_lexers.len() >= *max)
+        {
+            return Err(self.error(
+                &format!("Region stack exceeds maximum depth of {max}"),
+                self.current_token_pos(),
+            ));
+        }
+
+        self.regional_lexers.push_back(regional_lexer);
+        Ok(())
+    }
+
+    /// Replaces resource limits, which bound only nesting by default.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Counts tokens written to output, which are bounded by
+    /// [`Limits::max_output_tokens`]. Tokens of macro expansion are also
+    /// counted toward the invocation read from a file which produced them, so
+    /// that macros growing by rescanning are bounded by
+    /// [`Limits::max_expansion_tokens`].
+    pub fn count_output_tokens(&mut self, count: usize) -> Result<(), Box<Diagnostic>> {
+        self.output_tokens += count;
+
+        if self.current_regional_lexer().tokens.is_some() {
+            self.expansion_output_tokens += count;
+
+            // Outermost expansion is the one read from a file
+            let symbol = self
+                .regional_lexers
+                .iter()
+                .rev()
+                .take_while(|regional_lexer| regional_lexer.tokens.is_some())
+                .filter_map(|regional_lexer| regional_lexer.expansion)
+                .last();
+
+            if let Some(symbol) = symbol {
+                self.check_expansion_size(self.resolve(symbol), self.expansion_output_tokens)?;
+            }
+        }
+
+        if let Some(max) = self
+            .limits
+            .max_output_tokens
+            .filter(|max| self.output_tokens > *max)
+        {
+            return Err(self.error(
+                &format!("Output exceeds maximum of {max} tokens"),
+                self.current_token_pos(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks size of expansion of macro `name`, or of arguments of
+    /// function-like macro, which is bounded by
+    /// [`Limits::max_expansion_tokens`].
+    pub fn check_expansion_size(&self, name: &str, count: usize) -> Result<(), Box<Diagnostic>> {
+        if let Some(max) = self.limits.max_expansion_tokens.filter(|max| count > *max) {
+            return Err(self.error(
+                &format!("Expansion of macro {name} exceeds maximum of {max} tokens"),
+                self.current_token_pos(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Appends region which is not escaped once exhausted, so that its content
+    /// can be expanded in isolation. Returns the previous region floor, which
+    /// must be passed to [`Lexer::escape_isolated_lexer`].
+    pub fn append_isolated_lexer(&mut self, source: Rc<str>) -> Result<usize, Box<Diagnostic>> {
+        self.append_regional_lexer(source)?;
+        Ok(self.isolate_current_lexer())
+    }
+
+    /// Appends isolated region reading already lexed tokens, see
+    /// [`Lexer::append_isolated_lexer`].
+    pub fn append_isolated_token_lexer(
+        &mut self,
+        tokens: Rc<[Token]>,
+    ) -> Result<usize, Box<Diagnostic>> {
+        self.append_token_lexer(tokens)?;
+        Ok(self.isolate_current_lexer())
+    }
+
+    fn isolate_current_lexer(&mut self) -> usize {
+        std::mem::replace(&mut self.region_floor, self.regional_lexers.len())
+    }
+
+    pub fn escape_isolated_lexer(&mut self, region_floor: usize) {
+        self.regional_lexers.truncate(self.region_floor - 1);
+        self.region_floor = region_floor;
+    }
+
+    /// Adds directory searched by `#include`, after the directory of including
+    /// file for quoted header names.
+    pub fn add_include_path(&mut self, path: impl Into<PathBuf>) {
+        self.include_paths.push(path.into());
+    }
+
+    /// Index of the file which current region belongs to.
+    fn current_file(&self) -> usize {
+        self.regional_lexers
+            .iter()
+            .rev()
+            .find_map(|regional_lexer| regional_lexer.file)
+            .unwrap_or(0)
+    }
+
+    /// Whether current file is the main file.
+    pub fn in_main_file(&self) -> bool {
+        self.current_file() == 0
+    }
+
+    /// Searches file of header name, quoted names are first searched from the
+    /// directory of current file. If `next` is set, the search continues from
+    /// the include path following the one where current file was found.
+    /// Returns the path and index of include path where it was found.
+    pub fn resolve_include(
+        &self,
+        name: &str,
+        angled: bool,
+        next: bool,
+    ) -> Option<(PathBuf, Option<usize>)> {
+        let path = Path::new(name);
+
+        if path.is_absolute() {
+            return self
+                .source_manager
+                .is_file(path)
+                .then(|| (path.to_path_buf(), None));
+        }
+
+        let include_dir = self.file_regional_lexer().include_dir;
+
+        if let (true, Some(include_dir)) = (next, include_dir) {
+            return self.search_include_paths(path, include_dir + 1);
+        }
+
+        if !angled {
+            let directory = self.files[self.current_file()]
+                .path
+                .parent()
+                .unwrap_or(Path::new(""));
+            let candidate = directory.join(path);
+
+            if self.source_manager.is_file(&candidate) {
+                return Some((candidate, None));
+            }
+        }
+
+        self.search_include_paths(path, 0)
+    }
+
+    /// Searches include paths from `start`, skipping those which are not
+    /// directories.
+    fn search_include_paths(&self, path: &Path, start: usize) -> Option<(PathBuf, Option<usize>)> {
+        self.include_paths
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, directory)| self.source_manager.is_directory(directory))
+            .map(|(idx, directory)| (directory.join(path), Some(idx)))
+            .find(|(candidate, _)| self.source_manager.is_file(candidate))
+    }
+
+    /// Appends region of included file, unless the file has `#pragma once` or
+    /// its include guard is still defined, in which case it is not read again.
+    /// `include_dir` is index of include path where the file was found, and
+    /// failure to read the file is reported at `pos`. Returns index of the
+    /// entered file, which must be escaped by [`Lexer::escape_file_lexer`]
+    /// once exhausted.
+    pub fn enter_file(
+        &mut self,
+        path: &Path,
+        include_dir: Option<usize>,
+        pos: usize,
+    ) -> Result<Option<usize>, Box<Diagnostic>> {
+        let depth = self
+            .regional_lexers
+            .iter()
+            .filter(|regional_lexer| regional_lexer.file.is_some())
+            .count();
+
+        if depth >= self.limits.max_include_depth {
+            return Err(self.error(
+                &format!(
+                    "#include nested depth {} exceeds maximum",
+                    self.limits.max_include_depth
+                ),
+                self.current_token_pos(),
+            ));
+        }
+
+        // Skipped files are identified without being opened
+        let source = self
+            .source_manager
+            .identify(path)
+            .map_err(|err| self.error(&format!("{}: {err}", path.display()), pos))?;
+        let file = match self
+            .files
+            .iter()
+            .position(|file| file.source == Some(source))
+        {
+            Some(file) => file,
+            None => {
+                self.files
+                    .push(SourceFile::new(path.to_path_buf(), Some(source)));
+                self.files.len() - 1
+            }
+        };
+
+        let source_file = &self.files[file];
+
+        if source_file.pragma_once
+            || source_file
+                .guard
+                .as_ref()
+                .is_some_and(|guard| self.is_defined(guard))
+        {
+            self.notify(|observer| observer.include_skipped(path));
+            return Ok(None);
+        }
+
+        let text = self
+            .source_manager
+            .read(source)
+            .map_err(|err| self.error(&format!("{}: {err}", path.display()), pos))?;
+        let (source, splices) = splice_lines(&text);
+        let mut regional_lexer = self.new_regional_lexer(source.into());
+        regional_lexer.splices = splices;
+        regional_lexer.directives = true;
+        regional_lexer.file = Some(file);
+        regional_lexer.file_name = path.display().to_string();
+        regional_lexer.include_dir = include_dir;
+        self.push_region(regional_lexer)?;
+        self.notify(|observer| observer.file_entered(path));
+
+        Ok(Some(file))
+    }
+
+    /// Appends region of directives given by command-line options, which is
+    /// read as a file named `<command line>` in working directory. Returns
+    /// index of the entered file.
+    pub fn enter_command_line(&mut self, source: &str) -> Result<usize, Box<Diagnostic>> {
+        self.files.push(SourceFile::new(PathBuf::new(), None));
+
+        let (source, splices) = splice_lines(source);
+        let mut regional_lexer = self.new_regional_lexer(source.into());
+        regional_lexer.splices = splices;
+        regional_lexer.directives = true;
+        regional_lexer.file = Some(self.files.len() - 1);
+        regional_lexer.file_name = "<command line>".to_string();
+        self.push_region(regional_lexer)?;
+
+        Ok(self.files.len() - 1)
+    }
+
+    pub fn escape_file_lexer(&mut self) {
+        let file = self
+            .regional_lexers
+            .pop_back()
+            .and_then(|regional_lexer| regional_lexer.file);
+
+        debug_assert!(file.is_some());
+
+        // Regions of command-line options are not files entered by `#include`
+        if let Some(file) = file.filter(|file| self.files[*file].source.is_some()) {
+            let path = self.files[file].path.clone();
+            self.notify(|observer| observer.file_exited(&path));
+        }
+    }
+
+    /// Records include guard detected in file, which is skipped by later
+    /// `#include`s while the guard is defined.
+    pub fn set_include_guard(&mut self, file: usize, guard: Option<String>) {
+        self.files[file].guard = guard;
+    }
+
+    /// Marks current file to be never included again, returns false if it is
+    /// the main file.
+    pub fn mark_pragma_once(&mut self) -> bool {
+        let file = self.current_file();
+        self.files[file].pragma_once = true;
+
+        file != 0
+    }
+
+    /// Replaces capability table answering feature-test operators, which is
+    /// [`Capabilities::gcc`] by default.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Returns feature test of operator `name` if it is provided.
+    pub fn feature_test(&self, name: &str) -> Option<FeatureTest> {
+        FeatureTest::from_operator(name).filter(|test| self.capabilities.provides(*test))
+    }
+
+    pub fn test_feature(&self, test: FeatureTest, name: &str) -> u64 {
+        self.capabilities.test(test, name)
+    }
+
+    /// Adds observer of preprocessing events, observers are notified in the
+    /// order they are added.
+    pub fn add_observer(&mut self, observer: impl PreprocessorObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Presumed location of `pos` of current region, tokens of macro
+    /// expansions are located at the invocation.
+    pub fn location_of(&self, pos: usize) -> PresumedLocation {
+        let (regional_lexer, pos) = self.diagnostic_location(pos);
+        regional_lexer.presumed_location(pos)
+    }
+
+    /// Location of `pos` of current region reported to observers and the
+    /// tracer, which is `None` if neither is enabled to avoid computing it.
+    pub fn observed_location(&self, pos: usize) -> Option<PresumedLocation> {
+        if self.observers.is_empty() && self.tracer.is_none() {
+            return None;
+        }
+
+        Some(self.location_of(pos))
+    }
+
+    pub fn notify(&mut self, mut event: impl FnMut(&mut dyn PreprocessorObserver)) {
+        for observer in &mut self.observers {
+            event(observer.as_mut());
+        }
+    }
+
+    /// Starts recording macro expansions into a new [`ExpansionTrace`], or
+    /// stops recording if `enabled` is false. Tracing is disabled by default.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.tracer = enabled.then(ExpansionTrace::new);
+    }
+
+    pub fn trace(&self) -> Option<&ExpansionTrace> {
+        self.tracer.as_ref()
+    }
+
+    /// Adds node of `step` under `parent` if tracing is enabled, `step` is
+    /// not computed otherwise.
+    pub fn trace_step(
+        &mut self,
+        parent: Option<NodeId>,
+        step: impl FnOnce() -> TraceStep,
+    ) -> Option<NodeId> {
+        let tracer = self.tracer.as_mut()?;
+        Some(tracer.add(parent, step()))
+    }
+
+    pub(crate) fn tracer_mut(&mut self) -> Option<&mut ExpansionTrace> {
+        self.tracer.as_mut()
+    }
+
+    /// Node which invocations read from current region are traced under,
+    /// `None` outside of macro expansions.
+    pub fn region_trace(&self) -> Option<NodeId> {
+        self.current_regional_lexer().trace
+    }
+
+    /// Traces current region under `trace`, `origins` are the nodes which
+    /// produced each of its tokens.
+    pub fn set_region_trace(&mut self, trace: NodeId, origins: Rc<[Option<NodeId>]>) {
+        let regional_lexer = self.current_mut_regional_lexer();
+        regional_lexer.trace = Some(trace);
+        regional_lexer.origins = Some(origins);
+    }
+
+    /// Node of the expansion which produced current token, `None` if it is
+    /// read from source.
+    pub fn current_token_origin(&self) -> Option<NodeId> {
+        let regional_lexer = self.current_regional_lexer();
+
+        regional_lexer
+            .origins
+            .as_ref()
+            .and_then(|origins| origins.get(regional_lexer.cur_token_pos).copied())
+            .flatten()
+    }
+
+    /// Records current token as written to output if tracing is enabled.
+    pub fn trace_output(&mut self) {
+        let origin = self.current_token_origin();
+        let regional_lexer = self.regional_lexers.back().unwrap_or(&self.global_lexer);
+
+        if let Some(tracer) = &mut self.tracer {
+            tracer.push_output(&regional_lexer.cur_token_str, origin);
+        }
+    }
+
+    /// Traces expansion of object-like macro `symbol`, whose region is
+    /// current region.
+    fn trace_alias_expansion(
+        &mut self,
+        parent: Option<NodeId>,
+        symbol: Symbol,
+        location: PresumedLocation,
+        pastes: Vec<TraceStep>,
+    ) {
+        let name = self.resolve(symbol).to_string();
+        let tokens = self.current_regional_lexer().tokens.clone().unwrap();
+        let Some(tracer) = &mut self.tracer else {
+            return;
+        };
+
+        let invocation = tracer.add(
+            parent,
+            TraceStep::Invocation {
+                name,
+                arguments: None,
+                location,
+            },
+        );
+
+        for paste in pastes {
+            tracer.add(Some(invocation), paste);
+        }
+
+        tracer.add(
+            Some(invocation),
+            TraceStep::Substitution {
+                result: spell_tokens(&tokens),
+            },
+        );
+        let rescan = tracer.add(Some(invocation), TraceStep::Rescan);
+
+        self.set_region_trace(rescan, vec![Some(rescan); tokens.len()].into());
+    }
+
+    /// Claims pragma namespace, replacing previously registered handler.
+    pub fn register_pragma_handler(
+        &mut self,
+        namespace: &str,
+        handler: impl PragmaHandler + 'static,
+    ) {
+        self.pragma_handlers
+            .insert(namespace.to_string(), Box::new(handler));
+    }
+
+    pub fn pragma_expands_operands(&self, namespace: &str) -> bool {
+        self.pragma_handlers
+            .get(namespace)
+            .is_some_and(|handler| handler.expands_operands())
+    }
+
+    /// Dispatches pragma to handler of its namespace, unclaimed pragmas are
+    /// passed through.
+    pub fn handle_pragma(&mut self, pragma: &Pragma) -> Result<PragmaAction, Box<Diagnostic>> {
+        let Some((namespace, mut handler)) = self.pragma_handlers.remove_entry(pragma.namespace())
+        else {
+            return Ok(PragmaAction::PassThrough);
+        };
+
+        let action = handler.handle(&mut PragmaContext::new(self), pragma);
+        self.pragma_handlers.entry(namespace).or_insert(handler);
+        action.map_err(Box::new)
+    }
+
+    /// Marks current identifier never to be expanded if it names a macro
+    /// being expanded, as such names stay unexpanded when rescanned later
+    /// (C11 6.10.3.4p2). Returns whether the identifier is marked.
+    pub fn paint_current_token(&mut self) -> bool {
+        let regional_lexer = self.current_regional_lexer();
+
+        if regional_lexer.cur_token_no_expand {
+            return true;
+        }
+
+        let Some(symbol) = regional_lexer.cur_token_symbol else {
+            return false;
+        };
+
+        if !self.definitions.contains_key(&symbol) || !self.is_expanding(symbol) {
+            return false;
+        }
+
+        self.current_mut_regional_lexer().cur_token_no_expand = true;
+        true
+    }
+
+    fn is_expanding(&self, symbol: Symbol) -> bool {
+        self.regional_lexers
+            .iter()
+            .any(|regional_lexer| regional_lexer.expansion == Some(symbol))
+    }
+
+    /// Returns name of identifier `symbol`.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        self.interner.resolve(symbol)
+    }
+
+    /// Replaces definition of `name`.
+    fn add_definition(&mut self, name: &str, definition: Definition) {
+        let symbol = self.interner.intern(name);
+        self.definitions.insert(symbol, definition);
+    }
+
+    /// Defines object-like macro `alias`, `location` is where its name is
+    /// written in `#define`.
+    pub fn add_alias(
+        &mut self,
+        alias: &str,
+        replacement: Vec<Token>,
+        location: Option<PresumedLocation>,
+    ) {
+        self.add_definition(
+            alias,
+            Definition::Alias(Alias::new(alias.to_string(), replacement.into(), location)),
+        );
+    }
+
+    /// Defines predefined object-like macro whose replacement is lexed from
+    /// `source`, which must consist of valid tokens.
+    pub fn define(&mut self, name: &str, source: &str) {
+        let replacement = self
+            .lex_tokens(source)
+            .expect("predefined macro replacement is lexed");
+        self.add_alias(name, replacement, None);
+    }
+
+    /// Lexes tokens of `source` in the current language.
+    pub fn lex_tokens(&self, source: &str) -> Result<Vec<Token>, Box<Diagnostic>> {
+        let mut regional_lexer = self.new_regional_lexer(source.into());
+        let mut tokens = vec![];
+
+        loop {
+            regional_lexer.lex_token()?;
+
+            if regional_lexer.cur_token_type == TokenType::TEof {
+                break Ok(tokens);
+            }
+
+            tokens.push(Token {
+                token_type: regional_lexer.cur_token_type,
+                token_str: std::mem::take(&mut regional_lexer.cur_token_str),
+                spaced: regional_lexer.cur_token_spaced,
+                no_expand: false,
+            });
+        }
+    }
+
+    /// Pastes operands of `##` into a single token, which must be a valid
+    /// preprocessing token. The result inherits spacing of `lhs`.
+    pub fn paste_tokens(&self, lhs: &Token, rhs: &Token) -> Result<Token, Box<Diagnostic>> {
+        let spelling = format!("{}{}", lhs.token_str, rhs.token_str);
+        // Pasted comment would not be lexed as token
+        let comment = lhs.token_str.ends_with('/') && rhs.token_str.starts_with(['/', '*']);
+        let tokens = match comment {
+            true => vec![],
+            false => self.lex_tokens(&spelling).unwrap_or_default(),
+        };
+
+        match tokens.as_slice() {
+            [token] if token.token_str.len() == spelling.len() => Ok(Token {
+                spaced: lhs.spaced,
+                ..token.clone()
+            }),
+            _ => Err(self.error(
+                &format!(
+                    "Pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+                    lhs.token_str, rhs.token_str
+                ),
+                self.current_token_pos(),
+            )),
+        }
+    }
+
+    /// Pastes operands of every `##` in replacement of object-like macro,
+    /// recording the pastes as trace steps.
+    fn paste_replacement(
+        &self,
+        replacement: &[Token],
+        pastes: &mut Vec<TraceStep>,
+    ) -> Result<Vec<Token>, Box<Diagnostic>> {
+        let mut tokens: Vec<Token> = vec![];
+        let mut replacement = replacement.iter();
+
+        while let Some(token) = replacement.next() {
+            if token.token_type != TokenType::TCppdConcat {
+                tokens.push(token.clone());
+                continue;
+            }
+
+            // `#define` rejects `##` at either end, which is kept as written
+            // in replacements of other origins
+            let Some(lhs) = tokens.pop() else {
+                tokens.push(token.clone());
+                continue;
+            };
+            let Some(rhs) = replacement.next() else {
+                tokens.extend([lhs, token.clone()]);
+                continue;
+            };
+            let pasted = self.paste_tokens(&lhs, rhs)?;
+
+            pastes.push(TraceStep::Paste {
+                left: lhs.token_str.to_string(),
+                right: rhs.token_str.to_string(),
+                result: pasted.token_str.to_string(),
+            });
+            tokens.push(pasted);
+        }
+
+        Ok(tokens)
+    }
+
+    /// Returns object-like macro `symbol` unless it is being expanded.
+    pub fn find_alias(&self, symbol: Symbol) -> Option<&Alias> {
+        match self.definitions.get(&symbol) {
+            Some(Definition::Alias(alias)) if !self.is_expanding(symbol) => Some(alias),
+            _ => None,
+        }
+    }
+
+    /// Removes definition of `name`, returns whether `name` was defined.
+    pub fn undef(&mut self, name: &str) -> bool {
+        self.interner
+            .get(name)
+            .and_then(|symbol| self.definitions.remove(&symbol))
+            .is_some()
+    }
+
+    /// Tests whether `name` is defined as `defined`, `#ifdef` and `#ifndef`
+    /// do, which counts as use of the macro.
+    pub fn test_defined(&self, name: &str) -> bool {
+        if let Some(definition) = self
+            .interner
+            .get(name)
+            .and_then(|symbol| self.definitions.get(&symbol))
+        {
+            definition.used().set(true);
+        }
+
+        self.is_defined(name)
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        BUILTIN_MACROS.contains(&name)
+            || self.feature_test(name).is_some()
+            || self
+                .interner
+                .get(name)
+                .is_some_and(|symbol| self.definitions.contains_key(&symbol))
+    }
+
+    /// Defines function-like macro `name`, `location` is where its name is
+    /// written in `#define`.
+    pub fn add_macro(
+        &mut self,
+        name: &str,
+        parameters: Vec<String>,
+        is_variadic: bool,
+        replacement: Vec<ReplacementToken>,
+        location: Option<PresumedLocation>,
+    ) {
+        self.add_definition(
+            name,
+            Definition::Macro(Macro::new(
+                name.to_string(),
+                parameters,
+                is_variadic,
+                replacement,
+                location,
+            )),
+        );
+    }
+
+    /// Saves current definition of `name`, which is restored by
+    /// [`Lexer::pop_macro`].
+    pub fn push_macro(&mut self, name: &str) {
+        let symbol = self.interner.intern(name);
+        let definition = self.definitions.get(&symbol).cloned();
+
+        self.macro_stacks
+            .entry(symbol)
+            .or_default()
+            .push(definition);
+    }
+
+    /// Restores the latest definition of `name` saved by
+    /// [`Lexer::push_macro`], nothing happens if none is saved.
+    pub fn pop_macro(&mut self, name: &str) {
+        let Some(symbol) = self.interner.get(name) else {
+            return;
+        };
+        let Some(definition) = self
+            .macro_stacks
+            .get_mut(&symbol)
+            .and_then(|definitions| definitions.pop())
+        else {
+            return;
+        };
+
+        match definition {
+            Some(definition) => self.definitions.insert(symbol, definition),
+            None => self.definitions.remove(&symbol),
+        };
+    }
+
+    /// Returns function-like macro `symbol` unless it is being expanded.
+    pub fn find_macro(&self, symbol: Symbol) -> Option<&Macro> {
+        match self.definitions.get(&symbol) {
+            Some(Definition::Macro(mac)) if !self.is_expanding(symbol) => Some(mac),
+            _ => None,
         }
     }
 
-    fn next_token(&mut self) {
-        if let Some(regional) = self.regional_lexers.back_mut() {
-            regional.lex_token();
-        } else {
-            self.global_lexer.lex_token();
+    /// Describes macro `name` if it is currently defined.
+    pub fn macro_info(&self, name: &str) -> Option<MacroInfo<'_>> {
+        if BUILTIN_MACROS.contains(&name) || self.feature_test(name).is_some() {
+            let name = BUILTIN_MACROS
+                .iter()
+                .chain(&FEATURE_OPERATORS)
+                .find(|builtin| **builtin == name)
+                .unwrap();
+
+            return Some(MacroInfo {
+                name,
+                kind: MacroKind::Builtin,
+                parameters: &[],
+                replacement: vec![],
+                location: None,
+                used: false,
+            });
         }
+
+        let definition = self.definitions.get(&self.interner.get(name)?)?;
+
+        Some(match definition {
+            Definition::Alias(alias) => MacroInfo {
+                name: &alias.alias,
+                kind: MacroKind::Object,
+                parameters: &[],
+                replacement: alias.replacement.to_vec(),
+                location: alias.location.as_ref(),
+                used: alias.used.get(),
+            },
+            Definition::Macro(mac) => {
+                let mut replacement = vec![];
+                replacement_tokens(&mac.parameters, &mac.replacement, &mut replacement);
+
+                MacroInfo {
+                    name: &mac.name,
+                    kind: match mac.is_variadic {
+                        true => MacroKind::Variadic,
+                        false => MacroKind::Function,
+                    },
+                    parameters: &mac.parameters,
+                    replacement,
+                    location: mac.location.as_ref(),
+                    used: mac.used.get(),
+                }
+            }
+        })
     }
 
-    pub fn lex_token(&mut self, aliasing: bool) -> TokenType {
-        self.next_token();
+    /// Describes every currently defined macro including builtin macros,
+    /// sorted by name.
+    pub fn macros(&self) -> Vec<MacroInfo<'_>> {
+        let mut names = BUILTIN_MACROS
+            .iter()
+            .chain(&FEATURE_OPERATORS)
+            .copied()
+            .chain(
+                self.definitions
+                    .keys()
+                    .map(|symbol| self.interner.resolve(*symbol)),
+            )
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+
+        names
+            .into_iter()
+            .filter_map(|name| self.macro_info(name))
+            .collect()
+    }
+}
 
-        let token_type = self.current_token_type();
+/// Spells a string literal of given token sequence, backslashes and quotes in
+/// string and character literals are escaped.
+pub fn stringify(spelling: &str) -> String {
+    let mut builder = String::with_capacity(spelling.len() + 2);
+    let mut quote = None;
+    let mut escaped = false;
 
-        match token_type {
-            TokenType::TEof => {
-                if !self.regional_lexers.is_empty() {
-                    // escapes current region
-                    self.regional_lexers.pop_back();
-                    return self.lex_token(aliasing);
+    builder.push('"');
+
+    for ch in spelling.chars() {
+        match quote {
+            Some(delimiter) => {
+                if ch == '"' || ch == '\\' {
+                    builder.push('\\');
                 }
-            }
-            TokenType::TIdentifier => {
-                if aliasing {
-                    if let Some(alias) = self.find_alias(&self.current_token_str()) {
-                        // enter alias region for parsing
-                        self.append_regional_lexer(alias.replacement.clone(), vec![]);
-                        return self.lex_token(aliasing);
-                    }
+
+                if escaped {
+                    escaped = false;
+                } else if ch == '\\' {
+                    escaped = true;
+                } else if ch == delimiter {
+                    quote = None;
                 }
             }
-            TokenType::TCppdStringify => {
-                if self.ignore_stringify {
-                    return TokenType::TCppdStringify;
-                }
-
-                self.lex_token_then_expand_as_string();
-                return TokenType::TString;
+            None if ch == '"' => {
+                builder.push('\\');
+                quote = Some(ch);
             }
-            _ => {}
+            None if ch == '\'' => quote = Some(ch),
+            None => {}
         }
 
-        token_type
+        builder.push(ch);
     }
 
-    /// Tokenizes next token and expand into a string token if the following conditions are met:
-    /// 1. Token has type TIdentifier
-    /// 2. Expands to its alias once if identifier tokens
-    pub fn lex_token_then_expand_as_string(&mut self) {
-        self.next_token();
+    builder.push('"');
+    builder
+}
 
-        let token_type = self.current_token_type();
-        let token_str = self.current_token_str();
+pub struct RegionalLexer {
+    source: Rc<str>,
+    splices: Vec<Splice>,
+    /// Offset in source, or index of next token if the region reads tokens
+    pos: usize,
+    /// Already lexed tokens read instead of source, as in macro expansions
+    tokens: Option<Rc<[Token]>>,
+    /// Macro whose replacement the region reads
+    expansion: Option<Symbol>,
+    /// Traced node of invocations read from the region
+    trace: Option<NodeId>,
+    /// Traced nodes which produced each of the tokens
+    origins: Option<Rc<[Option<NodeId>]>>,
+    /// Index of source file if the region reads a whole file
+    file: Option<usize>,
+    file_name: String,
+    /// Index of include path where the file of region was found
+    include_dir: Option<usize>,
+    line_directives: Vec<LineDirective>,
+    cur_token_type: TokenType,
+    cur_token_str: Spelling,
+    /// Symbol of current token if it is an identifier, interned by [`Lexer`]
+    cur_token_symbol: Option<Symbol>,
+    cur_token_pos: usize,
+    cur_token_spaced: bool,
+    cur_token_no_expand: bool,
+    line_start: bool,
+    directives: bool,
+    /// Whether first token is spaced, inherited from the replaced token
+    pub leading_spaced: bool,
+    pub skip_newline: bool,
+    pub skipping: bool,
+    language: LanguageOptions,
+    /// Pedantic warnings about extensions in lexed tokens, with positions
+    extensions: Vec<(String, usize)>,
+    /// Furthest position whose location is computed, with count of newlines
+    /// before it
+    newlines: Cell<(usize, usize)>,
+}
 
-        match token_type {
-            TokenType::TIdentifier => {
-                if let Some(alias) = self.find_alias(&self.current_token_str()) {
-                    self.current_mut_regional_lexer().cur_token_str = alias.replacement.clone();
-                    self.current_mut_regional_lexer().cur_token_type = TokenType::TString;
-                }
-            }
-            _ => {}
+impl RegionalLexer {
+    pub fn new(source: Rc<str>) -> Self {
+        Self {
+            source,
+            splices: vec![],
+            pos: 0,
+            tokens: None,
+            expansion: None,
+            trace: None,
+            origins: None,
+            file: None,
+            file_name: String::new(),
+            include_dir: None,
+            line_directives: vec![],
+            cur_token_type: TokenType::TStart,
+            cur_token_str: Spelling::default(),
+            cur_token_symbol: None,
+            cur_token_pos: 0,
+            cur_token_spaced: false,
+            cur_token_no_expand: false,
+            line_start: true,
+            directives: false,
+            leading_spaced: false,
+            skip_newline: true,
+            skipping: false,
+            language: LanguageOptions::default(),
+            extensions: vec![],
+            newlines: Cell::new((0, 0)),
         }
+    }
 
-        self.current_mut_regional_lexer().cur_token_str = format!("\"{}\"", self.current_mut_regional_lexer().cur_token_str);
+    pub fn from_tokens(tokens: Rc<[Token]>) -> Self {
+        let mut regional_lexer = Self::new(Rc::default());
+        regional_lexer.tokens = Some(tokens);
+        regional_lexer
     }
 
-    pub fn lex_accept_internal(&mut self, token_type: TokenType, aliasing: bool) -> bool {
-        if self.current_token_type() == token_type {
-            self.lex_token(aliasing);
-            return true;
+    /// Maps position in spliced source back to physical source.
+    pub fn physical_pos(&self, pos: usize) -> usize {
+        match self.splices.partition_point(|splice| splice.pos <= pos) {
+            0 => pos,
+            idx => pos + self.splices[idx - 1].removed,
         }
-
-        return false;
     }
 
-    pub fn lex_accept(&mut self, token_type: TokenType, aliasing: bool) -> bool {
-        self.lex_accept_internal(token_type, aliasing)
-    }
+    /// Counts newlines before `pos`, resuming from the furthest position
+    /// counted so far so that locations of advancing positions are computed
+    /// in linear time overall.
+    fn count_newlines(&self, pos: usize) -> usize {
+        let (counted_pos, count) = self.newlines.get();
 
-    pub fn lex_peek(&mut self, token_type: TokenType) -> bool {
-        self.current_token_type() == token_type
+        if pos < counted_pos {
+            return self.source[..pos].matches('\n').count();
+        }
+
+        let count = count + self.source[counted_pos..pos].matches('\n').count();
+        self.newlines.set((pos, count));
+        count
     }
 
-    pub fn lex_expect(&mut self, token_type: TokenType, aliasing: bool) {
-        if self.current_token_type() != token_type {
-            error(
-                &self.regional_source(),
-                &format!(
-                    "Unexpected token {:?}, expexts {:?}",
-                    self.current_token_type(),
-                    token_type
-                ),
-                self.pos(),
-            )
+    /// Computes 1-based physical line and column of position in spliced source.
+    pub fn location(&self, pos: usize) -> (usize, usize) {
+        let pos = pos.min(self.source.len());
+        let line_start = self.source[..pos].rfind('\n').map_or(0, |idx| idx + 1);
+        let idx = self.splices.partition_point(|splice| splice.pos <= pos);
+        let spliced_lines = idx.checked_sub(1).map_or(0, |idx| self.splices[idx].lines);
+        let lines = self.count_newlines(pos) + spliced_lines;
+        let mut physical_line_start = self.physical_pos(line_start);
+
+        // Last line splice after line start begins a new physical line
+        for splice_idx in (0..idx).rev() {
+            let splice = self.splices[splice_idx];
+            let previous_lines = splice_idx
+                .checked_sub(1)
+                .map_or(0, |idx| self.splices[idx].lines);
+
+            if splice.pos < line_start {
+                break;
+            }
+
+            if splice.lines != previous_lines {
+                physical_line_start = physical_line_start.max(self.physical_pos(splice.pos));
+                break;
+            }
         }
 
-        self.lex_token(aliasing);
+        (lines + 1, self.physical_pos(pos) - physical_line_start + 1)
     }
 
-    pub fn current_regional_lexer(&self) -> &RegionalLexer {
-        self.regional_lexers.back().unwrap_or(&self.global_lexer)
+    /// Computes location of position in spliced source after remapping by
+    /// `#line` directives, column is left physical.
+    pub fn presumed_location(&self, pos: usize) -> PresumedLocation {
+        let (line, column) = self.location(pos);
+
+        match self
+            .line_directives
+            .partition_point(|directive| directive.pos <= pos)
+        {
+            0 => PresumedLocation {
+                file: self.file_name.clone(),
+                line,
+                column,
+            },
+            idx => {
+                let directive = &self.line_directives[idx - 1];
+
+                PresumedLocation {
+                    file: directive.file.clone(),
+                    line: directive.line + line - directive.physical_line,
+                    column,
+                }
+            }
+        }
     }
 
-    pub fn current_mut_regional_lexer(&mut self) -> &mut RegionalLexer {
-        self.regional_lexers.back_mut().unwrap_or(&mut self.global_lexer)
+    /// Remaps line starting at current position to `line` of `file`, the file
+    /// name is kept if not given.
+    pub fn add_line_directive(&mut self, line: usize, file: Option<String>) {
+        let file = file.unwrap_or_else(|| self.presumed_location(self.pos).file);
+
+        self.line_directives.push(LineDirective {
+            pos: self.pos,
+            line,
+            physical_line: self.location(self.pos).0,
+            file,
+        });
     }
 
-    pub fn current_token_type(&self) -> TokenType {
-        self.current_regional_lexer().cur_token_type
+    pub fn diagnostic(&self, severity: Severity, msg: &str, pos: usize) -> Diagnostic {
+        Diagnostic::new(
+            severity,
+            msg,
+            self.presumed_location(pos),
+            &self.source,
+            pos,
+        )
     }
 
-    pub fn current_token_str(&self) -> String {
-        self.current_regional_lexer().cur_token_str.clone()
+    /// Error at `pos`, which stops preprocessing once returned. Errors are
+    /// boxed to keep frames of the recursive descent small.
+    pub fn error(&self, msg: &str, pos: usize) -> Box<Diagnostic> {
+        Box::new(self.diagnostic(Severity::Error, msg, pos))
     }
 
-    pub fn current_token_pos(&self) -> usize {
-        self.current_regional_lexer().cur_token_pos
+    fn is_whitespace(ch: u8) -> bool {
+        ch == b' ' || ch == b'\t' || ch == b'\x0b' || ch == b'\x0c'
     }
 
-    pub fn pos(&self) -> usize {
-        self.current_regional_lexer().pos
+    fn is_newline(ch: u8) -> bool {
+        ch == b'\n'
+    }
+
+    /// Records pedantic warning about extension used by the file, extensions
+    /// in macro replacements are reported at their definitions.
+    fn extension(&mut self, msg: &str, pos: usize) {
+        if self.language.pedantic && self.file.is_some() && !self.skipping {
+            self.extensions.push((msg.to_string(), pos));
+        }
     }
 
-    pub fn global_source(&self) -> String {
-        self.global_lexer.source.clone()
+    fn is_digit(ch: u8) -> bool {
+        ch.is_ascii_digit()
     }
 
-    pub fn regional_source(&self) -> String {
-        (&self.current_regional_lexer().source).into()
+    fn is_hex(ch: u8) -> bool {
+        ch.is_ascii_hexdigit() || ch == b'x'
     }
 
-    pub fn regional_aliases(&self) -> &[Alias] {
-        &self.current_regional_lexer().regional_aliases
+    #[allow(dead_code)]
+    fn is_numeric(buf: &[u8]) -> bool {
+        let hex = buf.len() > 2 && buf.starts_with(b"0x");
+
+        buf.iter().all(|ch| {
+            if hex {
+                Self::is_hex(*ch)
+            } else {
+                Self::is_digit(*ch)
+            }
+        })
     }
 
-    pub fn append_regional_lexer(&mut self, source: String, regional_aliases: Vec<Alias>) {
-        self.regional_lexers
-            .push_back(RegionalLexer::new(source, regional_aliases));
+    fn peek_char(&self, offset: usize) -> u8 {
+        if self.pos + offset >= self.source.len() {
+            return b'\0';
+        }
+
+        self.source.as_bytes()[self.pos + offset]
     }
 
-    pub fn add_alias(&mut self, alias: &str, source_span: String) {
-        self.aliases
-            .push(Alias::new(alias.to_string(), source_span));
+    fn read_char(&mut self, offset: usize) {
+        self.pos += offset;
     }
 
-    pub fn find_alias(&self, alias: &str) -> Option<&Alias> {
-        let resolution = self
-            .aliases
-            .iter()
-            .find(|a| a.alias == alias && !a.disabled);
+    fn skip_whitespaces(&mut self) {
+        let bytes = &self.source.as_bytes()[self.pos..];
+
+        // Tokens are mostly separated by a single space or none
+        match bytes {
+            [b' ', ch, ..] if !Self::is_whitespace(*ch) && !Self::is_newline(*ch) => {
+                self.pos += 1;
+                return;
+            }
+            [ch, ..] if Self::is_whitespace(*ch) || Self::is_newline(*ch) => {}
+            _ => return,
+        }
 
-        if resolution.is_some() {
-            return resolution;
+        let length = count_matching(bytes, |word| whitespace_bytes(word, self.skip_newline));
+
+        if self.skip_newline && bytes[..length].contains(&b'\n') {
+            self.line_start = true;
         }
 
-        self
-            .current_regional_lexer()
-            .regional_aliases
-            .iter()
-            .find(|a| a.alias == alias)
+        self.pos += length;
     }
 
-    pub fn undef_alias(&mut self, alias: &str) -> bool {
-        for alias_instance in self.aliases.iter_mut() {
-            if alias_instance.alias == alias {
-                alias_instance.disabled = true;
-                return true;
+    fn next_token(&mut self) -> Result<TokenType, Box<Diagnostic>> {
+        // Comments are skipped iteratively, as long runs of them are common
+        loop {
+            if let Some(token_type) = self.scan_token()? {
+                return Ok(token_type);
             }
         }
+    }
+
+    /// Scans token following whitespaces, returns None if a comment is
+    /// skipped instead.
+    fn scan_token(&mut self) -> Result<Option<TokenType>, Box<Diagnostic>> {
+        self.skip_whitespaces();
+        self.cur_token_pos = self.pos;
+        let ch = self.peek_char(0);
+
+        let token_type = match CHAR_CLASSES[ch as usize] {
+            CharClass::Hash => self.next_hash(1)?,
+            CharClass::Slash => return self.next_slash(),
+            CharClass::Digit => self.next_number(),
+            CharClass::Dot if Self::is_digit(self.peek_char(1)) => self.next_number(),
+            CharClass::Dot if self.peek_char(1) == b'.' && self.peek_char(2) == b'.' => {
+                self.read_char(3);
+                TokenType::TElipsis
+            }
+            CharClass::Dot => {
+                self.read_char(1);
+                TokenType::TDot
+            }
+            CharClass::Quote if ch == b'"' => {
+                self.read_char(self.quoted_length(b'"')?);
+                TokenType::TString
+            }
+            CharClass::Quote => {
+                self.read_char(self.quoted_length(b'\'')?);
+                TokenType::TChar
+            }
+            CharClass::Punctuator => self.next_punctuator(ch)?,
+            CharClass::IdentifierStart => self.next_identifier(),
+            CharClass::Dollar if self.language.dollar_identifiers => self.next_identifier(),
+            CharClass::Newline => {
+                self.read_char(1);
+                TokenType::TNewline
+            }
+            CharClass::Nul => TokenType::TEof,
+            CharClass::Whitespace | CharClass::Dollar | CharClass::Other => {
+                let length = self.source[self.pos..]
+                    .chars()
+                    .next()
+                    .map_or(1, char::len_utf8);
+                self.read_char(length);
+                TokenType::TOther
+            }
+        };
 
-        return false;
+        Ok(Some(token_type))
     }
 
-    pub fn add_macro(
-        &mut self,
-        name: &str,
-        parameters: Vec<Alias>,
-        is_variadic: bool,
-        source_span: String,
-    ) {
-        self.macros.push(Macro::new(
-            name.to_string(),
-            parameters,
-            is_variadic,
-            source_span,
-        ));
+    /// Reads `#` or `%:` of `length`, which starts directive at line start.
+    fn next_hash(&mut self, length: usize) -> Result<TokenType, Box<Diagnostic>> {
+        if self.line_start && self.directives {
+            return self.next_directive(length);
+        }
+
+        self.read_char(length);
+        Ok(self.next_concat())
     }
 
-    pub fn find_macro(&self, name: &str) -> Option<&Macro> {
-        self.macros.iter().find(|m| m.name == name)
+    /// Extends `#` just read into `##` if another `#` or `%:` follows.
+    fn next_concat(&mut self) -> TokenType {
+        if self.peek_char(0) == b'#' {
+            self.read_char(1);
+            TokenType::TCppdConcat
+        } else if self.language.digraphs && self.peek_char(0) == b'%' && self.peek_char(1) == b':' {
+            self.read_char(2);
+            TokenType::TCppdConcat
+        } else {
+            TokenType::TCppdStringify
+        }
     }
-}
 
-pub struct RegionalLexer {
-    source: String,
-    pos: usize,
-    regional_aliases: Vec<Alias>,
-    cur_token_type: TokenType,
-    cur_token_str: String,
-    cur_token_pos: usize,
-    pub ignore_stringify: bool,
-    pub skip_backslash_newline: bool,
-    pub preproc_match: bool,
-}
+    /// Reads `/`, or skips comment starting with it and returns None.
+    fn next_slash(&mut self) -> Result<Option<TokenType>, Box<Diagnostic>> {
+        match self.peek_char(1) {
+            b'*' => {
+                let mut offset = 2;
 
-impl RegionalLexer {
-    pub fn new(source: String, regional_aliases: Vec<Alias>) -> Self {
-        Self {
-            source,
-            pos: 0,
-            regional_aliases,
-            cur_token_type: TokenType::TStart,
-            cur_token_str: String::new(),
-            cur_token_pos: 0,
-            ignore_stringify: false,
-            skip_backslash_newline: true,
-            preproc_match: false,
+                // Comment body is searched for `*` in chunks
+                loop {
+                    offset += count_until(&self.source.as_bytes()[self.pos + offset..], b'*');
+
+                    if self.peek_char(offset) != b'*' {
+                        return Err(self.error("Unenclosed comment", self.pos));
+                    }
+
+                    offset += 1;
+
+                    if self.peek_char(offset) == b'/' {
+                        break;
+                    }
+                }
+
+                self.read_char(offset + 1);
+                Ok(None)
+            }
+            b'/' if self.language.line_comments => {
+                if self.language.standard == Standard::C89 {
+                    self.extension("// comments are not allowed in ISO C89", self.pos);
+                }
+
+                let length = count_until(&self.source.as_bytes()[self.pos..], b'\n');
+                self.read_char(length);
+                Ok(None)
+            }
+            _ => {
+                self.read_char(1);
+                Ok(Some(TokenType::TDivide))
+            }
         }
     }
 
-    fn is_whitespace(ch: u8) -> bool {
-        ch == b' ' || ch == b'\t'
+    /// Reads preprocessing number, which includes suffixes and signed
+    /// exponents.
+    fn next_number(&mut self) -> TokenType {
+        let mut length = 1;
+
+        loop {
+            let ch = self.peek_char(length);
+
+            if matches!(ch, b'+' | b'-')
+                && matches!(self.peek_char(length - 1), b'e' | b'E' | b'p' | b'P')
+                || is_alnum(ch)
+                || ch == b'.'
+            {
+                length += 1;
+            } else {
+                break;
+            }
+        }
+
+        self.read_char(length);
+        TokenType::TNumeric
     }
 
-    fn is_newline(ch: u8) -> bool {
-        ch == b'\n' || ch == b'\r'
+    fn next_identifier(&mut self) -> TokenType {
+        let source = self.source.clone();
+        let dollar = self.language.dollar_identifiers;
+        let length = count_matching(&source.as_bytes()[self.pos..], |word| {
+            identifier_bytes(word, dollar)
+        });
+        let identifier = &source[self.pos..self.pos + length];
+
+        if dollar && identifier.contains('$') {
+            self.extension("'$' in identifier is an extension", self.pos);
+        }
+
+        self.read_char(length);
+
+        match identifier {
+            "if" => TokenType::TIf,
+            "while" => TokenType::TWhile,
+            "for" => TokenType::TFor,
+            "do" => TokenType::TDo,
+            "else" => TokenType::TElse,
+            "return" => TokenType::TReturn,
+            "typedef" => TokenType::TTypedef,
+            "enum" => TokenType::TEnum,
+            "struct" => TokenType::TStruct,
+            "sizeof" => TokenType::TSizeof,
+            "switch" => TokenType::TSwitch,
+            "case" => TokenType::TCase,
+            "break" => TokenType::TBreak,
+            "default" => TokenType::TDefault,
+            "continue" => TokenType::TContinue,
+            _ => TokenType::TIdentifier,
+        }
     }
 
-    fn is_alnum(ch: u8) -> bool {
-        ch >= b'a' && ch <= b'z'
-            || ch >= b'A' && ch <= b'Z'
-            || ch >= b'0' && ch <= b'9'
-            || ch == b'_'
+    /// Reads punctuator starting with `ch`, the longest one is taken.
+    fn next_punctuator(&mut self, ch: u8) -> Result<TokenType, Box<Diagnostic>> {
+        let next = self.peek_char(1);
+        let digraphs = self.language.digraphs;
+
+        let (token_type, length) = match (ch, next) {
+            (b'%', b':') if digraphs => return self.next_hash(2),
+            (b'<', b':') if digraphs => (TokenType::TOpenSquare, 2),
+            (b':', b'>') if digraphs => (TokenType::TCloseSquare, 2),
+            (b'<', b'%') if digraphs => (TokenType::TOpenCurly, 2),
+            (b'%', b'>') if digraphs => (TokenType::TCloseCurly, 2),
+            (b'(', _) => (TokenType::TOpenBracket, 1),
+            (b')', _) => (TokenType::TCloseBracket, 1),
+            (b'{', _) => (TokenType::TOpenCurly, 1),
+            (b'}', _) => (TokenType::TCloseCurly, 1),
+            (b'[', _) => (TokenType::TOpenSquare, 1),
+            (b']', _) => (TokenType::TCloseSquare, 1),
+            (b',', _) => (TokenType::TComma, 1),
+            (b'^', _) => (TokenType::TBitXor, 1),
+            (b'~', _) => (TokenType::TBitNot, 1),
+            (b'*', _) => (TokenType::TAsterisk, 1),
+            (b'&', b'&') => (TokenType::TLogAnd, 2),
+            (b'&', b'=') => (TokenType::TAndeq, 2),
+            (b'&', _) => (TokenType::TAmpersand, 1),
+            (b'|', b'|') => (TokenType::TLogOr, 2),
+            (b'|', _) => (TokenType::TBitOr, 1),
+            (b'<', b'=') => (TokenType::TLe, 2),
+            (b'<', b'<') => (TokenType::TLshift, 2),
+            (b'<', _) => (TokenType::TLt, 1),
+            (b'%', _) => (TokenType::TMod, 1),
+            (b'>', b'=') => (TokenType::TGe, 2),
+            (b'>', b'>') => (TokenType::TRshift, 2),
+            (b'>', _) => (TokenType::TGt, 1),
+            (b'!', b'=') => (TokenType::TNoteq, 2),
+            (b'!', _) => (TokenType::TLogNot, 1),
+            (b'-', b'>') => (TokenType::TArrow, 2),
+            (b'-', b'-') => (TokenType::TDecrement, 2),
+            (b'-', b'=') => (TokenType::TMinuseq, 2),
+            (b'-', _) => (TokenType::TMinus, 1),
+            (b'+', b'+') => (TokenType::TIncrement, 2),
+            (b'+', b'=') => (TokenType::TPluseq, 2),
+            (b'+', _) => (TokenType::TPlus, 1),
+            (b';', _) => (TokenType::TSemicolon, 1),
+            (b'?', _) => (TokenType::TQuestion, 1),
+            (b':', _) => (TokenType::TColon, 1),
+            (b'=', b'=') => (TokenType::TEq, 2),
+            (b'=', _) => (TokenType::TAssign, 1),
+            (b'\\', _) => (TokenType::TBackslash, 1),
+            _ => unreachable!("{} is not a punctuator", ch as char),
+        };
+
+        self.read_char(length);
+        Ok(token_type)
     }
 
-    fn is_digit(ch: u8) -> bool {
-        ch >= b'0' && ch <= b'9'
+    /// Reads directive name following `#` of `length` at line start, then
+    /// returns the directive token spanning from `#` to the name.
+    fn next_directive(&mut self, length: usize) -> Result<TokenType, Box<Diagnostic>> {
+        let hash_pos = self.cur_token_pos;
+        self.read_char(length);
+
+        let skip_newline = std::mem::replace(&mut self.skip_newline, false);
+        let name_type = self.next_token()?;
+        self.skip_newline = skip_newline;
+
+        let directive = match name_type {
+            TokenType::TNewline | TokenType::TEof => {
+                // Leaves newline for directive to consume
+                self.pos = self.cur_token_pos;
+                TokenType::TCppdNull
+            }
+            _ => match &self.source[self.cur_token_pos..self.pos] {
+                "include" => TokenType::TCppdInclude,
+                "embed" => TokenType::TCppdEmbed,
+                "include_next" if self.language.include_next => TokenType::TCppdIncludeNext,
+                "line" => TokenType::TCppdLine,
+                "define" => TokenType::TCppdDefine,
+                "undef" => TokenType::TCppdUndef,
+                "error" => TokenType::TCppdError,
+                "warning" if self.language.warning_directive => TokenType::TCppdWarning,
+                "pragma" => TokenType::TCppdPragma,
+                "if" => TokenType::TCppdIf,
+                "elif" => TokenType::TCppdElif,
+                "ifdef" => TokenType::TCppdIfdef,
+                "ifndef" => TokenType::TCppdIfndef,
+                "else" => TokenType::TCppdElse,
+                "endif" => TokenType::TCppdEndif,
+                _ => TokenType::TCppdNonDirective,
+            },
+        };
+
+        self.cur_token_pos = hash_pos;
+        Ok(directive)
     }
 
-    fn is_hex(ch: u8) -> bool {
-        ch >= b'0' && ch <= b'9'
-            || ch >= b'a' && ch <= b'f'
-            || ch >= b'A' && ch <= b'F'
-            || ch == b'x'
+    /// Returns name of directive token, or empty name for null directive.
+    pub fn directive_name(&self) -> &str {
+        let name_start = self
+            .cur_token_str
+            .trim_end_matches(|ch: char| ch.is_ascii_alphanumeric() || ch == '_')
+            .len();
+
+        &self.cur_token_str[name_start..]
     }
 
-    fn is_numeric(buf: &[u8]) -> bool {
-        let mut hex = false;
-        let size = buf.len();
+    /// Rescans `<` token as header name enclosed by `<` and `>` on the same
+    /// line, then returns the name.
+    pub fn lex_header_name(&mut self) -> Option<String> {
+        if self.tokens.is_some() {
+            return None;
+        }
+
+        let start = self.cur_token_pos + 1;
+        let length = self.source[start..].find(['>', '\n'])?;
 
-        if size > 2 {
-            hex = buf.starts_with(b"0x");
+        if self.source.as_bytes()[start + length] != b'>' {
+            return None;
         }
 
-        for i in 0..size {
-            if hex && !Self::is_hex(buf[i]) {
-                return false;
-            }
-            if !hex && !Self::is_digit(buf[i]) {
-                return false;
+        self.pos = start + length + 1;
+        Some(self.source[start..start + length].to_string())
+    }
+
+    /// Measures string or char literal starting at current position, including
+    /// both delimiters.
+    fn quoted_length(&self, delimiter: u8) -> Result<usize, Box<Diagnostic>> {
+        let mut length = 1;
+
+        loop {
+            match self.peek_char(length) {
+                b'\\' => length += 2,
+                b'\0' | b'\n' if self.skipping => return Ok(length),
+                b'\0' | b'\n' => {
+                    return Err(self.error(
+                        &format!("expected {} here to enclose literal", delimiter as char),
+                        self.pos + length,
+                    ))
+                }
+                ch if ch == delimiter => return Ok(length + 1),
+                _ => length += 1,
             }
         }
+    }
 
-        true
+    /// Peeks type of next token without consuming it.
+    pub fn peek_token(&mut self) -> Result<TokenType, Box<Diagnostic>> {
+        if let Some(tokens) = &self.tokens {
+            return Ok(tokens
+                .get(self.pos)
+                .map_or(TokenType::TEof, |token| token.token_type));
+        }
+
+        let pos = self.pos;
+        let cur_token_str = std::mem::take(&mut self.cur_token_str);
+        let cur_token_pos = self.cur_token_pos;
+        let line_start = self.line_start;
+        let extensions = self.extensions.len();
+        let token_type = self.next_token()?;
+
+        self.extensions.truncate(extensions);
+        self.pos = pos;
+        self.cur_token_str = cur_token_str;
+        self.cur_token_pos = cur_token_pos;
+        self.line_start = line_start;
+        Ok(token_type)
     }
 
-    fn peek_char(&self, offset: usize) -> u8 {
-        if self.pos + offset >= self.source.len() {
-            return b'\0';
+    pub fn lex_token(&mut self) -> Result<(), Box<Diagnostic>> {
+        if let Some(tokens) = &self.tokens {
+            self.cur_token_pos = self.pos;
+
+            let Some(token) = tokens.get(self.pos) else {
+                self.cur_token_type = TokenType::TEof;
+                self.cur_token_str = Spelling::default();
+                self.cur_token_spaced = false;
+                self.cur_token_no_expand = false;
+                return Ok(());
+            };
+
+            self.cur_token_type = token.token_type;
+            self.cur_token_no_expand = token.no_expand;
+            self.cur_token_str.clone_from(&token.token_str);
+            self.cur_token_spaced = if self.pos == 0 {
+                std::mem::take(&mut self.leading_spaced)
+            } else {
+                token.spaced
+            };
+            self.pos += 1;
+            return Ok(());
         }
 
-        self.source.as_bytes()[self.pos + offset]
+        let start_pos = self.pos;
+
+        self.cur_token_type = self.next_token()?;
+        self.cur_token_no_expand = false;
+        self.cur_token_spaced =
+            self.cur_token_pos != start_pos || std::mem::take(&mut self.leading_spaced);
+        self.line_start = self.cur_token_type == TokenType::TNewline;
+        self.cur_token_str = Spelling::new(self.source.clone(), self.cur_token_pos..self.pos);
+        Ok(())
     }
+}
 
-    fn read_char(&mut self, offset: usize) {
-        self.pos += offset;
+/// Byte-by-byte scanner which the table-driven scanner replaced, kept to check
+/// that both produce identical token streams.
+#[cfg(test)]
+impl RegionalLexer {
+    fn is_alnum(ch: u8) -> bool {
+        ch.is_ascii_alphanumeric() || ch == b'_'
     }
 
-    fn skip_whitespaces(&mut self) {
+    fn is_identifier_char(&self, ch: u8) -> bool {
+        Self::is_alnum(ch) || ch == b'$' && self.language.dollar_identifiers
+    }
+
+    fn reference_skip_whitespaces(&mut self) {
         loop {
             let ch = self.peek_char(0);
 
-            if Self::is_whitespace(ch)
-                || self.skip_backslash_newline && Self::is_newline(ch)
-                || self.skip_backslash_newline && ch == b'\\'
-            {
+            if Self::is_whitespace(ch) {
+                self.pos += 1;
+                continue;
+            }
+
+            if self.skip_newline && Self::is_newline(ch) {
                 self.pos += 1;
+                self.line_start = true;
                 continue;
             }
 
@@ -398,39 +2387,31 @@ impl RegionalLexer {
         }
     }
 
-    fn next_token(&mut self) -> TokenType {
-        self.skip_whitespaces();
+    fn reference_next_token(&mut self) -> Result<TokenType, Box<Diagnostic>> {
+        self.reference_skip_whitespaces();
         self.cur_token_pos = self.pos;
-        let start_pos = self.cur_token_pos;
-        let mut ch = self.peek_char(0);
+        let ch = self.peek_char(0);
 
-        if ch == b'#' {
-            let mut length = 1;
+        let digraph_hash = self.language.digraphs && ch == b'%' && self.peek_char(1) == b':';
 
-            while Self::is_alnum(self.peek_char(length)) {
-                length += 1;
+        if ch == b'#' || digraph_hash {
+            let length = if digraph_hash { 2 } else { 1 };
+
+            if self.line_start && self.directives {
+                return self.next_directive(length);
             }
 
-            self.cur_token_str = self.source[self.pos..self.pos + length].to_string();
             self.read_char(length);
 
-            return match self.cur_token_str.as_str() {
-                "#include" => TokenType::TCppdInclude,
-                "#define" => TokenType::TCppdDefine,
-                "#undef" => TokenType::TCppdUndef,
-                "#error" => TokenType::TCppdError,
-                "#if" => TokenType::TCppdIf,
-                "#elif" => TokenType::TCppdElif,
-                "#ifdef" => TokenType::TCppdIfdef,
-                "#else" => TokenType::TCppdElse,
-                "#endif" => TokenType::TCppdEndif,
-                "#" => TokenType::TCppdStringify,
-                _ => error(
-                    &Into::<String>::into(&self.source),
-                    &format!("Unexpected preprocessor directive {}", self.cur_token_str),
-                    start_pos,
-                ),
-            };
+            let digraph_concat =
+                self.language.digraphs && self.peek_char(0) == b'%' && self.peek_char(1) == b':';
+
+            if self.peek_char(0) == b'#' || digraph_concat {
+                self.read_char(if digraph_concat { 2 } else { 1 });
+                return Ok(TokenType::TCppdConcat);
+            }
+
+            return Ok(TokenType::TCppdStringify);
         }
 
         if ch == b'/' {
@@ -448,287 +2429,291 @@ impl RegionalLexer {
                 }
 
                 if !enclosed {
-                    error(
-                        &Into::<String>::into(&self.source),
-                        "Unenclosed comment",
-                        self.pos,
-                    );
+                    return Err(self.error("Unenclosed comment", self.pos));
                 } else {
                     self.read_char(offset + 2);
-                    return self.next_token();
+                    return self.reference_next_token();
+                }
+            } else if self.peek_char(1) == b'/' && self.language.line_comments {
+                if self.language.standard == Standard::C89 {
+                    self.extension("// comments are not allowed in ISO C89", self.pos);
+                }
+
+                let mut offset = 2;
+
+                while self.peek_char(offset) != b'\0' && !Self::is_newline(self.peek_char(offset)) {
+                    offset += 1;
                 }
+
+                self.read_char(offset);
+                return self.reference_next_token();
             } else {
                 self.read_char(1);
-                return TokenType::TDivide;
+                return Ok(TokenType::TDivide);
             }
         }
 
-        if Self::is_digit(ch) {
+        if Self::is_digit(ch) || ch == b'.' && Self::is_digit(self.peek_char(1)) {
             let mut length = 1;
 
-            while Self::is_hex(self.peek_char(length)) {
-                length += 1;
+            // Reads preprocessing number, which includes suffixes and signed
+            // exponents
+            loop {
+                let ch = self.peek_char(length);
+
+                if matches!(ch, b'+' | b'-')
+                    && matches!(self.peek_char(length - 1), b'e' | b'E' | b'p' | b'P')
+                    || Self::is_alnum(ch)
+                    || ch == b'.'
+                {
+                    length += 1;
+                } else {
+                    break;
+                }
             }
 
-            self.cur_token_str = self.source[self.pos..self.pos + length].to_string();
             self.read_char(length);
 
-            return TokenType::TNumeric;
+            return Ok(TokenType::TNumeric);
         }
 
         if ch == b'(' {
             self.read_char(1);
-            self.cur_token_str = "(".to_string();
-            return TokenType::TOpenBracket;
+            return Ok(TokenType::TOpenBracket);
         }
 
         if ch == b')' {
             self.read_char(1);
-            self.cur_token_str = ")".to_string();
-            return TokenType::TCloseBracket;
+            return Ok(TokenType::TCloseBracket);
+        }
+
+        if self.language.digraphs {
+            let digraph = match (ch, self.peek_char(1)) {
+                (b'<', b':') => Some(TokenType::TOpenSquare),
+                (b':', b'>') => Some(TokenType::TCloseSquare),
+                (b'<', b'%') => Some(TokenType::TOpenCurly),
+                (b'%', b'>') => Some(TokenType::TCloseCurly),
+                _ => None,
+            };
+
+            if let Some(token_type) = digraph {
+                self.read_char(2);
+                return Ok(token_type);
+            }
         }
 
         if ch == b'{' {
             self.read_char(1);
-            return TokenType::TOpenCurly;
+            return Ok(TokenType::TOpenCurly);
         }
 
         if ch == b'}' {
             self.read_char(1);
-            return TokenType::TCloseCurly;
+            return Ok(TokenType::TCloseCurly);
         }
 
         if ch == b'[' {
             self.read_char(1);
-            return TokenType::TOpenSquare;
+            return Ok(TokenType::TOpenSquare);
         }
 
         if ch == b']' {
             self.read_char(1);
-            return TokenType::TCloseSquare;
+            return Ok(TokenType::TCloseSquare);
         }
 
         if ch == b',' {
             self.read_char(1);
-            self.cur_token_str = ",".to_string();
-            return TokenType::TComma;
+            return Ok(TokenType::TComma);
         }
 
         if ch == b'^' {
             self.read_char(1);
-            return TokenType::TBitXor;
+            return Ok(TokenType::TBitXor);
         }
 
         if ch == b'~' {
             self.read_char(1);
-            return TokenType::TBitNot;
+            return Ok(TokenType::TBitNot);
         }
 
         if ch == b'"' {
-            let mut length = 1;
-
-            while self.peek_char(length) != b'"' {
-                ch = self.peek_char(length);
-
-                if ch == b'\\' {
-                    length += 2;
-                } else {
-                    length += 1;
-                }
-            }
-
-            self.cur_token_str = self.source[self.pos..self.pos + length].to_string();
-            self.read_char(length + 1);
-
-            return TokenType::TString;
+            self.read_char(self.quoted_length(b'"')?);
+            return Ok(TokenType::TString);
         }
 
         if ch == b'\'' {
-            let length: usize;
-            ch = self.peek_char(1);
-
-            if ch == b'\\' {
-                length = 2;
-            } else {
-                length = 1;
-            }
-
-            if self.peek_char(length + 1) != b'\'' {
-                error(
-                    &Into::<String>::into(&self.source),
-                    "expected \' here to enclose char literal",
-                    self.pos + length,
-                );
-            }
-
-            self.cur_token_str = self.source[self.pos..self.pos + length].to_string();
-            self.read_char(length + 1);
-            return TokenType::TChar;
+            self.read_char(self.quoted_length(b'\'')?);
+            return Ok(TokenType::TChar);
         }
 
         if ch == b'*' {
             self.read_char(1);
-            return TokenType::TAsterisk;
+            return Ok(TokenType::TAsterisk);
         }
 
         if ch == b'&' {
             if self.peek_char(1) == b'&' {
                 self.read_char(2);
-                return TokenType::TLogAnd;
+                return Ok(TokenType::TLogAnd);
             }
 
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TAndeq;
+                return Ok(TokenType::TAndeq);
             }
 
             self.read_char(1);
-            return TokenType::TAmpersand;
+            return Ok(TokenType::TAmpersand);
         }
 
         if ch == b'|' {
             if self.peek_char(1) == b'|' {
                 self.read_char(2);
-                return TokenType::TLogOr;
+                return Ok(TokenType::TLogOr);
             }
 
             if self.peek_char(1) == b'|' {
                 self.read_char(2);
-                return TokenType::TOreq;
+                return Ok(TokenType::TOreq);
             }
 
             self.read_char(1);
-            return TokenType::TBitOr;
+            return Ok(TokenType::TBitOr);
         }
 
         if ch == b'<' {
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TLe;
+                return Ok(TokenType::TLe);
             }
 
             if self.peek_char(1) == b'<' {
                 self.read_char(2);
-                return TokenType::TLshift;
+                return Ok(TokenType::TLshift);
             }
 
             self.read_char(1);
-            return TokenType::TLt;
+            return Ok(TokenType::TLt);
         }
 
         if ch == b'%' {
             self.read_char(1);
-            return TokenType::TMod;
+            return Ok(TokenType::TMod);
         }
 
         if ch == b'>' {
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TGe;
+                return Ok(TokenType::TGe);
             }
 
             if self.peek_char(1) == b'>' {
                 self.read_char(2);
-                return TokenType::TRshift;
+                return Ok(TokenType::TRshift);
             }
 
             self.read_char(1);
-            return TokenType::TGt;
+            return Ok(TokenType::TGt);
         }
 
         if ch == b'!' {
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TNoteq;
+                return Ok(TokenType::TNoteq);
             }
 
             self.read_char(1);
-            return TokenType::TLogNot;
+            return Ok(TokenType::TLogNot);
         }
 
         if ch == b'.' {
             if self.peek_char(1) == b'.' && self.peek_char(2) == b'.' {
                 self.read_char(3);
-                return TokenType::TElipsis;
+                return Ok(TokenType::TElipsis);
             }
 
             self.read_char(1);
-            return TokenType::TDot;
+            return Ok(TokenType::TDot);
         }
 
         if ch == b'-' {
             if self.peek_char(1) == b'>' {
                 self.read_char(2);
-                return TokenType::TArrow;
+                return Ok(TokenType::TArrow);
             }
 
             if self.peek_char(1) == b'-' {
                 self.read_char(2);
-                return TokenType::TDecrement;
+                return Ok(TokenType::TDecrement);
             }
 
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TMinuseq;
+                return Ok(TokenType::TMinuseq);
             }
 
             self.read_char(1);
-            return TokenType::TMinus;
+            return Ok(TokenType::TMinus);
         }
 
         if ch == b'+' {
             if self.peek_char(1) == b'+' {
                 self.read_char(2);
-                return TokenType::TIncrement;
+                return Ok(TokenType::TIncrement);
             }
 
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TPluseq;
+                return Ok(TokenType::TPluseq);
             }
 
             self.read_char(1);
-            self.cur_token_str = "+".to_string();
-            return TokenType::TPlus;
+            return Ok(TokenType::TPlus);
         }
 
         if ch == b';' {
             self.read_char(1);
-            self.cur_token_str = ";".to_string();
-            return TokenType::TSemicolon;
+            return Ok(TokenType::TSemicolon);
         }
 
         if ch == b'?' {
             self.read_char(1);
-            return TokenType::TQuestion;
+            return Ok(TokenType::TQuestion);
         }
 
         if ch == b':' {
             self.read_char(1);
-            return TokenType::TColon;
+            return Ok(TokenType::TColon);
         }
 
         if ch == b'=' {
             if self.peek_char(1) == b'=' {
                 self.read_char(2);
-                return TokenType::TEq;
+                return Ok(TokenType::TEq);
             }
 
             self.read_char(1);
-            return TokenType::TAssign;
+            return Ok(TokenType::TAssign);
         }
 
-        if Self::is_alnum(ch) {
+        if self.is_identifier_char(ch) {
             let mut length = 1;
 
-            while Self::is_alnum(self.peek_char(length)) {
+            while self.is_identifier_char(self.peek_char(length)) {
                 length += 1;
             }
 
-            self.cur_token_str = self.source[self.pos..self.pos + length].to_string();
+            let source = self.source.clone();
+            let identifier = &source[self.pos..self.pos + length];
+
+            if identifier.contains('$') {
+                self.extension("'$' in identifier is an extension", self.pos);
+            }
+
             self.read_char(length);
 
-            return match self.cur_token_str.as_str() {
+            return Ok(match identifier {
                 "if" => TokenType::TIf,
                 "while" => TokenType::TWhile,
                 "for" => TokenType::TFor,
@@ -745,27 +2730,69 @@ impl RegionalLexer {
                 "default" => TokenType::TDefault,
                 "continue" => TokenType::TContinue,
                 _ => TokenType::TIdentifier,
-            };
+            });
         }
 
         if ch == b'\\' {
             self.read_char(1);
-            return TokenType::TBackslash;
+            return Ok(TokenType::TBackslash);
         }
 
         if Self::is_newline(ch) {
             self.read_char(1);
-            return TokenType::TNewline;
+            return Ok(TokenType::TNewline);
         }
 
         if ch == b'\0' {
-            return TokenType::TEof;
+            return Ok(TokenType::TEof);
         }
 
-        unreachable!()
+        let length = self.source[self.pos..]
+            .chars()
+            .next()
+            .map_or(1, char::len_utf8);
+        self.read_char(length);
+        Ok(TokenType::TOther)
     }
 
-    pub fn lex_token(&mut self) {
-        self.cur_token_type = self.next_token();
+    /// Scans tokens of spliced `source` until its end with either scanner, returns
+    /// their types and spans along with reported extensions.
+    pub(crate) fn scan_tokens(
+        source: &str,
+        language: LanguageOptions,
+        skip_newline: bool,
+        reference: bool,
+    ) -> (Vec<(TokenType, Range<usize>)>, Vec<String>) {
+        let mut regional_lexer = Self::new(splice_lines(source).0.into());
+        regional_lexer.language = language;
+        regional_lexer.directives = true;
+        regional_lexer.file = Some(0);
+        regional_lexer.skip_newline = skip_newline;
+        // Unterminated literals are accepted as in skipped groups
+        regional_lexer.skipping = true;
+
+        let mut tokens = vec![];
+
+        loop {
+            let token_type = if reference {
+                regional_lexer.reference_next_token()
+            } else {
+                regional_lexer.next_token()
+            }
+            .unwrap();
+
+            regional_lexer.line_start = token_type == TokenType::TNewline;
+            tokens.push((token_type, regional_lexer.cur_token_pos..regional_lexer.pos));
+
+            if token_type == TokenType::TEof {
+                let extensions = regional_lexer.extensions.iter();
+                break (
+                    tokens,
+                    extensions
+                        .map(|(msg, pos)| format!("{pos}: {msg}"))
+                        .collect(),
+                );
+            }
+        }
     }
 }
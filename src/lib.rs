@@ -1,33 +1,86 @@
-use globals::get_source;
-use parser::Parser;
+//! C preprocessor library.
+//!
+//! [`Preprocessor`] preprocesses one translation unit, returning its output
+//! or the [`Diagnostic`] which stopped it. Pragma handlers registered with
+//! [`Preprocessor::register_pragma_handler`] receive the lexed [`Token`]s of
+//! their pragmas.
+//!
+//! ```
+//! use tangle::{Preprocessor, Severity};
+//!
+//! let mut preprocessor = Preprocessor::new("#warning check\n#error stop\n");
+//! let error = preprocessor.preprocess().unwrap_err();
+//!
+//! assert_eq!(error.to_string(), "2:1: error: #error stop");
+//! assert_eq!(preprocessor.warnings()[0].severity, Severity::Warning);
+//! ```
 
 mod defs;
+pub mod diagnostic;
+pub mod feature;
+pub mod file_system;
 mod globals;
+mod interner;
+pub mod language;
 mod lexer;
-pub mod parser;
+pub mod limits;
+pub mod macros;
+pub mod observer;
+pub mod options;
+mod parser;
+pub mod pragma;
+pub mod preprocessor;
+mod scanner;
+pub mod source;
+pub mod target;
+pub mod trace;
 
-fn main() {
-    let source = get_source();
+pub use diagnostic::{Diagnostic, Severity};
+pub use file_system::{FileSystem, OverlayFileSystem, RealFileSystem};
+pub use lexer::{PresumedLocation, Spelling, Token, TokenType};
 
-    {
-        let mut parser = Parser::new(include_str!("../example.c"));
-        parser.read_global_statements();
-    }
-}
+pub use macros::{MacroInfo, MacroKind};
+pub use observer::PreprocessorObserver;
+pub use options::PreprocessorOptions;
+pub use preprocessor::Preprocessor;
+pub use source::SourceManager;
+pub use trace::ExpansionTrace;
 
 #[cfg(test)]
 mod test {
-    use std::{fs, process::Command};
+    use std::{
+        cell::RefCell,
+        env, fs,
+        path::{Path, PathBuf},
+        process::Command,
+        rc::Rc,
+    };
 
     use test_case::test_case;
 
-    use crate::parser::Parser;
+    use crate::{
+        diagnostic::Diagnostic,
+        feature::Capabilities,
+        file_system::{FileStatus, FileSystem, OverlayFileSystem, RealFileSystem},
+        interner::Interner,
+        language::{LanguageOptions, Standard},
+        lexer::{spell_tokens, Lexer, PresumedLocation, RegionalLexer, Spelling, Token},
+        limits::Limits,
+        macros::MacroKind,
+        observer::{ConditionalDirective, PreprocessorObserver, SourceRange},
+        options::PreprocessorOptions,
+        parser::Parser,
+        pragma::{Pragma, PragmaAction, PragmaContext, PragmaHandler},
+        target::Target,
+        trace::TraceStep,
+        Preprocessor, Severity, SourceManager,
+    };
 
     #[test]
     fn test_cpp_result_eq() {
         let input = include_str!("../example.c");
         let mut parser = Parser::new(input);
-        let parser_output = parser.read_global_statements();
+        let parser_output = parser.preprocess().unwrap();
         let output = Command::new("cpp")
             .arg("example.c")
             .output()
@@ -40,17 +93,28 @@ mod test {
             .collect::<String>()
             .replace(" ", "");
 
-        assert_eq!(parser_output, output);
+        assert_eq!(parser_output.replace([' ', '\n'], ""), output);
     }
 
     #[test_case("alias.c"; "Test alias expansion")]
     #[test_case("macro.c"; "Test macro expansion")]
+    #[test_case("splice.c"; "Test line splicing")]
+    #[test_case("directive.c"; "Test directive recognition")]
+    #[test_case("pragma.c"; "Test pragma pass-through")]
+    #[test_case("include.c"; "Test include and include guards")]
+    #[test_case("line.c"; "Test line directive")]
+    #[test_case("feature.c"; "Test feature-test operators")]
+    #[test_case("push_pop.c"; "Test push_macro and pop_macro")]
+    #[test_case("poison.c"; "Test identifier poisoning")]
+    #[test_case("language.c"; "Test digraphs, dollars and __VA_OPT__")]
+    #[test_case("target.c"; "Test host target profile")]
+    #[test_case("rescan.c"; "Test rescanning of replaced macro names")]
+    #[test_case("arguments.c"; "Test directives within macro arguments")]
+    #[test_case("paste.c"; "Test token pasting")]
     fn test_cpp_result_eq_(file_path: &'static str) {
         let full_file_path = format!("test_suite/{}", file_path);
-        let input = fs::read_to_string(&full_file_path)
-            .expect("Unable to read file");
-        let mut parser = Parser::new(&input);
-        let parser_output = parser.read_global_statements();
+        let mut parser = Parser::from_file(&full_file_path).expect("Unable to read file");
+        let parser_output = parser.preprocess().unwrap();
         let output = Command::new("cpp")
             .arg(full_file_path)
             .output()
@@ -63,6 +127,1211 @@ mod test {
             .collect::<String>()
             .replace(" ", "");
 
-        assert_eq!(parser_output, output);
+        assert_eq!(parser_output.replace([' ', '\n'], ""), output);
+    }
+
+    #[test]
+    fn test_include_search_paths() {
+        let include_paths = [
+            "test_suite/include_next/first",
+            "test_suite/include_next/second",
+            "test_suite/include_next/third",
+        ];
+        let mut parser =
+            Parser::from_file("test_suite/include_next.c").expect("Unable to read file");
+
+        for include_path in include_paths {
+            parser.add_include_path(include_path);
+        }
+
+        let parser_output = parser.preprocess().unwrap();
+        let output = Command::new("cpp")
+            .args(include_paths.map(|include_path| format!("-I{include_path}")))
+            .arg("test_suite/include_next.c")
+            .output()
+            .expect("Failed to execute command");
+        let output = std::str::from_utf8(output.stdout.as_slice())
+            .expect("Failed to convert output to String");
+        let output = output
+            .split("\n")
+            .filter(|line| !line.starts_with("# "))
+            .collect::<String>()
+            .replace(" ", "");
+
+        assert_eq!(parser_output.replace([' ', '\n'], ""), output);
+    }
+
+    #[test]
+    fn test_preprocessor_options() {
+        let args = [
+            "-DANSWER",
+            "-DSIZE=4",
+            "-DTWICE(x)=((x) * 2)",
+            "-DREMOVED",
+            "-UREMOVED",
+            "-DEMPTY=",
+            "-imacros",
+            "test_suite/options/macros.h",
+            "-include",
+            "test_suite/options/prelude.h",
+        ];
+        let options = PreprocessorOptions::new()
+            .define("ANSWER")
+            .define("SIZE=4")
+            .define("TWICE(x)=((x) * 2)")
+            .define("REMOVED")
+            .undefine("REMOVED")
+            .define("EMPTY=")
+            .imacros("test_suite/options/macros.h")
+            .include("test_suite/options/prelude.h");
+        let mut parser = Parser::from_file("test_suite/options.c").expect("Unable to read file");
+        parser.set_options(&options);
+
+        let parser_output = parser.preprocess().unwrap();
+        let output = Command::new("cpp")
+            .args(args)
+            .arg("test_suite/options.c")
+            .output()
+            .expect("Failed to execute command");
+        let output = std::str::from_utf8(output.stdout.as_slice())
+            .expect("Failed to convert output to String");
+        let output = output
+            .split("\n")
+            .filter(|line| !line.starts_with("# "))
+            .collect::<String>()
+            .replace(" ", "");
+
+        assert_eq!(parser_output.replace([' ', '\n'], ""), output);
+    }
+
+    #[test]
+    fn test_forced_include_before_imacros() {
+        let args = [
+            "-include",
+            "test_suite/options/prelude.h",
+            "-imacros",
+            "test_suite/options/macros.h",
+            "-DANSWER",
+            "-DSIZE=4",
+            "-DTWICE(x)=((x) * 2)",
+        ];
+        let options = PreprocessorOptions::new()
+            .include("test_suite/options/prelude.h")
+            .imacros("test_suite/options/macros.h")
+            .define("ANSWER")
+            .define("SIZE=4")
+            .define("TWICE(x)=((x) * 2)");
+        let mut parser = Parser::from_file("test_suite/options.c").expect("Unable to read file");
+        parser.set_options(&options);
+
+        let parser_output = parser.preprocess().unwrap();
+        let output = Command::new("cpp")
+            .args(args)
+            .arg("test_suite/options.c")
+            .output()
+            .expect("Failed to execute command");
+        let output = std::str::from_utf8(output.stdout.as_slice())
+            .expect("Failed to convert output to String");
+        let output = output
+            .split("\n")
+            .filter(|line| !line.starts_with("# "))
+            .collect::<String>()
+            .replace(" ", "");
+
+        assert!(parser_output.contains("prelude_tokens"));
+        assert_eq!(parser_output.replace([' ', '\n'], ""), output);
+    }
+
+    #[test]
+    fn test_embed_directive() {
+        let mut parser = Parser::from_file("test_suite/embed.c").expect("Unable to read file");
+
+        assert_eq!(
+            parser.preprocess().unwrap(),
+            "constunsignedchardata[]={0,1,255,10};\
+             constunsignedcharlimited[]={0x10,0,1, 0};\
+             constunsignedcharexpanded[]={0};\
+             constunsignedcharempty[]={-1};\
+             found;not_found;"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_directives() {
+        let input = fs::read_to_string("test_suite/diagnostic.c").expect("Unable to read file");
+        let mut parser = Parser::new(&input);
+        let parser_output = parser.preprocess().unwrap();
+
+        assert_eq!(parser_output, "arm;\n#pragma unknown_pragma\n");
+        assert_eq!(
+            parser.warnings(),
+            [
+                "6:1: #warning building for ARCH \"target\"",
+                "13:1: #pragma message: configured for arm",
+                "14:3: #pragma message: indented",
+                "16:1: #warning it's only a warning",
+            ]
+        );
+
+        let mut parser = Parser::new("#error ARCH not set, it's 'bad\n");
+        assert_eq!(
+            parser.preprocess().unwrap_err().to_string(),
+            "1:1: error: #error ARCH not set, it's 'bad"
+        );
+    }
+
+    #[test]
+    fn test_line_directive_remaps_locations() {
+        let input = "#line 10 \"gen.y\"\n\
+                     #warning here\n\
+                     __LINE__ __FILE__\n";
+        let mut parser = Parser::new(input);
+        parser.set_linemarkers(true);
+
+        assert_eq!(
+            parser.preprocess().unwrap(),
+            "# 1 \"\"\n# 10 \"gen.y\"\n\n11\"gen.y\""
+        );
+        assert_eq!(parser.warnings(), ["gen.y:10:1: #warning here"]);
+    }
+
+    #[test]
+    fn test_linemarkers_keep_source_lines() {
+        // Skipped groups are replaced by newlines, longer gaps by linemarker
+        let input = format!("a\n#if 0\nx\n#endif\nb c\n{}d\n", "\n".repeat(10));
+        let mut parser = Parser::new(&input);
+        parser.set_linemarkers(true);
+
+        assert_eq!(
+            parser.preprocess().unwrap(),
+            "# 1 \"\"\na\n\n\n\nbc\n# 16 \"\"\nd"
+        );
+    }
+
+    #[test]
+    fn test_language_profiles() {
+        let input = "a // b\n\
+                     #define F(x, ...) x __VA_OPT__(+) __VA_ARGS__\n\
+                     F(1, 2) F(3) $x;\n\
+                     #warning old\n\
+                     #if defined(__STRICT_ANSI__) && !defined(__STDC_VERSION__)\n\
+                     c89;\n\
+                     #endif\n";
+        let mut parser = Parser::new(input);
+        parser.set_language(LanguageOptions::c89().pedantic());
+
+        assert_eq!(parser.preprocess().unwrap(), "a//b1+23$x;c89;");
+        assert_eq!(
+            parser.warnings(),
+            [
+                "2:14: variadic macro is a C99 feature used as an extension in C89",
+                "2:21: __VA_OPT__ is a C23 feature used as an extension in C89",
+                "3:14: '$' in identifier is an extension",
+                "4:1: #warning is a C23 feature used as an extension in C89",
+                "4:1: #warning old",
+            ]
+        );
+
+        let mut parser = Parser::new("a // b\n");
+        parser.set_language(LanguageOptions::gnu(Standard::C89).pedantic());
+
+        assert_eq!(parser.preprocess().unwrap(), "a");
+        assert_eq!(
+            parser.warnings(),
+            ["1:3: // comments are not allowed in ISO C89"]
+        );
+
+        let input = "#if true && !false && __STDC_VERSION__ == 202311L\nc23;\n#endif\n";
+        let mut parser = Parser::new(input);
+        parser.set_language(LanguageOptions::c23());
+
+        assert_eq!(parser.preprocess().unwrap(), "c23;");
+    }
+
+    #[test]
+    fn test_target_profiles() {
+        let input = "#if defined(__arm__) && __ARM_ARCH == 7 && '\\xff' > 0\n\
+                     arm;\n\
+                     #endif\n\
+                     #if __riscv && __riscv_xlen == 32 && !defined(__LP64__)\n\
+                     riscv32;\n\
+                     #endif\n\
+                     #if 0x7fffffff + 1 < 0\n\
+                     intmax32;\n\
+                     #endif\n\
+                     __SIZEOF_POINTER__ __SIZE_MAX__ __INTMAX_MAX__ __WCHAR_MAX__;\n";
+        let outputs = [
+            (
+                Target::armv7(),
+                "arm;40xffffffffU0x7fffffffffffffffLL0xffffffffU;",
+            ),
+            (
+                Target::riscv32(),
+                "riscv32;40xffffffffU0x7fffffffffffffffLL0x7fffffff;",
+            ),
+            (
+                Target {
+                    intmax_width: 32,
+                    ..Target::riscv32()
+                },
+                "riscv32;intmax32;40xffffffffU0x7fffffffL0x7fffffff;",
+            ),
+        ];
+
+        for (target, output) in outputs {
+            let mut parser = Parser::new(input);
+            parser.set_target(target);
+
+            assert_eq!(parser.preprocess().unwrap(), output);
+        }
+    }
+
+    #[test]
+    fn test_capability_profiles() {
+        let input = "#ifdef __has_feature\n\
+                     #if __has_feature(c_alignas) && __has_builtin(__builtin_assume)\n\
+                     clang;\n\
+                     #endif\n\
+                     #endif\n\
+                     #if __has_c_attribute(noreturn) == 202202 && __has_attribute(noreturn) == 1\n\
+                     c23;\n\
+                     #endif\n\
+                     #if __has_builtin(__builtin_custom) == 2 && !__has_attribute(packed)\n\
+                     custom;\n\
+                     #endif\n";
+
+        let mut parser = Parser::new(input);
+        parser.set_capabilities(Capabilities::clang());
+        assert_eq!(parser.preprocess().unwrap(), "clang;c23;");
+
+        let mut capabilities = Capabilities::default();
+        capabilities
+            .builtins
+            .insert("__builtin_custom".to_string(), 2);
+        let mut parser = Parser::new(input);
+        parser.set_capabilities(capabilities);
+        assert_eq!(parser.preprocess().unwrap(), "custom;");
+    }
+
+    #[test]
+    fn test_custom_pragma_handler() {
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl PragmaHandler for Recorder {
+            fn expands_operands(&self) -> bool {
+                true
+            }
+
+            fn handle(
+                &mut self,
+                _: &mut PragmaContext,
+                pragma: &Pragma,
+            ) -> Result<PragmaAction, Diagnostic> {
+                self.0.borrow_mut().push(pragma.spelling());
+                Ok(PragmaAction::Consume)
+            }
+        }
+
+        let input = "#define VALUE 1\n\
+                     #define RECORD(x) _Pragma(#x)\n\
+                     #pragma record VALUE (2)\n\
+                     RECORD(record VALUE);\n";
+        let records = Rc::new(RefCell::new(vec![]));
+        let mut parser = Parser::new(input);
+        parser.register_pragma_handler("record", Recorder(records.clone()));
+
+        assert_eq!(parser.preprocess().unwrap(), ";");
+        assert_eq!(*records.borrow(), ["record 1 (2)", "record 1"]);
+    }
+
+    #[test]
+    fn test_pragma_handler_error() {
+        struct Require;
+
+        impl PragmaHandler for Require {
+            fn handle(
+                &mut self,
+                context: &mut PragmaContext,
+                pragma: &Pragma,
+            ) -> Result<PragmaAction, Diagnostic> {
+                let name = pragma.tokens[1].token_str.as_str();
+
+                if context.is_defined(name) {
+                    context.warning(&format!("{name} is defined"), pragma.pos);
+                    Ok(PragmaAction::Consume)
+                } else {
+                    Err(context.error(&format!("{name} is required"), pragma.pos))
+                }
+            }
+        }
+
+        let mut preprocessor =
+            Preprocessor::new("#define A\n#pragma require A\n#pragma require B\n");
+        preprocessor.register_pragma_handler("require", Require);
+        let error = preprocessor.preprocess().unwrap_err();
+
+        assert_eq!(error.to_string(), "3:1: error: B is required");
+        assert_eq!(
+            preprocessor.warnings()[0].to_string(),
+            "2:1: warning: A is defined"
+        );
+    }
+
+    #[test]
+    fn test_include_skips_guarded_files() {
+        struct Rewrite(Vec<PathBuf>);
+
+        impl PragmaHandler for Rewrite {
+            fn handle(
+                &mut self,
+                _: &mut PragmaContext,
+                _: &Pragma,
+            ) -> Result<PragmaAction, Diagnostic> {
+                for path in &self.0 {
+                    fs::write(path, "reopened;\n").unwrap();
+                }
+
+                Ok(PragmaAction::Consume)
+            }
+        }
+
+        let directory = env::temp_dir().join(format!("tangle-include-{}", std::process::id()));
+        let guarded = directory.join("guarded.h");
+        let once = directory.join("once.h");
+        let main = directory.join("main.c");
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            &guarded,
+            "#ifndef GUARDED_H\n#define GUARDED_H\nguarded;\n#endif\n",
+        )
+        .unwrap();
+        fs::write(&once, "#pragma once\nonce;\n").unwrap();
+        fs::write(
+            &main,
+            "#include \"guarded.h\"\n\
+             #include \"once.h\"\n\
+             #pragma rewrite\n\
+             #include \"guarded.h\"\n\
+             #include \"./once.h\"\n\
+             #undef GUARDED_H\n\
+             #include \"guarded.h\"\n",
+        )
+        .unwrap();
+
+        let mut parser = Parser::from_file(&main).unwrap();
+        parser.register_pragma_handler("rewrite", Rewrite(vec![guarded, once]));
+        let parser_output = parser.preprocess().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(parser_output, "guarded;once;reopened;");
+    }
+
+    #[test]
+    fn test_token_spellings() {
+        let lexer = Lexer::new("");
+        let tokens = lexer
+            .lex_tokens("static int  value = F(1, \"s\");")
+            .unwrap();
+
+        let source: Rc<str> = "int value".into();
+
+        assert_eq!(spell_tokens(&tokens), "static int value = F(1, \"s\");");
+        assert_eq!(tokens[2].token_str, "value");
+        assert_eq!(Spelling::new(source.clone(), 4..9), tokens[2].token_str);
+        assert_eq!(Spelling::new(source, 0..3).as_str(), "int");
+    }
+
+    #[test]
+    fn test_scanner_matches_reference() {
+        const FRAGMENTS: [&str; 40] = [
+            " ",
+            "\t",
+            "\x0b\x0c",
+            "\n",
+            "    \n  ",
+            "#",
+            "%:",
+            "%:%:",
+            "# define",
+            "#include",
+            "ident",
+            "_x1",
+            "$dollar",
+            "a$b",
+            "é",
+            "0x1F",
+            ".5e+3",
+            "1.2.3",
+            "...",
+            "..",
+            "/* comment * / ** */",
+            "/**/",
+            "// line\n",
+            "//",
+            "/",
+            "\"str\\\"ing\"",
+            "'c'",
+            "\"open",
+            "<:",
+            ":>",
+            "<%",
+            "%>",
+            "||=",
+            "&&=",
+            "<<=",
+            "->--",
+            "+++=",
+            "!==",
+            "\\",
+            "\0",
+        ];
+
+        fn collect_sources(path: &Path, sources: &mut Vec<String>) {
+            for entry in fs::read_dir(path).unwrap() {
+                let path = entry.unwrap().path();
+
+                if path.is_dir() {
+                    collect_sources(&path, sources);
+                } else {
+                    sources.push(String::from_utf8_lossy(&fs::read(&path).unwrap()).into_owned());
+                }
+            }
+        }
+
+        let mut sources = vec![include_str!("../example.c").to_string()];
+        collect_sources(Path::new("test_suite"), &mut sources);
+
+        // Random sequences of fragments by linear congruential generator
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+
+        for _ in 0..500 {
+            let mut source = String::new();
+
+            for _ in 0..64 {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                source.push_str(FRAGMENTS[(seed >> 33) as usize % FRAGMENTS.len()]);
+            }
+
+            sources.push(source);
+        }
+
+        let languages = [
+            LanguageOptions::default().pedantic(),
+            LanguageOptions::c89().pedantic(),
+            LanguageOptions {
+                dollar_identifiers: false,
+                ..LanguageOptions::c99()
+            },
+        ];
+
+        for source in &sources {
+            for language in languages {
+                for skip_newline in [false, true] {
+                    assert_eq!(
+                        RegionalLexer::scan_tokens(source, language, skip_newline, false),
+                        RegionalLexer::scan_tokens(source, language, skip_newline, true),
+                        "{source:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_deep_expansions() {
+        // Long runs of empty macros and comments are read iteratively
+        let mut input = "#define EMPTY\n".to_string();
+        input.push_str(&"EMPTY /**/ ".repeat(100000));
+        input.push_str("end;\n");
+
+        assert_eq!(Parser::new(&input).preprocess().unwrap().trim(), "end;");
+
+        // Chain of object-like macros, each nested in the previous expansion
+        let mut input = String::new();
+
+        for i in 0..5000 {
+            input.push_str(&format!("#define CHAIN_{i} CHAIN_{}\n", i + 1));
+        }
+
+        input.push_str("CHAIN_0;\n");
+
+        let mut parser = Parser::new(&input);
+        parser.set_limits(Limits {
+            max_expansion_depth: 5000,
+            ..Limits::default()
+        });
+        assert_eq!(parser.preprocess().unwrap().trim(), "CHAIN_5000;");
+
+        // Invocations nested in arguments within the default depth
+        let input = format!(
+            "#define F(x) x\n{}1{};\n",
+            "F(".repeat(100),
+            ")".repeat(100)
+        );
+
+        assert_eq!(Parser::new(&input).preprocess().unwrap().trim(), "1;");
+
+        // Invocations nested in arguments are kept off the native stack,
+        // which overflowed at this depth in threads of 2 MiB stack
+        let input = format!(
+            "#define F(x) x\n{}1{};\n",
+            "F(".repeat(1000),
+            ")".repeat(1000)
+        );
+        let mut parser = Parser::new(&input);
+        parser.set_limits(Limits {
+            max_expansion_depth: 100000,
+            ..Limits::default()
+        });
+        assert_eq!(parser.preprocess().unwrap().trim(), "1;");
+
+        let mut parser = Parser::new(&input);
+        parser.set_limits(Limits {
+            max_expansion_depth: 500,
+            ..Limits::default()
+        });
+        assert!(parser
+            .preprocess()
+            .unwrap_err()
+            .message
+            .starts_with("Macro expansion too deep"));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        // Recursively parsed constructs are bounded by default within threads
+        // of 2 MiB stack
+        let deep = [
+            format!("#if {}1{}\n#endif\n", "(".repeat(10000), ")".repeat(10000)),
+            format!("#if {}1\n#endif\n", "- ".repeat(10000)),
+            format!(
+                "#if {}1{}\n#endif\n",
+                "1?".repeat(10000),
+                ":1".repeat(10000)
+            ),
+            format!("#if {}1\n#endif\n", "1?1:".repeat(10000)),
+            format!(
+                "#define F(...) {}x{}\n",
+                "__VA_OPT__(".repeat(10000),
+                ")".repeat(10000)
+            ),
+        ];
+        // Deepest nesting within the default limit, each bracket nested in
+        // operators of every priority
+        let operators = "1||1&&1|1^1&1==1<1<<1+1*(";
+        let shallow = format!(
+            "#if {}1{}\nshallow\n#endif\n",
+            operators.repeat(255),
+            ")".repeat(255)
+        );
+
+        std::thread::Builder::new()
+            .stack_size(2 << 20)
+            .spawn(move || {
+                for input in deep {
+                    assert!(Parser::new(&input)
+                        .preprocess()
+                        .unwrap_err()
+                        .message
+                        .starts_with("Nesting too deep"));
+                }
+
+                assert_eq!(
+                    Parser::new(&shallow).preprocess().unwrap().trim(),
+                    "shallow"
+                );
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let input = "#define F(x) x x\n#define G(x) F(F(x))\nG(1 2) a;\n";
+        let mut parser = Parser::new(input);
+
+        // Limits are inclusive, so input reaching them exactly is accepted
+        parser.set_limits(Limits {
+            max_output_tokens: Some(10),
+            max_expansion_tokens: Some(8),
+            max_region_depth: Some(3),
+            ..Limits::untrusted()
+        });
+        assert_eq!(
+            parser.preprocess().unwrap(),
+            Parser::new(input).preprocess().unwrap()
+        );
+
+        // Expansion size is bounded as arguments are read, as they are
+        // expanded, and as they are substituted
+        for (input, location) in [
+            ("#define F(x) 1\nF(a b c d e)\n", "2:11"),
+            ("#define A a b c\n#define F(x) x\nF(A A)\n", "3:6"),
+            ("#define F(x) x x x\nF(a b)\n", "2:6"),
+        ] {
+            let mut parser = Parser::new(input);
+
+            parser.set_limits(Limits {
+                max_expansion_tokens: Some(4),
+                ..Limits::default()
+            });
+            assert_eq!(
+                parser.preprocess().unwrap_err().to_string(),
+                format!("{location}: error: Expansion of macro F exceeds maximum of 4 tokens")
+            );
+        }
+
+        // Object-like macros growing by rescanning are bounded as a whole
+        let mut input = String::new();
+
+        for i in 0..40 {
+            input.push_str(&format!("#define M{i} M{} M{}\n", i + 1, i + 1));
+        }
+
+        input.push_str("int a;\nM0\n");
+
+        let mut parser = Parser::new(&input);
+        parser.set_limits(Limits::untrusted());
+        assert_eq!(
+            parser.preprocess().unwrap_err().to_string(),
+            "42:1: error: Expansion of macro M0 exceeds maximum of 100000 tokens"
+        );
+
+        // Output of `#embed` discarded by `-imacros` is not counted
+        let mut source_manager = SourceManager::new();
+        source_manager.add_file("blob", [0; 100]);
+        source_manager.add_file("macros.h", "#define N 1\n#embed \"blob\"\n");
+        source_manager.add_file("main.c", "int n = N;\n#embed \"blob\" limit(2)\n");
+
+        let mut preprocessor = Preprocessor::with_source_manager(source_manager, "main.c").unwrap();
+        preprocessor.set_options(&PreprocessorOptions::new().imacros("macros.h"));
+        preprocessor.set_limits(Limits {
+            max_output_tokens: Some(8),
+            ..Limits::default()
+        });
+        assert_eq!(preprocessor.preprocess().unwrap(), "int n = 1;0,0");
+
+        let input = include_str!("../example.c");
+        let mut parser = Parser::new(input);
+
+        parser.set_limits(Limits::untrusted());
+        assert_eq!(
+            parser.preprocess().unwrap(),
+            Parser::new(input).preprocess().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let input = "#define F(x) x x\n#warning deep\nF(F(F(1)))\n";
+        let mut parser = Parser::new(input);
+
+        parser.set_limits(Limits {
+            max_expansion_tokens: Some(4),
+            ..Limits::default()
+        });
+
+        let error = parser.preprocess().unwrap_err();
+        assert_eq!(error.severity, Severity::Error);
+        assert_eq!(
+            error.to_string(),
+            "3:7: error: Expansion of macro F exceeds maximum of 4 tokens"
+        );
+        assert_eq!(error.snippet(), "F(F(F(1)))\n      ^");
+        assert_eq!(parser.diagnostics()[0].severity, Severity::Warning);
+        assert_eq!(parser.warnings(), ["2:1: #warning deep"]);
+
+        let mut preprocessor = Preprocessor::new("#define F(a, b) a + b\nF(1)\n");
+        assert_eq!(
+            preprocessor.preprocess().unwrap_err().to_string(),
+            "2:4: error: Macro F requires 2 arguments, but 1 given"
+        );
+
+        let mut preprocessor = Preprocessor::new("#define LOG(a, b, ...) a\nLOG(1)\n");
+        assert_eq!(
+            preprocessor.preprocess().unwrap_err().to_string(),
+            "2:6: error: Macro LOG requires at least 2 arguments, but 1 given"
+        );
+
+        let mut preprocessor = Preprocessor::new("#define F(a) a\nF(\n#include \"x.h\"\n)\n");
+        assert_eq!(
+            preprocessor.preprocess().unwrap_err().to_string(),
+            "3:1: error: #include is not allowed within arguments of macro F"
+        );
+
+        let mut preprocessor = Preprocessor::from_file("test_suite/include.c").unwrap();
+        preprocessor.set_limits(Limits {
+            max_include_depth: 1,
+            ..Limits::default()
+        });
+        assert_eq!(
+            preprocessor.preprocess().unwrap_err().to_string(),
+            "test_suite/include/nested.h:1:21: error: #include nested depth 1 exceeds maximum"
+        );
+    }
+
+    #[test]
+    fn test_token_spacing() {
+        let input = "#define PLUS +\n#define CAT(a, b) a b\n\
+                     int f(void) { return s.x + s->y - 1.5 + PLUS+1 + -PLUS 1; }\n\
+                     CAT(u8, \"x\") CAT(1, .) CAT(., 5) CAT(x, y)(a)\n";
+        let mut preprocessor = Preprocessor::new(input);
+
+        assert_eq!(
+            preprocessor.preprocess().unwrap(),
+            "int f(void) { return s.x + s->y - 1.5 + + +1 + - + 1; } \
+             u8 \"x\" 1 . . 5 x y(a)"
+        );
+    }
+
+    #[test]
+    fn test_source_manager() {
+        let threads = (0..4)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    let mut source_manager = SourceManager::new();
+                    source_manager.add_file(
+                        "src/main.c",
+                        "#include \"config.h\"\n#include \"./config.h\"\n#include <sys/value.h>\n\
+                         #if __has_include(\"missing.h\")\nmissing\n#endif\n\
+                         int data[] = {\n#embed \"../data.bin\"\n};\n",
+                    );
+                    source_manager.add_file("src/config.h", "#pragma once\nint config;\n");
+                    source_manager.add_file("sys/value.h", format!("int value = {i};\n"));
+                    source_manager.add_file("data.bin", [i as u8, 255]);
+
+                    let mut preprocessor =
+                        Preprocessor::with_source_manager(source_manager, "src/main.c").unwrap();
+                    preprocessor.add_include_path(".");
+
+                    (
+                        preprocessor.preprocess().unwrap(),
+                        preprocessor.source_manager().files().count(),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(
+                thread.join().unwrap(),
+                (
+                    format!("int config;int value = {i};int data[] = {{{i},255}};"),
+                    3
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_system() {
+        // Files on disk are invisible to file system of in-memory files only
+        let mut file_system = OverlayFileSystem::new();
+        file_system.add_file("gen/version.h", "#define VERSION 3\n");
+        file_system.add_file("blob", "ab");
+        file_system.add_file(
+            "main.c",
+            "#include \"gen/../gen/version.h\"\n\
+             #if __has_include(\"test_suite/macro.c\") || !__has_embed(\"blob\")\ndisk\n#endif\n\
+             VERSION {\n#embed \"blob\"\n}\n",
+        );
+
+        assert!(file_system.is_directory(Path::new("gen")));
+        assert_eq!(file_system.stat(Path::new("blob")).unwrap().size, 2);
+
+        let source_manager = SourceManager::with_file_system(file_system);
+        let mut preprocessor = Preprocessor::with_source_manager(source_manager, "main.c").unwrap();
+        assert_eq!(preprocessor.preprocess().unwrap(), "3 {97,98}");
+
+        // Files are opened through file system of the embedder
+        struct CountingFileSystem(Rc<RefCell<Vec<PathBuf>>>);
+
+        impl FileSystem for CountingFileSystem {
+            fn open(&self, path: &Path) -> std::io::Result<Box<dyn std::io::Read>> {
+                self.0.borrow_mut().push(path.to_path_buf());
+                RealFileSystem.open(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+                RealFileSystem.canonicalize(path)
+            }
+
+            fn stat(&self, path: &Path) -> std::io::Result<FileStatus> {
+                RealFileSystem.stat(path)
+            }
+        }
+
+        let opened = Rc::new(RefCell::new(vec![]));
+        let source_manager = SourceManager::with_file_system(CountingFileSystem(opened.clone()));
+        let mut parser =
+            Parser::with_source_manager(source_manager, "test_suite/include.c").unwrap();
+        parser.preprocess().unwrap();
+
+        assert_eq!(opened.borrow()[0], Path::new("test_suite/include.c"));
+        assert!(opened
+            .borrow()
+            .contains(&PathBuf::from("test_suite/include/guarded.h")));
+    }
+
+    #[test]
+    fn test_include_opens_guarded_files_once() {
+        struct CountingFileSystem(OverlayFileSystem, Rc<RefCell<Vec<PathBuf>>>);
+
+        impl FileSystem for CountingFileSystem {
+            fn open(&self, path: &Path) -> std::io::Result<Box<dyn std::io::Read>> {
+                self.1.borrow_mut().push(path.to_path_buf());
+                self.0.open(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+                self.0.canonicalize(path)
+            }
+
+            fn stat(&self, path: &Path) -> std::io::Result<FileStatus> {
+                self.0.stat(path)
+            }
+        }
+
+        let mut file_system = OverlayFileSystem::new();
+        file_system.add_file(
+            "guarded.h",
+            "#ifndef GUARDED_H\n#define GUARDED_H\nguarded;\n#endif\n",
+        );
+        file_system.add_file("once.h", "#pragma once\nonce;\n");
+        file_system.add_file(
+            "main.c",
+            "#include \"guarded.h\"\n#include \"once.h\"\n#include \"guarded.h\"\n\
+             #include \"./once.h\"\n#include \"./guarded.h\"\n#include \"once.h\"\n",
+        );
+
+        let opened = Rc::new(RefCell::new(vec![]));
+        let source_manager =
+            SourceManager::with_file_system(CountingFileSystem(file_system, opened.clone()));
+        let mut preprocessor = Preprocessor::with_source_manager(source_manager, "main.c").unwrap();
+
+        assert_eq!(preprocessor.preprocess().unwrap(), "guarded;once;");
+        assert_eq!(
+            *opened.borrow(),
+            [
+                PathBuf::from("main.c"),
+                PathBuf::from("guarded.h"),
+                PathBuf::from("once.h"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_observer() {
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl PreprocessorObserver for Recorder {
+            fn macro_defined(&mut self, name: &str, location: &PresumedLocation) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("define {name} {location}"));
+            }
+
+            fn macro_undefined(&mut self, name: &str, location: &PresumedLocation) {
+                self.0.borrow_mut().push(format!("undef {name} {location}"));
+            }
+
+            fn macro_expanded(
+                &mut self,
+                name: &str,
+                arguments: Option<&[Vec<Token>]>,
+                range: &SourceRange,
+            ) {
+                let arguments = arguments.map(|arguments| {
+                    arguments
+                        .iter()
+                        .map(|argument| spell_tokens(argument))
+                        .collect::<Vec<_>>()
+                });
+
+                self.0.borrow_mut().push(format!(
+                    "expand {name} {arguments:?} {}-{}",
+                    range.start, range.end
+                ));
+            }
+
+            fn file_entered(&mut self, path: &Path) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("enter {}", path.display()));
+            }
+
+            fn file_exited(&mut self, path: &Path) {
+                self.0.borrow_mut().push(format!("exit {}", path.display()));
+            }
+
+            fn include_skipped(&mut self, path: &Path) {
+                self.0.borrow_mut().push(format!("skip {}", path.display()));
+            }
+
+            fn conditional_evaluated(
+                &mut self,
+                directive: ConditionalDirective,
+                location: &PresumedLocation,
+                taken: bool,
+            ) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("{directive:?} {location} {taken}"));
+            }
+
+            fn pragma_seen(&mut self, pragma: &Pragma, location: &PresumedLocation) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("pragma {} {location}", pragma.spelling()));
+            }
+        }
+
+        let mut source_manager = SourceManager::new();
+        source_manager.add_file("once.h", "#pragma once\n#define ONCE 1\n");
+        source_manager.add_file(
+            "main.c",
+            "#include \"once.h\"\n#include \"once.h\"\n\
+             #define ADD(a, b) a + b\n\
+             #if ONCE > 1\n#elif defined(ADD)\nADD(ONCE,\n  (2, 3));\n#else\n#endif\n\
+             #ifndef ADD\n#endif\n#undef ADD\n_Pragma(\"mark\")\n",
+        );
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut preprocessor = Preprocessor::with_source_manager(source_manager, "main.c").unwrap();
+        preprocessor.set_options(&PreprocessorOptions::new().define("X").undefine("X"));
+        preprocessor.add_observer(Recorder(events.clone()));
+
+        assert_eq!(
+            preprocessor.preprocess().unwrap(),
+            "1 + (2, 3);\n#pragma mark\n"
+        );
+        assert_eq!(
+            *events.borrow(),
+            [
+                "define X <command line>:1:9",
+                "undef X <command line>:2:8",
+                "enter once.h",
+                "pragma once once.h:1:1",
+                "define ONCE once.h:2:9",
+                "exit once.h",
+                "skip once.h",
+                "define ADD main.c:3:9",
+                "expand ONCE None main.c:4:5-main.c:4:5",
+                "If main.c:4:1 false",
+                "Elif main.c:5:1 true",
+                "expand ADD Some([\"ONCE\", \"(2, 3)\"]) main.c:6:1-main.c:7:9",
+                "expand ONCE None main.c:7:9-main.c:7:9",
+                "Else main.c:8:1 false",
+                "Ifndef main.c:10:1 false",
+                "undef ADD main.c:12:8",
+                "pragma mark main.c:13:1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expansion_trace() {
+        let mut preprocessor = Preprocessor::new(
+            "#define TWO 2\n#define STR(x) #x\n#define ADD(a, b) a + b\n\
+             #define CALL ADD(TWO, STR(z))\nint x = CALL;\n",
+        );
+        preprocessor.set_tracing(true);
+
+        assert_eq!(preprocessor.preprocess().unwrap(), "int x = 2 + \"z\";");
+
+        let trace = preprocessor.trace().unwrap();
+        assert_eq!(
+            trace.to_text(),
+            "CALL at 5:9\n\
+             \x20 substitution: ADD(TWO, STR(z))\n\
+             \x20 rescan\n\
+             \x20   ADD(TWO, STR(z)) at 5:9\n\
+             \x20     prescan #0: TWO -> 2\n\
+             \x20       TWO at 5:9\n\
+             \x20         substitution: 2\n\
+             \x20         rescan\n\
+             \x20     prescan #1: STR(z) -> \"z\"\n\
+             \x20       STR(z) at 5:9\n\
+             \x20         stringize #0: \"z\"\n\
+             \x20         substitution: \"z\"\n\
+             \x20         rescan\n\
+             \x20     substitution: 2 + \"z\"\n\
+             \x20     rescan\n"
+        );
+
+        let invocations = (0..trace.output().len())
+            .map(|index| {
+                let names = trace
+                    .invocations(index)
+                    .into_iter()
+                    .map(|id| match &trace.node(id).step {
+                        TraceStep::Invocation { name, .. } => name.as_str(),
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+
+                format!("{} {}", trace.output()[index].spelling, names.join(" < "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            invocations,
+            [
+                "int ",
+                "x ",
+                "= ",
+                "2 TWO < ADD < CALL",
+                "+ ADD < CALL",
+                "\"z\" STR < ADD < CALL",
+                "; ",
+            ]
+        );
+        assert!(trace.to_json().starts_with(
+            "{\"invocations\":[{\"id\":0,\"step\":\"invocation\",\"name\":\"CALL\",\
+             \"arguments\":null,\"location\":\"5:9\",\"children\":[{\"id\":1,"
+        ));
+        assert!(trace.to_json().ends_with(
+            "{\"token\":\"\\\"z\\\"\",\"invocations\":[9,3,0]},\
+             {\"token\":\";\",\"invocations\":[]}]}"
+        ));
+    }
+
+    #[test]
+    fn test_paste_trace() {
+        let mut preprocessor =
+            Preprocessor::new("#define CAT(a, b) a ## b\n#define GLUE x ## y\nCAT(x, 1) GLUE\n");
+        preprocessor.set_tracing(true);
+
+        assert_eq!(preprocessor.preprocess().unwrap(), "x1 xy");
+
+        let trace = preprocessor.trace().unwrap();
+        assert_eq!(
+            trace.to_text(),
+            "CAT(x, 1) at 3:1\n\
+             \x20 paste: x ## 1 -> x1\n\
+             \x20 substitution: x1\n\
+             \x20 rescan\n\
+             GLUE at 3:11\n\
+             \x20 paste: x ## y -> xy\n\
+             \x20 substitution: xy\n\
+             \x20 rescan\n"
+        );
+        assert!(trace
+            .to_json()
+            .contains("\"step\":\"paste\",\"left\":\"x\",\"right\":\"1\",\"result\":\"x1\""));
+    }
+
+    #[test]
+    fn test_macro_introspection() {
+        let mut source_manager = SourceManager::new();
+        source_manager.add_file(
+            "main.c",
+            "#define ONE 1\n#define ADD(a, b) a + b\n\
+             #define LOG(format, ...) log(#format __VA_OPT__(,) __VA_ARGS__)\n\
+             #define UNUSED\n#define REMOVED\n#undef REMOVED\n\
+             #ifdef LOG\nADD(ONE, 2)\n#endif\n",
+        );
+
+        let mut preprocessor = Preprocessor::with_source_manager(source_manager, "main.c").unwrap();
+        preprocessor.set_options(&PreprocessorOptions::new().define("COMMAND=2"));
+        preprocessor.preprocess().unwrap();
+
+        let macros = preprocessor.macros();
+        let describe = |name: &str| {
+            let info = macros.iter().find(|info| info.name == name).unwrap();
+            let location = info.location.map(|location| location.to_string());
+
+            (info.kind, info.parameters, info.used, location)
+        };
+
+        assert_eq!(
+            describe("ONE"),
+            (
+                MacroKind::Object,
+                &[][..],
+                true,
+                Some("main.c:1:9".to_string())
+            )
+        );
+        assert_eq!(
+            describe("ADD"),
+            (
+                MacroKind::Function,
+                &["a".to_string(), "b".to_string()][..],
+                true,
+                Some("main.c:2:9".to_string())
+            )
+        );
+        assert_eq!(describe("LOG").0, MacroKind::Variadic);
+        assert!(describe("LOG").2);
+        assert!(!describe("UNUSED").2);
+        assert_eq!(
+            describe("COMMAND").3,
+            Some("<command line>:1:9".to_string())
+        );
+        assert_eq!(describe("__LINE__").0, MacroKind::Builtin);
+        assert_eq!(describe("__STDC_EMBED_FOUND__").3, None);
+        assert!(macros.iter().all(|info| info.name != "REMOVED"));
+        assert!(macros.windows(2).all(|pair| pair[0].name < pair[1].name));
+
+        let directives = macros
+            .iter()
+            .filter(|info| info.location.is_some())
+            .filter_map(|info| info.to_directive())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            directives,
+            [
+                "#define ADD(a,b) a + b",
+                "#define COMMAND 2",
+                "#define LOG(format,...) log(#format __VA_OPT__(,) __VA_ARGS__)",
+                "#define ONE 1",
+                "#define UNUSED",
+            ]
+        );
+        assert_eq!(
+            spell_tokens(
+                &macros
+                    .iter()
+                    .find(|info| info.name == "LOG")
+                    .unwrap()
+                    .replacement
+            ),
+            "log(#format __VA_OPT__(,) __VA_ARGS__)"
+        );
+    }
+
+    #[test]
+    fn test_large_macro_table() {
+        let mut input = String::new();
+
+        for i in 0..20000 {
+            input.push_str(&format!(
+                "#define OBJECT_{i} {i}\n#define FUNCTION_{i}(x) x + {i}\n"
+            ));
+        }
+
+        input.push_str(
+            "#undef OBJECT_1\n#define FUNCTION_2 redefined\n\
+             OBJECT_0 OBJECT_1 OBJECT_19999 FUNCTION_1(OBJECT_3) FUNCTION_2 FUNCTION_19999\n",
+        );
+
+        let mut parser = Parser::new(&input);
+
+        assert_eq!(
+            parser.preprocess().unwrap().replace([' ', '\n'], ""),
+            "0OBJECT_1199993+1redefinedFUNCTION_19999"
+        );
+
+        let mut interner = Interner::new();
+        let symbol = interner.intern("OBJECT");
+
+        assert_eq!(interner.intern("OBJECT"), symbol);
+        assert_eq!(interner.get("OBJECT"), Some(symbol));
+        assert_eq!(interner.get("FUNCTION"), None);
+        assert_eq!(interner.resolve(symbol), "OBJECT");
     }
 }
@@ -0,0 +1,56 @@
+/// Resource limits enforced by the lexer, so that untrusted input such as
+/// macro bombs or deep include chains fails with a diagnostic instead of
+/// exhausting memory or time. `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Tokens written to output, including those of `#embed`
+    pub max_output_tokens: Option<usize>,
+    /// Tokens produced by a single macro invocation, both as substituted and
+    /// as written to output after rescanning, where the output of nested
+    /// invocations counts toward the invocation read from a file. Also bounds
+    /// arguments of function-like macros as read and as fully expanded.
+    pub max_expansion_tokens: Option<usize>,
+    /// Nested regions reading macro expansions or arguments
+    pub max_expansion_depth: usize,
+    /// Regions of files, expansions and arguments on the region stack
+    pub max_region_depth: Option<usize>,
+    /// Files nested by `#include`
+    pub max_include_depth: usize,
+    /// Brackets, unary and conditional operators nested in `#if`
+    /// expressions, and `__VA_OPT__` nested in replacement lists, which are
+    /// parsed recursively
+    pub max_nesting_depth: usize,
+    /// Tokens lexed in total, including those of skipped groups and macro
+    /// expansions
+    pub max_steps: Option<u64>,
+}
+
+impl Default for Limits {
+    /// Limits of GCC, which only bounds nesting.
+    fn default() -> Self {
+        Self {
+            max_output_tokens: None,
+            max_expansion_tokens: None,
+            max_expansion_depth: 256,
+            max_region_depth: None,
+            max_include_depth: 200,
+            max_nesting_depth: 256,
+            max_steps: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Limits suited to small snippets submitted by untrusted users.
+    pub fn untrusted() -> Self {
+        Self {
+            max_output_tokens: Some(1_000_000),
+            max_expansion_tokens: Some(100_000),
+            max_expansion_depth: 128,
+            max_region_depth: Some(256),
+            max_include_depth: 32,
+            max_nesting_depth: 128,
+            max_steps: Some(10_000_000),
+        }
+    }
+}
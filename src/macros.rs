@@ -0,0 +1,98 @@
+use crate::{
+    defs::ReplacementToken,
+    lexer::{spell_tokens, PresumedLocation, Token, TokenType},
+};
+
+/// Kind of macro definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroKind {
+    Object,
+    Function,
+    /// Function-like macro whose last parameter is `...`
+    Variadic,
+    /// Macro whose replacement is computed by the preprocessor, such as
+    /// `__LINE__` or `__has_include`
+    Builtin,
+}
+
+/// Currently defined macro, reported by
+/// [`Preprocessor::macros`](crate::Preprocessor::macros)
+#[derive(Debug, Clone)]
+pub struct MacroInfo<'a> {
+    pub name: &'a str,
+    pub kind: MacroKind,
+    /// Parameters of function-like macro, the variadic one is `__VA_ARGS__`
+    pub parameters: &'a [String],
+    /// Replacement list with parameters spelled by their names, empty for
+    /// builtin macros
+    pub replacement: Vec<Token>,
+    /// Location of the name in `#define`, `None` for predefined and builtin
+    /// macros
+    pub location: Option<&'a PresumedLocation>,
+    /// Whether the macro has been expanded or tested by `defined`, `#ifdef`
+    /// or `#ifndef` since it was defined
+    pub used: bool,
+}
+
+impl MacroInfo<'_> {
+    /// Spells the definition as `#define` directive, `None` for builtin
+    /// macros.
+    pub fn to_directive(&self) -> Option<String> {
+        let parameters = match self.kind {
+            MacroKind::Builtin => return None,
+            MacroKind::Object => String::new(),
+            MacroKind::Function | MacroKind::Variadic => {
+                let mut parameters = self.parameters.to_vec();
+
+                if self.kind == MacroKind::Variadic {
+                    *parameters.last_mut().unwrap() = "...".to_string();
+                }
+
+                format!("({})", parameters.join(","))
+            }
+        };
+        let replacement = spell_tokens(&self.replacement);
+
+        Some(match replacement.is_empty() {
+            true => format!("#define {}{parameters}", self.name),
+            false => format!("#define {}{parameters} {replacement}", self.name),
+        })
+    }
+}
+
+/// Spells replacement list of function-like macro back into tokens.
+pub(crate) fn replacement_tokens(
+    parameters: &[String],
+    replacement: &[ReplacementToken],
+    tokens: &mut Vec<Token>,
+) {
+    let token = |token_type, spelling: &str, spaced| Token {
+        token_type,
+        token_str: spelling.into(),
+        spaced,
+        no_expand: false,
+    };
+
+    for replacement_token in replacement {
+        match replacement_token {
+            ReplacementToken::Token(replaced) => tokens.push(replaced.clone()),
+            ReplacementToken::Parameter { index, spaced }
+            | ReplacementToken::Operand { index, spaced } => {
+                tokens.push(token(TokenType::TIdentifier, &parameters[*index], *spaced));
+            }
+            ReplacementToken::Stringized { index, spaced } => {
+                tokens.push(token(TokenType::TCppdStringify, "#", *spaced));
+                tokens.push(token(TokenType::TIdentifier, &parameters[*index], false));
+            }
+            ReplacementToken::Paste { spaced } => {
+                tokens.push(token(TokenType::TCppdConcat, "##", *spaced));
+            }
+            ReplacementToken::VaOpt { content, spaced } => {
+                tokens.push(token(TokenType::TIdentifier, "__VA_OPT__", *spaced));
+                tokens.push(token(TokenType::TOpenBracket, "(", false));
+                replacement_tokens(parameters, content, tokens);
+                tokens.push(token(TokenType::TCloseBracket, ")", false));
+            }
+        }
+    }
+}
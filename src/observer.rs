@@ -0,0 +1,72 @@
+use std::path::Path;
+
+use crate::{
+    lexer::{PresumedLocation, Token},
+    pragma::Pragma,
+};
+
+/// Conditional directive whose condition is evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionalDirective {
+    If,
+    Ifdef,
+    Ifndef,
+    Elif,
+    Else,
+}
+
+/// Range of source from the first to the last token, tokens of macro
+/// expansions are located at the invocation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceRange {
+    pub start: PresumedLocation,
+    pub end: PresumedLocation,
+}
+
+/// Observer of preprocessing events, registered by
+/// [`Preprocessor::add_observer`](crate::Preprocessor::add_observer). Every
+/// method does nothing by default.
+#[allow(unused_variables)]
+pub trait PreprocessorObserver {
+    /// Macro `name` is defined by `#define` or `-D`, replacing its previous
+    /// definition if any.
+    fn macro_defined(&mut self, name: &str, location: &PresumedLocation) {}
+
+    /// Macro `name` is undefined by `#undef` or `-U`, whether it was defined
+    /// or not.
+    fn macro_undefined(&mut self, name: &str, location: &PresumedLocation) {}
+
+    /// Macro `name` is expanded. Arguments are given as written for
+    /// function-like macros, and `None` for object-like macros.
+    fn macro_expanded(
+        &mut self,
+        name: &str,
+        arguments: Option<&[Vec<Token>]>,
+        range: &SourceRange,
+    ) {
+    }
+
+    /// File at `path` is entered by `#include`.
+    fn file_entered(&mut self, path: &Path) {}
+
+    /// File at `path` entered by `#include` is finished.
+    fn file_exited(&mut self, path: &Path) {}
+
+    /// File at `path` is not entered again because of `#pragma once` or its
+    /// include guard.
+    fn include_skipped(&mut self, path: &Path) {}
+
+    /// Condition of `directive` is evaluated, `taken` is whether its group is
+    /// read. `#elif` following a taken group is not evaluated.
+    fn conditional_evaluated(
+        &mut self,
+        directive: ConditionalDirective,
+        location: &PresumedLocation,
+        taken: bool,
+    ) {
+    }
+
+    /// Pragma of `#pragma` directive or `_Pragma` operator is read, before it
+    /// is dispatched to its handler.
+    fn pragma_seen(&mut self, pragma: &Pragma, location: &PresumedLocation) {}
+}
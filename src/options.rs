@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use crate::lexer::quote;
+
+/// Option given on command line, kept in the order of appearance
+#[derive(Debug, Clone)]
+enum CommandLineOption {
+    /// `-D NAME`, `-D NAME=value` or `-D 'F(x)=x'`
+    Define(String),
+    /// `-U NAME`
+    Undefine(String),
+    /// `-include file`
+    Include(PathBuf),
+    /// `-imacros file`
+    Imacros(PathBuf),
+}
+
+/// Command-line style configuration of the preprocessor, processed before the
+/// main file like cpp does: definitions in the order they are given, then
+/// every `-imacros`, then every `-include`. Their locations are reported as
+/// `<command line>`.
+#[derive(Debug, Clone, Default)]
+pub struct PreprocessorOptions {
+    options: Vec<CommandLineOption>,
+    include_paths: Vec<PathBuf>,
+}
+
+impl PreprocessorOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `-D`, defines `NAME` as 1 unless value is given after `=`. Function
+    /// like macros are defined as `F(x)=x`.
+    pub fn define(mut self, definition: &str) -> Self {
+        self.options
+            .push(CommandLineOption::Define(definition.to_string()));
+        self
+    }
+
+    /// `-U`, undefines `NAME` including macros defined by previous `-D`.
+    pub fn undefine(mut self, name: &str) -> Self {
+        self.options
+            .push(CommandLineOption::Undefine(name.to_string()));
+        self
+    }
+
+    /// `-include`, reads file as if `#include "file"` appeared before the
+    /// main file. The file is searched from working directory first.
+    pub fn include(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.push(CommandLineOption::Include(path.into()));
+        self
+    }
+
+    /// `-imacros`, reads file like `-include` but discards its output, so
+    /// that only its macros are kept.
+    pub fn imacros(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.push(CommandLineOption::Imacros(path.into()));
+        self
+    }
+
+    /// `-I`, adds directory searched by `#include`.
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    pub fn include_paths(&self) -> &[PathBuf] {
+        &self.include_paths
+    }
+
+    /// Directives equivalent to the options, split into segments which are
+    /// read in order. Segments of `-imacros` are flagged to discard output.
+    pub(crate) fn command_line(&self) -> Vec<(String, bool)> {
+        let mut segments: Vec<(String, bool)> = vec![];
+        let rank = |option: &CommandLineOption| match option {
+            CommandLineOption::Define(_) | CommandLineOption::Undefine(_) => 0,
+            CommandLineOption::Imacros(_) => 1,
+            CommandLineOption::Include(_) => 2,
+        };
+        let mut options = self.options.iter().collect::<Vec<_>>();
+        options.sort_by_key(|option| rank(option));
+
+        for option in options {
+            let (directive, discard) = match option {
+                CommandLineOption::Define(definition) => {
+                    // Definition ends at newline as if it ended the directive
+                    let definition = definition.lines().next().unwrap_or("");
+                    let directive = match definition.split_once('=') {
+                        Some((name, value)) => format!("#define {name} {value}"),
+                        None => format!("#define {definition} 1"),
+                    };
+
+                    (directive, false)
+                }
+                CommandLineOption::Undefine(name) => (format!("#undef {name}"), false),
+                CommandLineOption::Include(path) => (
+                    format!("#include {}", quote(&path.display().to_string())),
+                    false,
+                ),
+                CommandLineOption::Imacros(path) => (
+                    format!("#include {}", quote(&path.display().to_string())),
+                    true,
+                ),
+            };
+
+            match segments.last_mut() {
+                Some((source, false)) if !discard => {
+                    source.push_str(&directive);
+                    source.push('\n');
+                }
+                _ => segments.push((directive + "\n", discard)),
+            }
+        }
+
+        segments
+    }
+}
@@ -1,248 +1,2541 @@
+use std::{fmt::Write, io, path::PathBuf, rc::Rc};
+
 use crate::{
-    defs::Alias,
-    globals::error,
-    lexer::{Lexer, TokenType},
+    defs::{Conditional, EmbedParameters, Include, IncludeGuard, ReplacementToken},
+    diagnostic::Diagnostic,
+    feature::{Capabilities, FeatureTest},
+    interner::Symbol,
+    language::{LanguageOptions, Standard},
+    lexer::{quote, spell_tokens, stringify, Lexer, PresumedLocation, Token, TokenType},
+    limits::Limits,
+    macros::MacroInfo,
+    observer::{ConditionalDirective, PreprocessorObserver, SourceRange},
+    options::PreprocessorOptions,
+    pragma::{Pragma, PragmaAction, PragmaHandler},
+    source::SourceManager,
+    target::Target,
+    trace::{ExpansionTrace, NodeId, TraceStep},
 };
 
+/// Integer value of `#if` expression, which has type of either `intmax_t` or
+/// `uintmax_t`
+#[derive(Debug, Clone, Copy)]
+struct Constant {
+    value: u64,
+    unsigned: bool,
+}
+
+impl Constant {
+    fn signed(value: i64) -> Self {
+        Self {
+            value: value as u64,
+            unsigned: false,
+        }
+    }
+
+    fn truth(value: bool) -> Self {
+        Self::signed(value as i64)
+    }
+
+    /// Wraps value into integer type of `width`, signed values are kept sign
+    /// extended to 64 bits.
+    fn wrap(self, width: u32) -> Self {
+        if width >= 64 {
+            return self;
+        }
+
+        let shift = 64 - width;
+        let value = if self.unsigned {
+            self.value << shift >> shift
+        } else {
+            ((self.value << shift) as i64 >> shift) as u64
+        };
+
+        Self { value, ..self }
+    }
+}
+
+/// Whether token starting with `first` may continue token ending with `last`
+/// if they are written adjacent.
+fn pastes(last: u8, first: u8) -> bool {
+    let word = |ch: u8| ch.is_ascii_alphanumeric() || matches!(ch, b'_' | b'$');
+    let operator = |ch: u8| b"!#%&*+-/:<=>^|".contains(&ch);
+
+    match (last, first) {
+        // Preprocessing numbers and ellipses
+        (b'0'..=b'9' | b'.', b'.') | (b'.', b'0'..=b'9') => true,
+        // Identifiers, numbers and encoding prefixes of literals
+        (last, first) if word(last) => word(first) || matches!(first, b'"' | b'\''),
+        (last, first) => operator(last) && operator(first),
+    }
+}
+
 pub struct Parser {
     lexer: Lexer,
+    conditionals: Vec<Conditional>,
+    includes: Vec<Include>,
+    linemarkers: bool,
+    token_spacing: bool,
+    /// Directives of command-line options, flagged if output is discarded
+    command_line: Vec<(String, bool)>,
+    /// Constructs being parsed recursively, see [`Limits::max_nesting_depth`]
+    nesting_depth: usize,
+    /// Presumed file and line which output has reached, tracked while
+    /// linemarkers are enabled
+    output_line: Option<(String, usize)>,
+    /// Whether arguments of function-like macro invocation are being read,
+    /// where directives leave the following token unexpanded
+    reading_arguments: bool,
 }
 
 impl Parser {
     pub fn new(source: &str) -> Self {
+        Self::with_lexer(Lexer::new(source))
+    }
+
+    /// Creates parser of main file at `path`, which is where quoted includes
+    /// are searched from.
+    pub fn from_file(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(Self::with_lexer(Lexer::from_file(path)?))
+    }
+
+    /// Creates parser of main file at `path` loaded by `source_manager`,
+    /// which may hold in-memory files.
+    pub fn with_source_manager(
+        source_manager: SourceManager,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        Ok(Self::with_lexer(Lexer::with_source_manager(
+            source_manager,
+            path,
+        )?))
+    }
+
+    pub fn source_manager(&self) -> &SourceManager {
+        self.lexer.source_manager()
+    }
+
+    fn with_lexer(lexer: Lexer) -> Self {
         Self {
-            lexer: Lexer::new(source),
+            lexer,
+            conditionals: vec![],
+            includes: vec![],
+            linemarkers: false,
+            token_spacing: false,
+            command_line: vec![],
+            nesting_depth: 0,
+            output_line: None,
+            reading_arguments: false,
         }
     }
 
-    pub fn read_global_statements(&mut self) -> String {
+    /// Applies command-line style options, whose definitions and forced
+    /// includes are read before the main file.
+    pub fn set_options(&mut self, options: &PreprocessorOptions) {
+        for path in options.include_paths() {
+            self.lexer.add_include_path(path);
+        }
+
+        self.command_line.extend(options.command_line());
+    }
+
+    /// Enables `# line "file" flags` linemarkers in output, which are written
+    /// when files are entered or left, after `#line` directives and where
+    /// source lines are skipped. Tokens are kept on their source lines.
+    pub fn set_linemarkers(&mut self, enabled: bool) {
+        self.linemarkers = enabled;
+    }
+
+    /// Separates output tokens which were spaced in source, or which would
+    /// otherwise be lexed as one token. Tokens are written adjacent by
+    /// default.
+    pub fn set_token_spacing(&mut self, enabled: bool) {
+        self.token_spacing = enabled;
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.lexer.set_capabilities(capabilities);
+    }
+
+    pub fn add_include_path(&mut self, path: impl Into<PathBuf>) {
+        self.lexer.add_include_path(path);
+    }
+
+    pub fn set_language(&mut self, language: LanguageOptions) {
+        self.lexer.set_language(language);
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.lexer.set_target(target);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.lexer.set_limits(limits);
+    }
+
+    pub fn register_pragma_handler(
+        &mut self,
+        namespace: &str,
+        handler: impl PragmaHandler + 'static,
+    ) {
+        self.lexer.register_pragma_handler(namespace, handler);
+    }
+
+    pub fn add_observer(&mut self, observer: impl PreprocessorObserver + 'static) {
+        self.lexer.add_observer(observer);
+    }
+
+    /// Records macro expansions and the origins of output tokens, which is
+    /// disabled by default.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.lexer.set_tracing(enabled);
+    }
+
+    pub fn trace(&self) -> Option<&ExpansionTrace> {
+        self.lexer.trace()
+    }
+
+    /// Describes every currently defined macro, sorted by name.
+    pub fn macros(&self) -> Vec<MacroInfo<'_>> {
+        self.lexer.macros()
+    }
+
+    /// Warnings reported so far, each preceded by its location.
+    #[cfg(test)]
+    pub fn warnings(&self) -> Vec<String> {
+        self.lexer
+            .warnings()
+            .iter()
+            .map(|warning| format!("{}: {}", warning.location, warning.message))
+            .collect()
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.lexer.warnings()
+    }
+
+    /// Preprocesses the translation unit, returning its output or the error
+    /// which stopped it.
+    pub fn preprocess(&mut self) -> Result<String, Diagnostic> {
+        self.read_translation_unit().map_err(|error| *error)
+    }
+
+    fn read_translation_unit(&mut self) -> Result<String, Box<Diagnostic>> {
         let mut builder = String::new();
-        self.lexer.lex_token(true); // Skip TStart
+        let mut discarded = String::new();
+        self.write_linemarker(&mut builder, None);
+        self.enter_command_line()?;
+        self.lexer.lex_token(true)?; // Skip TStart
+
+        loop {
+            let discard_output = self.discards_output();
+            let builder = if discard_output {
+                discarded.clear();
+                &mut discarded
+            } else {
+                &mut builder
+            };
+
+            if self.lexer.lex_peek(TokenType::TEof) {
+                if self.read_file_end(builder)? {
+                    continue;
+                }
+
+                break;
+            }
+
+            if self.read_preproc_directive(builder)? {
+                continue;
+            }
+
+            self.break_include_guard();
 
-        while self.lexer.current_token_type() != TokenType::TEof {
-            if self.read_preproc_directive() {
-                self.lexer.current_token_type();
+            if self.lexer.lex_peek(TokenType::TIdentifier)
+                && self.lexer.current_token_str() == "_Pragma"
+            {
+                self.read_pragma_operator(builder)?;
                 continue;
-            } else if self.lexer.lex_peek(TokenType::TIdentifier) {
-                // We just assume it's a macro invocation atm
-                self.read_macro_invocation();
-                self.lexer.lex_token(true);
+            } else if self.lexer.lex_peek(TokenType::TIdentifier) && self.read_macro_invocation()? {
+                self.lexer.lex_token(true)?;
                 continue;
             } else {
-                builder.push_str(&self.lexer.current_token_str());
+                if !discard_output {
+                    self.lexer.count_output_tokens(1)?;
+                }
+
+                self.write_line_break(builder);
+
+                if self.token_spacing {
+                    self.write_token_spacing(builder);
+                }
+
+                builder.push_str(self.lexer.current_token_str());
+
+                if !discard_output {
+                    self.lexer.trace_output();
+                }
+
                 // println!(
                 //     "{:?}: {:?}",
                 //     self.lexer.current_token_type(),
                 //     self.lexer.current_token_str()
                 // );
-                self.lexer.lex_token(true);
+                self.lexer.lex_token(true)?;
             }
         }
 
-        return builder;
+        Ok(builder)
     }
 
-    #[allow(unused_variables)]
-    pub fn read_macro_invocation(&mut self) {
-        let alias = self.lexer.current_token_str();
-        self.lexer.lex_expect(TokenType::TIdentifier, false);
-        self.lexer.lex_expect(TokenType::TOpenBracket, true);
-        let mut argument_regions = vec![];
-        let mut replacement_builder = String::new();
-        let mut bracket_depth = 0;
+    /// Whether output is discarded, as in files read by `-imacros`. Segments
+    /// of command line are stacked at once, so only the innermost segment
+    /// being read decides.
+    fn discards_output(&self) -> bool {
+        self.includes
+            .iter()
+            .rev()
+            .find(|include| include.command_line)
+            .is_some_and(|include| include.discard_output)
+    }
 
-        while !self.lexer.lex_peek(TokenType::TEof) {
-            let region = self.lexer.regional_source();
-            let region_alias = self.lexer.regional_aliases();
-            let token_type = self.lexer.current_token_type();
-            let token_str = self.lexer.current_token_str();
-            let pos = self.lexer.pos();
+    /// Enters regions of command-line options, stacked so that they are read
+    /// in order before the main file.
+    fn enter_command_line(&mut self) -> Result<(), Box<Diagnostic>> {
+        for (source, discard_output) in std::mem::take(&mut self.command_line).into_iter().rev() {
+            let mut include = Include::new(self.lexer.enter_command_line(&source)?, 0);
+            include.command_line = true;
+            include.discard_output = discard_output;
+            self.includes.push(include);
+        }
 
-            if self.lexer.lex_peek(TokenType::TOpenBracket) {
-                bracket_depth += 1;
-            } else if self.lexer.lex_peek(TokenType::TCloseBracket) {
-                bracket_depth -= 1;
+        Ok(())
+    }
+
+    /// Finishes current file at its end, then resumes the including file.
+    /// Returns false if main file is finished.
+    fn read_file_end(&mut self, builder: &mut String) -> Result<bool, Box<Diagnostic>> {
+        let conditional_depth = self
+            .includes
+            .last()
+            .map_or(0, |include| include.conditional_depth);
+
+        if let Some(conditional) = self.conditionals.get(conditional_depth) {
+            return Err(self
+                .lexer
+                .error("Unterminated conditional directive", conditional.pos));
+        }
+
+        let Some(include) = self.includes.pop() else {
+            return Ok(false);
+        };
+        let guard = match include.guard {
+            IncludeGuard::Closed(guard) => Some(guard),
+            _ => None,
+        };
+
+        self.lexer.set_include_guard(include.file, guard);
+        self.lexer.escape_file_lexer();
+
+        if !include.command_line {
+            self.write_linemarker(builder, Some(2));
+        }
+
+        self.lexer.lex_token(true)?;
+        Ok(true)
+    }
+
+    /// Writes linemarker of the line following current token if linemarkers
+    /// are enabled, `flag` is 1 when entering file and 2 when returning.
+    fn write_linemarker(&mut self, builder: &mut String, flag: Option<u8>) {
+        if !self.linemarkers {
+            return;
+        }
+
+        let location = self.lexer.presumed_location_after();
+        self.write_marker(builder, location, flag);
+    }
+
+    /// Moves output to the presumed line of current token if linemarkers are
+    /// enabled. Like cpp, lines a few lines below are reached by newlines and
+    /// others by a linemarker, so that tokens keep their source lines.
+    fn write_line_break(&mut self, builder: &mut String) {
+        if !self.linemarkers {
+            return;
+        }
+
+        let location = self.lexer.presumed_location();
+
+        match &self.output_line {
+            Some((file, line))
+                if *file == location.file && (*line..=line + 8).contains(&location.line) =>
+            {
+                for _ in *line..location.line {
+                    builder.push('\n');
+                }
+
+                self.output_line = Some((location.file, location.line));
+            }
+            _ => self.write_marker(builder, location, None),
+        }
+    }
+
+    fn write_marker(&mut self, builder: &mut String, location: PresumedLocation, flag: Option<u8>) {
+        // Linemarker occupies its own line
+        if !builder.is_empty() && !builder.ends_with('\n') {
+            builder.push('\n');
+        }
+
+        builder.push_str(&format!("# {} {}", location.line, quote(&location.file)));
+
+        if let Some(flag) = flag {
+            builder.push_str(&format!(" {flag}"));
+        }
+
+        builder.push('\n');
+        self.output_line = Some((location.file, location.line));
+    }
+
+    /// Writes space before current token if it was spaced, or if it would
+    /// be lexed together with the preceding output.
+    fn write_token_spacing(&self, builder: &mut String) {
+        let Some(last) = builder.bytes().last().filter(|last| *last != b'\n') else {
+            return;
+        };
+        let first = self.lexer.current_token_str().as_bytes()[0];
+
+        if self.lexer.current_token_spaced() || pastes(last, first) {
+            builder.push(' ');
+        }
+    }
+
+    /// Marks current file unguarded when tokens or directives appear outside
+    /// of its `#ifndef` group.
+    fn break_include_guard(&mut self) {
+        if let Some(include) = self.includes.last_mut() {
+            if matches!(
+                include.guard,
+                IncludeGuard::Unknown | IncludeGuard::Closed(_)
+            ) {
+                include.guard = IncludeGuard::Unguarded;
+            }
+        }
+    }
+
+    /// Reads function-like macro invocation if current identifier names a macro
+    /// and is followed by an opening bracket, then enters its replacement
+    /// region. Returns false and leaves current token untouched otherwise.
+    ///
+    /// Arguments are fully expanded in isolation before substitution.
+    /// Invocations nested in arguments are kept on an explicit stack instead
+    /// of recursing, and the isolated region of each argument counts toward
+    /// the maximum expansion depth.
+    pub fn read_macro_invocation(&mut self) -> Result<bool, Box<Diagnostic>> {
+        let Some(invocation) = self.read_invocation_arguments()? else {
+            return Ok(false);
+        };
+        let mut pending = vec![invocation];
+
+        loop {
+            let invocation = pending.last_mut().unwrap();
+
+            if invocation.prescan.is_none() {
+                let index = invocation.expanded_arguments.len();
+
+                if index < invocation.arguments.len() {
+                    // Operands of `#` and `##` are never expanded
+                    if expands_argument(&invocation.replacement, index, invocation.arguments.len())
+                    {
+                        self.begin_prescan(invocation)?;
+                    } else {
+                        invocation.expanded_arguments.push(vec![]);
+                        invocation.argument_origins.push(vec![]);
+                    }
+
+                    continue;
+                }
+
+                let invocation = pending.pop().unwrap();
+                self.enter_invocation(invocation)?;
+
+                if pending.is_empty() {
+                    return Ok(true);
+                }
+
+                // Expansion nested in argument is rescanned as part of it
+                self.lexer.lex_token(true)?;
+                continue;
+            }
+
+            if self.lexer.lex_peek(TokenType::TEof) {
+                self.finish_prescan(invocation);
+                continue;
             }
 
             if self.lexer.lex_peek(TokenType::TIdentifier) {
-                self.read_macro_invocation();
-            } else {
-                replacement_builder.push_str(&self.lexer.current_token_str());
-                self.lexer.lex_token(true);
+                if let Some(nested) = self.read_invocation_arguments()? {
+                    pending.push(nested);
+                    continue;
+                }
+            }
+
+            let prescan = invocation.prescan.as_mut().unwrap();
+
+            if prescan.node.is_some() {
+                prescan.origins.push(self.lexer.current_token_origin());
+            }
+
+            prescan.tokens.push(self.lexer.current_token());
+            self.lexer
+                .check_expansion_size(&invocation.alias, prescan.tokens.len())?;
+            self.lexer.lex_token(true)?;
+        }
+    }
+
+    /// Reads arguments of function-like macro invocation if current
+    /// identifier names a macro and is followed by an opening bracket, then
+    /// leaves the closing bracket as current token.
+    fn read_invocation_arguments(&mut self) -> Result<Option<PendingInvocation>, Box<Diagnostic>> {
+        if self.lexer.paint_current_token() {
+            return Ok(None);
+        }
+
+        let Some(symbol) = self.lexer.current_token_symbol() else {
+            return Ok(None);
+        };
+        let spaced = self.lexer.current_token_spaced();
+        let Some(mac) = self.lexer.find_macro(symbol) else {
+            return Ok(None);
+        };
+        let parameter_count = mac.parameters.len();
+        let is_variadic = mac.is_variadic;
+        let replacement = mac.replacement.clone();
+
+        if self.lexer.peek_next_token_type()? != TokenType::TOpenBracket {
+            return Ok(None);
+        }
+        let alias = self.lexer.resolve(symbol).to_string();
+        let start = self.lexer.observed_location(self.lexer.current_token_pos());
+        let parent = self.lexer.region_trace();
+        self.lexer.find_macro(symbol).unwrap().used.set(true);
+        self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+        self.lexer.lex_expect(TokenType::TOpenBracket, false)?;
+        let mut arguments: Vec<Vec<Token>> = vec![];
+        let mut argument = vec![];
+        let mut bracket_depth = 0;
+        // Tokens of every argument, bounded as they are read
+        let mut count = 0;
+
+        while !self.lexer.lex_peek(TokenType::TEof) && !self.lexer.lex_peek(TokenType::TNewline) {
+            if self.read_argument_directive(&alias)? {
+                continue;
             }
 
             if bracket_depth == 0 {
-                if self.lexer.lex_accept(TokenType::TComma, true) {
-                    argument_regions.push(replacement_builder.to_owned());
-                    replacement_builder.clear();
+                if self.lexer.lex_accept(TokenType::TComma, false)? {
+                    arguments.push(std::mem::take(&mut argument));
                     continue;
                 } else if self.lexer.lex_peek(TokenType::TCloseBracket) {
-                    argument_regions.push(replacement_builder.to_owned());
+                    arguments.push(std::mem::take(&mut argument));
                     break;
                 }
             }
+
+            if self.lexer.lex_peek(TokenType::TOpenBracket) {
+                bracket_depth += 1;
+            } else if self.lexer.lex_peek(TokenType::TCloseBracket) {
+                bracket_depth -= 1;
+            }
+
+            // Names of macros being expanded stay unexpanded even if their
+            // regions are escaped before the argument is rescanned
+            self.lexer.paint_current_token();
+            argument.push(self.lexer.current_token());
+            count += 1;
+            self.lexer.check_expansion_size(&alias, count)?;
+            self.lexer.lex_token(false)?;
         }
 
-        if self.lexer.lex_peek(TokenType::TEof) {
-            error(
-                &self.lexer.regional_source(),
-                "Unexpected source end",
+        if self.lexer.lex_peek(TokenType::TEof) || self.lexer.lex_peek(TokenType::TNewline) {
+            return Err(self.lexer.error(
+                &format!("Unterminated argument list invoking macro {alias}"),
                 self.lexer.current_token_pos(),
-            );
+            ));
         }
 
-        let Some(mac) = self.lexer.find_macro(&alias) else {
-            panic!("Macro {alias} is not defined but yet used");
-        };
+        if let Some(start) = start.clone() {
+            let end = self
+                .lexer
+                .observed_location(self.lexer.current_token_pos())
+                .unwrap();
+            let range = SourceRange { start, end };
 
-        let mut aliases = mac.parameters.clone();
+            self.lexer
+                .notify(|observer| observer.macro_expanded(&alias, Some(&arguments), &range));
+        }
 
-        if !mac.is_variadic && aliases.len() != argument_regions.len() {
-            panic!(
-                "Macro {alias} has mismatched parameter list:\nExpects: {:?}, Got: {:?}",
-                aliases.iter().map(|a| &a.alias).collect::<Vec<_>>(),
-                argument_regions
-            );
+        // Empty argument list invokes macro without parameters
+        if parameter_count == 0 && arguments.len() == 1 && arguments[0].is_empty() {
+            arguments.clear();
         }
 
-        if mac.is_variadic && argument_regions.len() < aliases.len() - 1 {
-            panic!(
-                "Macro {alias} takes at least {:?} arguments",
-                aliases.len() - 1
-            );
+        if !is_variadic && parameter_count != arguments.len() {
+            return Err(self.lexer.error(
+                &format!(
+                    "Macro {alias} requires {parameter_count} arguments, but {} given",
+                    arguments.len()
+                ),
+                self.lexer.current_token_pos(),
+            ));
+        }
+
+        if is_variadic && arguments.len() < parameter_count - 1 {
+            return Err(self.lexer.error(
+                &format!(
+                    "Macro {alias} requires at least {} arguments, but {} given",
+                    parameter_count - 1,
+                    arguments.len()
+                ),
+                self.lexer.current_token_pos(),
+            ));
         }
 
-        if mac.is_variadic {
-            if argument_regions.len() == aliases.len() - 1 {
+        if is_variadic {
+            if arguments.len() == parameter_count - 1 {
                 // Appends synthesized parameter
-                argument_regions.push(String::new());
+                arguments.push(vec![]);
             } else {
                 // Concats remaining parameters into single parameter
-                let variadic_argument = argument_regions[aliases.len() - 1..].join(",");
-                argument_regions.truncate(aliases.len());
-                argument_regions[aliases.len() - 1] = variadic_argument;
+                let variadic_arguments = arguments.split_off(parameter_count - 1);
+                let mut variadic_argument = vec![];
+
+                for (i, argument) in variadic_arguments.into_iter().enumerate() {
+                    if i > 0 {
+                        variadic_argument.push(Token {
+                            token_type: TokenType::TComma,
+                            token_str: ",".into(),
+                            spaced: false,
+                            no_expand: false,
+                        });
+                    }
+
+                    variadic_argument.extend(argument);
+                }
+
+                arguments.push(variadic_argument);
+            }
+        }
+
+        let node = self.lexer.trace_step(parent, || TraceStep::Invocation {
+            name: alias.clone(),
+            arguments: Some(
+                arguments
+                    .iter()
+                    .map(|argument| spell_tokens(argument))
+                    .collect(),
+            ),
+            location: start.unwrap(),
+        });
+
+        Ok(Some(PendingInvocation {
+            symbol,
+            alias,
+            spaced,
+            replacement,
+            arguments,
+            expanded_arguments: vec![],
+            argument_origins: vec![],
+            node,
+            prescan: None,
+        }))
+    }
+
+    /// Processes directive on its own line within arguments of macro `alias`
+    /// as cpp does, so that definitions and conditionals take effect before
+    /// the arguments are expanded. Directives producing output or entering
+    /// files are rejected. Returns false if current token is not directive.
+    fn read_argument_directive(&mut self, alias: &str) -> Result<bool, Box<Diagnostic>> {
+        let pos = self.lexer.current_token_pos();
+
+        match self.lexer.current_token_type() {
+            TokenType::TCppdInclude
+            | TokenType::TCppdIncludeNext
+            | TokenType::TCppdEmbed
+            | TokenType::TCppdLine
+            | TokenType::TCppdPragma => {
+                let name = self.lexer.current_regional_lexer().directive_name();
+
+                return Err(self.lexer.error(
+                    &format!("#{name} is not allowed within arguments of macro {alias}"),
+                    pos,
+                ));
             }
+            TokenType::TCppdDefine
+            | TokenType::TCppdUndef
+            | TokenType::TCppdIf
+            | TokenType::TCppdIfdef
+            | TokenType::TCppdIfndef
+            | TokenType::TCppdElif
+            | TokenType::TCppdElse
+            | TokenType::TCppdEndif
+            | TokenType::TCppdError
+            | TokenType::TCppdWarning
+            | TokenType::TCppdNull
+            | TokenType::TCppdNonDirective => {}
+            _ => return Ok(false),
+        }
+
+        let reading_arguments = std::mem::replace(&mut self.reading_arguments, true);
+        let directive = self.read_preproc_directive(&mut String::new());
+        self.reading_arguments = reading_arguments;
+
+        directive
+    }
+
+    /// Enters isolated region of the next argument of `invocation` to be
+    /// fully expanded.
+    fn begin_prescan(&mut self, invocation: &mut PendingInvocation) -> Result<(), Box<Diagnostic>> {
+        let index = invocation.expanded_arguments.len();
+        let argument = &invocation.arguments[index];
+        let node = invocation.node.and_then(|node| {
+            self.lexer.trace_step(Some(node), || TraceStep::Prescan {
+                index,
+                argument: spell_tokens(argument),
+                expanded: String::new(),
+            })
+        });
+        let region_floor = self
+            .lexer
+            .append_isolated_token_lexer(argument[..].into())?;
+
+        if let Some(node) = node {
+            self.lexer
+                .set_region_trace(node, vec![None; argument.len()].into());
         }
 
-        for (i, alias) in aliases.iter_mut().enumerate() {
-            alias.replacement = argument_regions[i].to_owned();
+        self.lexer.lex_token(true)?;
+        invocation.prescan = Some(Prescan {
+            region_floor,
+            node,
+            tokens: vec![],
+            origins: vec![],
+        });
+
+        Ok(())
+    }
+
+    /// Escapes region of the argument being expanded once it is exhausted.
+    fn finish_prescan(&mut self, invocation: &mut PendingInvocation) {
+        let prescan = invocation.prescan.take().unwrap();
+        self.lexer.escape_isolated_lexer(prescan.region_floor);
+
+        if let (Some(node), Some(tracer)) = (prescan.node, self.lexer.tracer_mut()) {
+            if let TraceStep::Prescan { expanded, .. } = tracer.step_mut(node) {
+                *expanded = spell_tokens(&prescan.tokens);
+            }
         }
 
+        invocation.expanded_arguments.push(prescan.tokens);
+        invocation.argument_origins.push(prescan.origins);
+    }
+
+    /// Substitutes fully expanded arguments of `invocation`, then enters its
+    /// replacement region.
+    fn enter_invocation(&mut self, invocation: PendingInvocation) -> Result<(), Box<Diagnostic>> {
+        let mut tokens = vec![];
+        let mut trace = invocation.node.map(|_| SubstitutionTrace {
+            argument_origins: &invocation.argument_origins,
+            origins: vec![],
+            stringized: vec![],
+            pastes: vec![],
+        });
+        substitute(
+            &self.lexer,
+            &invocation.replacement,
+            &invocation.arguments,
+            &invocation.expanded_arguments,
+            &mut tokens,
+            trace.as_mut(),
+        )?;
+
+        self.lexer
+            .check_expansion_size(&invocation.alias, tokens.len())?;
+
+        let region_trace = invocation
+            .node
+            .zip(trace)
+            .map(|(node, trace)| self.trace_substitution(node, &tokens, trace));
+
         self.lexer
-            .append_regional_lexer(mac.source_span.clone(), aliases);
+            .append_expansion_lexer(invocation.symbol, tokens.into())?;
+        self.lexer.current_mut_regional_lexer().leading_spaced = invocation.spaced;
+
+        if let Some((rescan, origins)) = region_trace {
+            self.lexer.set_region_trace(rescan, origins);
+        }
+
+        Ok(())
     }
 
-    pub fn read_preproc_directive(&mut self) -> bool {
-        if self.lexer.lex_accept(TokenType::TCppdDefine, false) {
-            let alias = self.lexer.current_token_str();
-            self.lexer.lex_expect(TokenType::TIdentifier, false);
-            let start_pos = self.lexer.current_token_pos();
-            self.lexer.current_mut_regional_lexer().skip_backslash_newline = false;
-            self.lexer.ignore_stringify = true;
-
-            if self.lexer.lex_accept(TokenType::TOpenBracket, false) {
-                let mut is_variadic = false;
-
-                // Macro
-                let mut parameters = vec![];
-
-                if !self.lexer.lex_accept(TokenType::TCloseBracket, false) {
-                    loop {
-                        let alias = if self.lexer.lex_accept(TokenType::TElipsis, false) {
-                            is_variadic = true;
-                            "__VA_ARGS__".to_string()
-                        } else {
-                            let alias = self.lexer.current_token_str();
-                            self.lexer.lex_expect(TokenType::TIdentifier, false);
-                            alias
-                        };
-
-                        // We don't care the alias region now, it is
-                        // later replaced with actual parsed argument
-                        parameters.push(Alias::new(alias, String::new()));
-
-                        if self.lexer.lex_accept(TokenType::TComma, false) {
-                            continue;
-                        } else {
-                            self.lexer.lex_expect(TokenType::TCloseBracket, false);
-                            break;
-                        }
-                    }
+    /// Traces stringized arguments and substituted `tokens` of `invocation`,
+    /// then its rescan. Returns the rescan node with origins of the tokens.
+    fn trace_substitution(
+        &mut self,
+        invocation: NodeId,
+        tokens: &[Token],
+        trace: SubstitutionTrace,
+    ) -> (NodeId, Rc<[Option<NodeId>]>) {
+        let tracer = self.lexer.tracer_mut().unwrap();
+
+        for (index, result) in trace.stringized {
+            tracer.add(Some(invocation), TraceStep::Stringize { index, result });
+        }
+
+        for paste in trace.pastes {
+            tracer.add(Some(invocation), paste);
+        }
+
+        tracer.add(
+            Some(invocation),
+            TraceStep::Substitution {
+                result: spell_tokens(tokens),
+            },
+        );
+        let rescan = tracer.add(Some(invocation), TraceStep::Rescan);
+        let origins = trace
+            .origins
+            .into_iter()
+            .map(|origin| origin.or(Some(rescan)))
+            .collect();
+
+        (rescan, origins)
+    }
+
+    pub fn read_preproc_directive(
+        &mut self,
+        builder: &mut String,
+    ) -> Result<bool, Box<Diagnostic>> {
+        let pos = self.lexer.current_token_pos();
+        let token_type = self.lexer.current_token_type();
+        let location = self.lexer.observed_location(pos);
+
+        if token_type != TokenType::TCppdIfndef {
+            self.break_include_guard();
+        }
+
+        match token_type {
+            TokenType::TCppdInclude => self.read_include_directive(pos, builder, false)?,
+            TokenType::TCppdIncludeNext => {
+                if self.lexer.language().pedantic {
+                    self.lexer.warning("#include_next is an extension", pos);
+                }
+
+                self.read_include_directive(pos, builder, true)?
+            }
+            TokenType::TCppdLine => self.read_line_directive(pos, builder)?,
+            TokenType::TCppdEmbed => self.read_embed_directive(pos, builder)?,
+            TokenType::TCppdDefine => self.read_define_directive()?,
+            TokenType::TCppdUndef => {
+                self.read_directive_name(false)?;
+                let alias = self.lexer.current_token_str().to_string();
+                let location = self.lexer.observed_location(self.lexer.current_token_pos());
+                self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+                self.lexer.undef(&alias);
+
+                if let Some(location) = location {
+                    self.lexer
+                        .notify(|observer| observer.macro_undefined(&alias, &location));
                 }
 
-                let start_pos = self.lexer.current_token_pos();
+                self.read_directive_end(true)?;
+            }
+            TokenType::TCppdIf => {
+                self.read_directive_name(true)?;
+                let taken = self.read_condition(pos)?;
+                self.notify_conditional(ConditionalDirective::If, location, taken);
+                self.enter_conditional(taken, pos)?;
+            }
+            TokenType::TCppdIfdef | TokenType::TCppdIfndef => {
+                let negated = self.lexer.lex_peek(TokenType::TCppdIfndef);
+                self.read_directive_name(false)?;
+                let alias = self.lexer.current_token_str().to_string();
+                self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+                let taken = self.lexer.test_defined(&alias) != negated;
+                let directive = match negated {
+                    true => ConditionalDirective::Ifndef,
+                    false => ConditionalDirective::Ifdef,
+                };
+                self.notify_conditional(directive, location, taken);
 
-                while !self.lexer.lex_peek(TokenType::TNewline) {
-                    if self.lexer.lex_accept(TokenType::TBackslash, true) {
-                        self.lexer.lex_expect(TokenType::TNewline, true);
-                    } else {
-                        self.lexer.lex_token(false);
+                if negated {
+                    match self.includes.last_mut() {
+                        Some(include) if matches!(include.guard, IncludeGuard::Unknown) => {
+                            include.guard = IncludeGuard::Open(alias);
+                        }
+                        _ => self.break_include_guard(),
                     }
                 }
 
-                let end_pos = self.lexer.current_token_pos();
-                self.lexer.current_mut_regional_lexer().skip_backslash_newline = true;
-                self.lexer.ignore_stringify = false;
-
-                // Validate if __VA_ARGS__ is at the end of parameter list
-                if is_variadic {
-                    let va_args_parameters = parameters
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, parameter)| parameter.alias == "__VA_ARGS__")
-                        .collect::<Vec<_>>();
-
-                    if va_args_parameters.len() != 1 {
-                        error(
-                            &self.lexer.regional_source(),
-                            "__VA_ARGS__ cannot be declared more than once",
-                            start_pos,
-                        );
-                    }
+                self.enter_conditional(taken, pos)?;
+            }
+            TokenType::TCppdElif => {
+                let taken = self.current_conditional("#elif", pos)?.taken;
+                self.reopen_include_guard();
 
-                    let (param_idx, _) = va_args_parameters.first().unwrap();
+                // Conditions after taken branch are never evaluated
+                if taken {
+                    self.read_directive_name(false)?;
+                    self.read_directive_end(false)?;
+                    self.skip_conditional_group()?;
+                } else {
+                    self.read_directive_name(true)?;
+                    let taken = self.read_condition(pos)?;
+                    self.notify_conditional(ConditionalDirective::Elif, location, taken);
+                    self.conditionals.last_mut().unwrap().taken = taken;
+                    self.read_directive_end(taken)?;
 
-                    if *param_idx != parameters.len() - 1 {
-                        error(
-                            &self.lexer.regional_source(),
-                            "__VA_ARGS__ must be defined at the end of macro parameter list",
-                            start_pos,
-                        );
+                    if !taken {
+                        self.skip_conditional_group()?;
                     }
                 }
+            }
+            TokenType::TCppdElse => {
+                let conditional = self.current_conditional("#else", pos)?;
+                let taken = !conditional.taken;
+                conditional.taken = true;
+                conditional.has_else = true;
+                self.notify_conditional(ConditionalDirective::Else, location, taken);
+                self.reopen_include_guard();
+                self.read_directive_name(false)?;
+                self.read_directive_end(taken)?;
 
-                self.lexer.add_macro(
-                    &alias,
-                    parameters,
-                    is_variadic,
-                    self.lexer.global_source()[start_pos..end_pos].to_string(),
-                );
+                if !taken {
+                    self.skip_conditional_group()?;
+                }
+            }
+            TokenType::TCppdEndif => {
+                if self.conditionals.pop().is_none() {
+                    return Err(self.lexer.error("#endif without #if", pos));
+                }
 
-                self.lexer.lex_expect(TokenType::TNewline, true);
-            } else {
-                while !self.lexer.lex_peek(TokenType::TNewline) {
-                    if self.lexer.lex_accept(TokenType::TBackslash, true) {
-                        self.lexer.lex_expect(TokenType::TNewline, true);
-                    } else {
-                        self.lexer.lex_token(false);
+                if let Some(include) = self.includes.last_mut() {
+                    if let IncludeGuard::Open(guard) = &include.guard {
+                        if self.conditionals.len() == include.conditional_depth {
+                            include.guard = IncludeGuard::Closed(guard.clone());
+                        }
                     }
                 }
 
-                let end_pos = self.lexer.current_token_pos();
-                self.lexer.current_mut_regional_lexer().skip_backslash_newline = true;
+                self.read_directive_name(false)?;
+                self.read_directive_end(true)?;
+            }
+            TokenType::TCppdError => {
+                let message = self.read_directive_message()?;
+
+                return Err(self.lexer.error(&format!("#error {message}"), pos));
+            }
+            TokenType::TCppdWarning => {
+                if let Some(msg) = self.lexer.language().extension("#warning", Standard::C23) {
+                    self.lexer.warning(&msg, pos);
+                }
 
-                // Add alias first then resolve next potential alias
-                self.lexer.add_alias(
-                    &alias,
-                    self.lexer.global_source()[start_pos..end_pos].to_string(),
-                );
-                
-                self.lexer.lex_expect(TokenType::TNewline, true);
+                let message = self.read_directive_message()?;
+
+                self.lexer.warning(&format!("#warning {message}"), pos);
+                self.read_directive_end(true)?;
+            }
+            TokenType::TCppdPragma => self.read_pragma_directive(pos, builder)?,
+            TokenType::TCppdNull => {
+                self.read_directive_name(false)?;
+                self.read_directive_end(true)?;
             }
+            TokenType::TCppdNonDirective => {
+                let name = self.lexer.current_regional_lexer().directive_name();
 
-            return true;
+                return Err(self
+                    .lexer
+                    .error(&format!("Invalid preprocessing directive #{name}"), pos));
+            }
+            _ => return Ok(false),
         }
 
-        false
+        Ok(true)
     }
+
+    fn notify_conditional(
+        &mut self,
+        directive: ConditionalDirective,
+        location: Option<PresumedLocation>,
+        taken: bool,
+    ) {
+        if let Some(location) = location {
+            self.lexer
+                .notify(|observer| observer.conditional_evaluated(directive, &location, taken));
+        }
+    }
+
+    /// Marks current file unguarded when `#ifndef` group opening the file has
+    /// other branches.
+    fn reopen_include_guard(&mut self) {
+        if let Some(include) = self.includes.last_mut() {
+            if matches!(include.guard, IncludeGuard::Open(_))
+                && self.conditionals.len() == include.conditional_depth + 1
+            {
+                include.guard = IncludeGuard::Unguarded;
+            }
+        }
+    }
+
+    /// Reads `#include` or `#include_next` if `next` is set, then enters the
+    /// included file.
+    fn read_include_directive(
+        &mut self,
+        pos: usize,
+        builder: &mut String,
+        next: bool,
+    ) -> Result<(), Box<Diagnostic>> {
+        if next && self.lexer.in_main_file() {
+            self.lexer
+                .warning("#include_next in primary source file", pos);
+        }
+
+        self.read_directive_name(true)?;
+        let (name, angled) = self.read_header_name("#include", pos, false)?;
+
+        if !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            self.lexer.warning(
+                "Extra tokens at end of #include directive",
+                self.lexer.current_token_pos(),
+            );
+        }
+
+        self.skip_directive_line(true)?;
+
+        let Some((path, include_dir)) = self.lexer.resolve_include(&name, angled, next) else {
+            return Err(self
+                .lexer
+                .error(&format!("{name}: No such file or directory"), pos));
+        };
+
+        // Included file is read before the line following directive
+        if let Some(file) = self.lexer.enter_file(&path, include_dir, pos)? {
+            self.includes
+                .push(Include::new(file, self.conditionals.len()));
+            self.write_linemarker(builder, Some(1));
+        }
+
+        self.lexer.lex_token(true)?;
+
+        Ok(())
+    }
+
+    /// Reads `"name"` or `<name>` header name operand of `operator`, which
+    /// may also be produced by macro expansion. Token following the name is
+    /// lexed with `aliasing`. Returns the name and whether it is angled.
+    fn read_header_name(
+        &mut self,
+        operator: &str,
+        pos: usize,
+        aliasing: bool,
+    ) -> Result<(String, bool), Box<Diagnostic>> {
+        match self.lexer.current_token_type() {
+            TokenType::TString => {
+                let literal = self.lexer.current_token_str().to_string();
+                self.lexer.lex_token(aliasing)?;
+
+                return Ok((literal[1..literal.len() - 1].to_string(), false));
+            }
+            TokenType::TLt => {
+                if let Some(name) = self.lexer.current_mut_regional_lexer().lex_header_name() {
+                    self.lexer.lex_token(aliasing)?;
+
+                    return Ok((name, true));
+                }
+            }
+            _ => {}
+        }
+
+        // Expanded tokens are read until they complete a header name
+        let mut tokens: Vec<Token> = vec![];
+
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            if self.lexer.lex_peek(TokenType::TIdentifier) && self.read_macro_invocation()? {
+                self.lexer.lex_token(true)?;
+                continue;
+            }
+
+            tokens.push(self.lexer.current_token());
+            self.lexer.lex_token(true)?;
+
+            match tokens[0].token_type {
+                TokenType::TLt if tokens.last().unwrap().token_type != TokenType::TGt => {}
+                _ => break,
+            }
+        }
+
+        parse_header_name(&spell_tokens(&tokens)).ok_or_else(|| {
+            self.lexer.error(
+                &format!("{operator} expects \"FILENAME\" or <FILENAME>"),
+                pos,
+            )
+        })
+    }
+
+    /// Reads opening bracket of operator in `#if`, then returns the operator
+    /// name.
+    fn read_operator_bracket(&mut self, pos: usize) -> Result<String, Box<Diagnostic>> {
+        let operator = self.lexer.current_token_str().to_string();
+        self.lexer.lex_token(false)?;
+
+        if !self.lexer.lex_accept(TokenType::TOpenBracket, true)? {
+            return Err(self
+                .lexer
+                .error(&format!("Missing '(' after {operator}"), pos));
+        }
+
+        Ok(operator)
+    }
+
+    fn read_operator_end(&mut self, operator: &str, pos: usize) -> Result<(), Box<Diagnostic>> {
+        if !self.lexer.lex_peek(TokenType::TCloseBracket) {
+            return Err(self
+                .lexer
+                .error(&format!("Missing ')' after {operator} operand"), pos));
+        }
+
+        self.lexer.lex_token(true)?;
+
+        Ok(())
+    }
+
+    /// Reads `__has_include` operator, or `__has_include_next` if `next` is
+    /// set, which tests whether header name can be included.
+    fn read_has_include(&mut self, next: bool, pos: usize) -> Result<Constant, Box<Diagnostic>> {
+        let operator = self.read_operator_bracket(pos)?;
+        let (name, angled) = self.read_header_name(&operator, pos, true)?;
+        self.read_operator_end(&operator, pos)?;
+
+        Ok(Constant::truth(
+            self.lexer.resolve_include(&name, angled, next).is_some(),
+        ))
+    }
+
+    /// Reads `#embed` directive, which is replaced by comma separated bytes of
+    /// the resource enclosed by its prefix and suffix. Bytes are spelled into
+    /// output directly instead of being tokenized.
+    fn read_embed_directive(
+        &mut self,
+        pos: usize,
+        builder: &mut String,
+    ) -> Result<(), Box<Diagnostic>> {
+        self.read_directive_name(true)?;
+        let (name, angled) = self.read_header_name("#embed", pos, false)?;
+        let parameters = self.read_embed_parameters(true)?;
+
+        if !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            return Err(self.lexer.error(
+                &format!(
+                    "Expected embed parameter, found {}",
+                    self.lexer.current_token_str()
+                ),
+                self.lexer.current_token_pos(),
+            ));
+        }
+
+        self.skip_directive_line(true)?;
+
+        let Some((path, _)) = self.lexer.resolve_include(&name, angled, false) else {
+            return Err(self
+                .lexer
+                .error(&format!("{name}: No such file or directory"), pos));
+        };
+        let data = self
+            .lexer
+            .source_manager()
+            .read_binary(&path, parameters.limit)
+            .map_err(|err| self.lexer.error(&format!("{}: {err}", path.display()), pos))?;
+
+        // Discarded output is neither written nor counted
+        if self.discards_output() {
+            self.lexer.lex_token(true)?;
+            return Ok(());
+        }
+
+        if data.is_empty() {
+            self.lexer.count_output_tokens(parameters.if_empty.len())?;
+            builder.push_str(&spell_tokens(&parameters.if_empty));
+        } else {
+            // Bytes are separated by commas
+            self.lexer.count_output_tokens(
+                parameters.prefix.len() + data.len() * 2 - 1 + parameters.suffix.len(),
+            )?;
+            builder.push_str(&spell_tokens(&parameters.prefix));
+
+            for (idx, byte) in data.iter().enumerate() {
+                if idx != 0 {
+                    builder.push(',');
+                }
+
+                write!(builder, "{byte}").unwrap();
+            }
+
+            builder.push_str(&spell_tokens(&parameters.suffix));
+        }
+
+        self.lexer.lex_token(true)?;
+
+        Ok(())
+    }
+
+    /// Reads `__has_embed` operator, which is `__STDC_EMBED_NOT_FOUND__` if
+    /// resource is missing or parameters are unsupported,
+    /// `__STDC_EMBED_EMPTY__` if it is empty and `__STDC_EMBED_FOUND__`
+    /// otherwise.
+    fn read_has_embed(&mut self, pos: usize) -> Result<Constant, Box<Diagnostic>> {
+        let operator = self.read_operator_bracket(pos)?;
+        let (name, angled) = self.read_header_name(&operator, pos, false)?;
+        let parameters = self.read_embed_parameters(false)?;
+        self.read_operator_end(&operator, pos)?;
+
+        let length = match self.lexer.resolve_include(&name, angled, false) {
+            Some((path, _)) if parameters.supported => self.lexer.source_manager().file_size(&path),
+            _ => return Ok(Constant::signed(0)),
+        };
+
+        Ok(match length {
+            Ok(length) if length.min(parameters.limit.unwrap_or(u64::MAX)) == 0 => {
+                Constant::signed(2)
+            }
+            Ok(_) => Constant::signed(1),
+            Err(_) => Constant::signed(0),
+        })
+    }
+
+    /// Reads embed parameters, unsupported parameters are reported if
+    /// `strict` is set.
+    fn read_embed_parameters(&mut self, strict: bool) -> Result<EmbedParameters, Box<Diagnostic>> {
+        let mut parameters = EmbedParameters {
+            supported: true,
+            ..Default::default()
+        };
+        let mut names = vec![];
+
+        while self.lexer.lex_peek(TokenType::TIdentifier) {
+            let parameter_pos = self.lexer.current_token_pos();
+            let mut name = self.lexer.current_token_str().to_string();
+            self.lexer.lex_token(false)?;
+
+            // Vendor parameters are scoped by `::`
+            while self.lexer.lex_peek(TokenType::TColon) {
+                name.push_str(self.lexer.current_token_str());
+                self.lexer.lex_token(false)?;
+            }
+
+            if name.contains(':') {
+                name.push_str(self.lexer.current_token_str());
+                self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+            }
+
+            let name = match name
+                .strip_prefix("__")
+                .and_then(|name| name.strip_suffix("__"))
+            {
+                Some(stripped) if !stripped.is_empty() => stripped.to_string(),
+                _ => name,
+            };
+
+            if names.contains(&name) {
+                return Err(self
+                    .lexer
+                    .error(&format!("Duplicate embed parameter {name}"), parameter_pos));
+            }
+
+            match name.as_str() {
+                "limit" => {
+                    if !self.lexer.lex_accept(TokenType::TOpenBracket, true)? {
+                        return Err(self
+                            .lexer
+                            .error("Missing '(' after embed parameter limit", parameter_pos));
+                    }
+
+                    let limit = self.read_constant_expr(true)?;
+
+                    if !limit.unsigned && (limit.value as i64) < 0 {
+                        return Err(self
+                            .lexer
+                            .error("Negative embed parameter limit", parameter_pos));
+                    }
+
+                    self.lexer.lex_expect(TokenType::TCloseBracket, false)?;
+                    parameters.limit = Some(limit.value);
+                }
+                "prefix" | "suffix" | "if_empty" => {
+                    let tokens = self.read_balanced_tokens(&name, parameter_pos)?;
+
+                    match name.as_str() {
+                        "prefix" => parameters.prefix = tokens,
+                        "suffix" => parameters.suffix = tokens,
+                        _ => parameters.if_empty = tokens,
+                    }
+                }
+                _ if strict => {
+                    return Err(self.lexer.error(
+                        &format!("Unsupported embed parameter {name}"),
+                        parameter_pos,
+                    ))
+                }
+                _ => {
+                    if self.lexer.lex_peek(TokenType::TOpenBracket) {
+                        self.read_balanced_tokens(&name, parameter_pos)?;
+                    }
+
+                    parameters.supported = false;
+                }
+            }
+
+            names.push(name);
+        }
+
+        Ok(parameters)
+    }
+
+    /// Reads bracketed clause of embed parameter `name`, then returns tokens
+    /// inside the brackets.
+    fn read_balanced_tokens(
+        &mut self,
+        name: &str,
+        pos: usize,
+    ) -> Result<Vec<Token>, Box<Diagnostic>> {
+        if !self.lexer.lex_accept(TokenType::TOpenBracket, false)? {
+            return Err(self
+                .lexer
+                .error(&format!("Missing '(' after embed parameter {name}"), pos));
+        }
+
+        let mut tokens = vec![];
+        let mut depth = 0;
+
+        loop {
+            match self.lexer.current_token_type() {
+                TokenType::TNewline | TokenType::TEof => {
+                    return Err(self
+                        .lexer
+                        .error(&format!("Unterminated embed parameter {name}"), pos))
+                }
+                TokenType::TCloseBracket if depth == 0 => break,
+                TokenType::TOpenBracket => depth += 1,
+                TokenType::TCloseBracket => depth -= 1,
+                _ => {}
+            }
+
+            tokens.push(self.lexer.current_token());
+            self.lexer.lex_token(false)?;
+        }
+
+        self.lexer.lex_token(false)?;
+        Ok(tokens)
+    }
+
+    /// Reads `#line` directive, which numbers the following line and
+    /// optionally renames current file.
+    fn read_line_directive(
+        &mut self,
+        pos: usize,
+        builder: &mut String,
+    ) -> Result<(), Box<Diagnostic>> {
+        self.read_directive_name(true)?;
+        let tokens = self.read_expanded_tokens()?;
+
+        let line = match tokens.first() {
+            Some(token)
+                if token.token_type == TokenType::TNumeric
+                    && token.token_str.bytes().all(|ch| ch.is_ascii_digit()) =>
+            {
+                token.token_str.parse::<usize>().unwrap_or(usize::MAX)
+            }
+            Some(token) => {
+                return Err(self.lexer.error(
+                    &format!(
+                        "\"{}\" after #line is not a positive integer",
+                        token.token_str
+                    ),
+                    pos,
+                ))
+            }
+            None => {
+                return Err(self
+                    .lexer
+                    .error("#line directive requires a line number", pos))
+            }
+        };
+
+        if line > 2147483647 {
+            self.lexer.warning("Line number out of range", pos);
+        }
+
+        let file = match &tokens[1..] {
+            [] => None,
+            [token]
+                if token.token_type == TokenType::TString && token.token_str.starts_with('"') =>
+            {
+                Some(destringize(&token.token_str))
+            }
+            [token, ..] => {
+                return Err(self.lexer.error(
+                    &format!("Invalid filename {} in #line directive", token.token_str),
+                    pos,
+                ))
+            }
+        };
+
+        self.skip_directive_line(true)?;
+        self.lexer
+            .current_mut_regional_lexer()
+            .add_line_directive(line, file);
+        self.write_linemarker(builder, None);
+        self.lexer.lex_token(true)?;
+
+        Ok(())
+    }
+
+    /// Reads macro expanded tokens until the end of directive line.
+    fn read_expanded_tokens(&mut self) -> Result<Vec<Token>, Box<Diagnostic>> {
+        let mut tokens = vec![];
+
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            if self.lexer.lex_peek(TokenType::TIdentifier) && self.read_macro_invocation()? {
+                self.lexer.lex_token(true)?;
+                continue;
+            }
+
+            tokens.push(self.lexer.current_token());
+            self.lexer.lex_token(true)?;
+        }
+
+        Ok(tokens)
+    }
+
+    /// Consumes directive token, the rest of directive line is tokenized with
+    /// significant newline.
+    fn read_directive_name(&mut self, aliasing: bool) -> Result<(), Box<Diagnostic>> {
+        self.lexer.current_mut_regional_lexer().skip_newline = false;
+        self.lexer.lex_token(aliasing)?;
+
+        Ok(())
+    }
+
+    /// Skips remaining tokens of directive line and consumes its newline, the
+    /// following line is tokenized as skipped group if `active` is not set.
+    fn read_directive_end(&mut self, active: bool) -> Result<(), Box<Diagnostic>> {
+        self.skip_directive_line(active)?;
+        self.lexer.lex_token(active && !self.reading_arguments)?;
+
+        Ok(())
+    }
+
+    /// Skips remaining tokens of directive line up to its newline, without
+    /// tokenizing the following line.
+    fn skip_directive_line(&mut self, active: bool) -> Result<(), Box<Diagnostic>> {
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            self.lexer.lex_token(false)?;
+        }
+
+        let regional_lexer = self.lexer.current_mut_regional_lexer();
+        regional_lexer.skip_newline = true;
+        regional_lexer.skipping = !active;
+
+        Ok(())
+    }
+
+    /// Consumes directive token, then spells unexpanded tokens until the end
+    /// of directive line. The message is prose rather than C, so unterminated
+    /// literals such as apostrophes are accepted as in skipped groups.
+    fn read_directive_message(&mut self) -> Result<String, Box<Diagnostic>> {
+        let mut message = String::new();
+        self.lexer.current_mut_regional_lexer().skipping = true;
+        self.read_directive_name(false)?;
+
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            if !message.is_empty() && self.lexer.current_token_spaced() {
+                message.push(' ');
+            }
+
+            message.push_str(self.lexer.current_token_str());
+            self.lexer.lex_token(false)?;
+        }
+
+        self.lexer.current_mut_regional_lexer().skipping = false;
+        Ok(message)
+    }
+
+    fn read_pragma_directive(
+        &mut self,
+        pos: usize,
+        builder: &mut String,
+    ) -> Result<(), Box<Diagnostic>> {
+        let location = self.lexer.observed_location(pos);
+        self.read_directive_name(false)?;
+        let pragma = self.read_pragma(pos)?;
+        self.run_pragma(&pragma, location, builder)?;
+        self.read_directive_end(true)?;
+
+        Ok(())
+    }
+
+    /// Reads `_Pragma` operator, then runs its destringized operand as pragma.
+    fn read_pragma_operator(&mut self, builder: &mut String) -> Result<(), Box<Diagnostic>> {
+        let pos = self.lexer.current_token_pos();
+        let location = self.lexer.observed_location(pos);
+        self.lexer.lex_expect(TokenType::TIdentifier, true)?;
+
+        if !self.lexer.lex_accept(TokenType::TOpenBracket, true)?
+            || !self.lexer.lex_peek(TokenType::TString)
+        {
+            return Err(self.lexer.error(
+                "_Pragma takes a parenthesized string literal",
+                self.lexer.current_token_pos(),
+            ));
+        }
+
+        let literal = self.lexer.current_token_str().to_string();
+        self.lexer.lex_token(true)?;
+
+        if !self.lexer.lex_peek(TokenType::TCloseBracket) {
+            return Err(self.lexer.error(
+                "_Pragma takes a parenthesized string literal",
+                self.lexer.current_token_pos(),
+            ));
+        }
+
+        let region_floor = self
+            .lexer
+            .append_isolated_lexer(destringize(&literal).into())?;
+        self.lexer.lex_token(false)?;
+        let pragma = self.read_pragma(pos)?;
+        self.lexer.escape_isolated_lexer(region_floor);
+
+        self.run_pragma(&pragma, location, builder)?;
+        self.lexer.lex_token(true)?;
+
+        Ok(())
+    }
+
+    /// Reads pragma tokens until the end of directive line or isolated region,
+    /// operands are macro expanded if the handler of namespace asks for.
+    fn read_pragma(&mut self, pos: usize) -> Result<Pragma, Box<Diagnostic>> {
+        let expand = self
+            .lexer
+            .pragma_expands_operands(self.lexer.current_token_str());
+        let mut tokens = vec![];
+        // Unexpanded operands may name poisoned identifiers, e.g. to poison
+        // them again
+        self.lexer.set_poison_checked(expand);
+
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            if expand
+                && !tokens.is_empty()
+                && self.lexer.lex_peek(TokenType::TIdentifier)
+                && self.read_macro_invocation()?
+            {
+                self.lexer.lex_token(true)?;
+                continue;
+            }
+
+            tokens.push(self.lexer.current_token());
+            self.lexer.lex_token(expand)?;
+        }
+
+        self.lexer.set_poison_checked(true);
+        Ok(Pragma { tokens, pos })
+    }
+
+    fn run_pragma(
+        &mut self,
+        pragma: &Pragma,
+        location: Option<PresumedLocation>,
+        builder: &mut String,
+    ) -> Result<(), Box<Diagnostic>> {
+        if let Some(location) = location {
+            self.lexer
+                .notify(|observer| observer.pragma_seen(pragma, &location));
+        }
+
+        if self.lexer.handle_pragma(pragma)? == PragmaAction::Consume {
+            return Ok(());
+        }
+
+        // Passed pragma occupies its own line
+        if !builder.is_empty() && !builder.ends_with('\n') {
+            builder.push('\n');
+        }
+
+        builder.push_str("#pragma ");
+        builder.push_str(&pragma.spelling());
+        builder.push('\n');
+
+        Ok(())
+    }
+
+    fn read_define_directive(&mut self) -> Result<(), Box<Diagnostic>> {
+        self.read_directive_name(false)?;
+        let alias = self.lexer.current_token_str().to_string();
+        let location = self.lexer.location_of(self.lexer.current_token_pos());
+        self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+
+        // Only bracket immediately following macro name opens parameter list
+        if self.lexer.lex_peek(TokenType::TOpenBracket) && !self.lexer.current_token_spaced() {
+            self.lexer.lex_token(false)?;
+            let mut is_variadic = false;
+
+            // Macro
+            let mut parameters = vec![];
+
+            if !self.lexer.lex_accept(TokenType::TCloseBracket, false)? {
+                loop {
+                    let alias = if self.lexer.lex_peek(TokenType::TElipsis) {
+                        let language = self.lexer.language();
+
+                        if let Some(msg) = language.extension("variadic macro", Standard::C99) {
+                            self.lexer.warning(&msg, self.lexer.current_token_pos());
+                        }
+
+                        self.lexer.lex_token(false)?;
+                        is_variadic = true;
+                        "__VA_ARGS__".to_string()
+                    } else {
+                        let alias = self.lexer.current_token_str().to_string();
+                        self.lexer.lex_expect(TokenType::TIdentifier, false)?;
+                        alias
+                    };
+
+                    parameters.push(alias);
+
+                    if self.lexer.lex_accept(TokenType::TComma, false)? {
+                        continue;
+                    } else {
+                        self.lexer.lex_expect(TokenType::TCloseBracket, false)?;
+                        break;
+                    }
+                }
+            }
+
+            let start_pos = self.lexer.current_token_pos();
+
+            // Validate if __VA_ARGS__ is at the end of parameter list
+            if is_variadic {
+                let va_args_parameters = parameters
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, parameter)| *parameter == "__VA_ARGS__")
+                    .collect::<Vec<_>>();
+
+                if va_args_parameters.len() != 1 {
+                    return Err(self
+                        .lexer
+                        .error("__VA_ARGS__ cannot be declared more than once", start_pos));
+                }
+
+                let (param_idx, _) = va_args_parameters.first().unwrap();
+
+                if *param_idx != parameters.len() - 1 {
+                    return Err(self.lexer.error(
+                        "__VA_ARGS__ must be defined at the end of macro parameter list",
+                        start_pos,
+                    ));
+                }
+            }
+
+            let replacement = self.read_replacement_list(&parameters, is_variadic, false)?;
+            self.lexer.add_macro(
+                &alias,
+                parameters,
+                is_variadic,
+                replacement,
+                Some(location.clone()),
+            );
+        } else {
+            let mut replacement: Vec<Token> = vec![];
+
+            while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof)
+            {
+                if self.lexer.lex_peek(TokenType::TCppdConcat)
+                    && (replacement.is_empty()
+                        || matches!(
+                            self.lexer.peek_next_token_type()?,
+                            TokenType::TNewline | TokenType::TEof
+                        ))
+                {
+                    return Err(self.lexer.error(
+                        "'##' cannot appear at either end of a macro expansion",
+                        self.lexer.current_token_pos(),
+                    ));
+                }
+
+                replacement.push(self.lexer.current_token());
+                self.lexer.lex_token(false)?;
+            }
+
+            self.lexer
+                .add_alias(&alias, replacement, Some(location.clone()));
+        }
+
+        self.lexer
+            .notify(|observer| observer.macro_defined(&alias, &location));
+
+        self.read_directive_end(true)?;
+
+        Ok(())
+    }
+
+    /// Reads replacement list of function-like macro until the end of
+    /// directive line, or until the closing bracket of `__VA_OPT__` operand if
+    /// `va_opt` is set. Parameters are resolved into their indices.
+    fn read_replacement_list(
+        &mut self,
+        parameters: &[String],
+        is_variadic: bool,
+        va_opt: bool,
+    ) -> Result<Vec<ReplacementToken>, Box<Diagnostic>> {
+        let mut replacement = vec![];
+        let mut bracket_depth = 0;
+        // Position of `##` whose right operand is not read yet
+        let mut paste_pos = None;
+
+        while !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            let token = self.lexer.current_token();
+            let pos = self.lexer.current_token_pos();
+            let parameter = |token: &Token| {
+                parameters
+                    .iter()
+                    .position(|parameter| token.token_str == parameter.as_str())
+                    .filter(|_| token.token_type == TokenType::TIdentifier)
+            };
+
+            match token.token_type {
+                TokenType::TOpenBracket => bracket_depth += 1,
+                TokenType::TCloseBracket if va_opt && bracket_depth == 0 => break,
+                TokenType::TCloseBracket => bracket_depth -= 1,
+                _ => {}
+            }
+
+            let operand = paste_pos.take().is_some();
+
+            if token.token_type == TokenType::TCppdConcat {
+                let at_start = operand || replacement.is_empty();
+
+                if at_start {
+                    return Err(self
+                        .lexer
+                        .error("'##' cannot appear at either end of a macro expansion", pos));
+                }
+
+                // Parameter operands are replaced by unexpanded arguments
+                if let Some(ReplacementToken::Parameter { index, spaced }) = replacement.last() {
+                    *replacement.last_mut().unwrap() = ReplacementToken::Operand {
+                        index: *index,
+                        spaced: *spaced,
+                    };
+                }
+
+                replacement.push(ReplacementToken::Paste {
+                    spaced: token.spaced,
+                });
+                paste_pos = Some(pos);
+            } else if token.token_type == TokenType::TCppdStringify {
+                self.lexer.lex_token(false)?;
+
+                let Some(index) = parameter(&self.lexer.current_token()) else {
+                    return Err(self
+                        .lexer
+                        .error("'#' is not followed by a macro parameter", pos));
+                };
+
+                replacement.push(ReplacementToken::Stringized {
+                    index,
+                    spaced: token.spaced,
+                });
+            } else if let Some(index) = parameter(&token) {
+                replacement.push(match operand {
+                    true => ReplacementToken::Operand {
+                        index,
+                        spaced: token.spaced,
+                    },
+                    false => ReplacementToken::Parameter {
+                        index,
+                        spaced: token.spaced,
+                    },
+                });
+            } else if is_variadic && self.lexer.language().va_opt && token.token_str == "__VA_OPT__"
+            {
+                if let Some(msg) = self.lexer.language().extension("__VA_OPT__", Standard::C23) {
+                    self.lexer.warning(&msg, pos);
+                }
+
+                self.lexer.lex_token(false)?;
+
+                if !self.lexer.lex_peek(TokenType::TOpenBracket) {
+                    return Err(self
+                        .lexer
+                        .error("__VA_OPT__ must be followed by an opening bracket", pos));
+                }
+
+                self.enter_nesting(pos)?;
+                self.lexer.lex_token(false)?;
+                let content = self.read_replacement_list(parameters, is_variadic, true)?;
+                self.leave_nesting();
+
+                if !self.lexer.lex_peek(TokenType::TCloseBracket) {
+                    return Err(self.lexer.error("Unterminated __VA_OPT__", pos));
+                }
+
+                replacement.push(ReplacementToken::VaOpt {
+                    content,
+                    spaced: token.spaced,
+                });
+            } else {
+                replacement.push(ReplacementToken::Token(token));
+            }
+
+            self.lexer.lex_token(false)?;
+        }
+
+        if let Some(pos) = paste_pos {
+            return Err(self
+                .lexer
+                .error("'##' cannot appear at either end of a macro expansion", pos));
+        }
+
+        Ok(replacement)
+    }
+
+    fn current_conditional(
+        &mut self,
+        directive: &str,
+        pos: usize,
+    ) -> Result<&mut Conditional, Box<Diagnostic>> {
+        match self.conditionals.last_mut() {
+            Some(conditional) if conditional.has_else => {
+                Err(self.lexer.error(&format!("{directive} after #else"), pos))
+            }
+            Some(conditional) => Ok(conditional),
+            None => Err(self.lexer.error(&format!("{directive} without #if"), pos)),
+        }
+    }
+
+    fn enter_conditional(&mut self, taken: bool, pos: usize) -> Result<(), Box<Diagnostic>> {
+        self.conditionals.push(Conditional::new(taken, pos));
+        self.read_directive_end(taken)?;
+
+        if !taken {
+            self.skip_conditional_group()?;
+        }
+
+        Ok(())
+    }
+
+    /// Skips tokens of conditional group until `#elif`, `#else` or `#endif`
+    /// of the same nesting level, directives in between are not processed.
+    fn skip_conditional_group(&mut self) -> Result<(), Box<Diagnostic>> {
+        let mut depth = 0;
+
+        loop {
+            match self.lexer.current_token_type() {
+                TokenType::TEof => break,
+                TokenType::TCppdIf | TokenType::TCppdIfdef | TokenType::TCppdIfndef => depth += 1,
+                TokenType::TCppdElif | TokenType::TCppdElse if depth == 0 => break,
+                TokenType::TCppdEndif if depth == 0 => break,
+                TokenType::TCppdEndif => depth -= 1,
+                _ => {}
+            }
+
+            self.lexer.lex_token(false)?;
+        }
+
+        self.lexer.current_mut_regional_lexer().skipping = false;
+
+        Ok(())
+    }
+
+    /// Evaluates controlling expression of `#if` or `#elif`.
+    fn read_condition(&mut self, pos: usize) -> Result<bool, Box<Diagnostic>> {
+        if self.lexer.lex_peek(TokenType::TNewline) || self.lexer.lex_peek(TokenType::TEof) {
+            return Err(self.lexer.error("#if with no expression", pos));
+        }
+
+        let condition = self.read_constant_expr(true)?;
+
+        if !self.lexer.lex_peek(TokenType::TNewline) && !self.lexer.lex_peek(TokenType::TEof) {
+            return Err(self.lexer.error(
+                &format!(
+                    "Missing binary operator before token {}",
+                    self.lexer.current_token_str()
+                ),
+                self.lexer.current_token_pos(),
+            ));
+        }
+
+        Ok(condition.value != 0)
+    }
+
+    /// Enters construct parsed by recursion, unless such constructs are nested
+    /// deeper than the maximum nesting depth. Paired with
+    /// [`Self::leave_nesting`].
+    fn enter_nesting(&mut self, pos: usize) -> Result<(), Box<Diagnostic>> {
+        let max = self.lexer.limits().max_nesting_depth;
+
+        if self.nesting_depth >= max {
+            return Err(self.lexer.error(
+                &format!("Nesting too deep, exceeding maximum depth of {max}"),
+                pos,
+            ));
+        }
+
+        self.nesting_depth += 1;
+
+        Ok(())
+    }
+
+    fn leave_nesting(&mut self) {
+        self.nesting_depth -= 1;
+    }
+
+    /// Reads conditional expression, arithmetic errors are only reported when
+    /// `evaluated` is set.
+    fn read_constant_expr(&mut self, evaluated: bool) -> Result<Constant, Box<Diagnostic>> {
+        self.enter_nesting(self.lexer.current_token_pos())?;
+        let condition = self.read_binary_expr(1, evaluated)?;
+
+        if !self.lexer.lex_accept(TokenType::TQuestion, true)? {
+            self.leave_nesting();
+            return Ok(condition);
+        }
+
+        let truthy = condition.value != 0;
+        let lhs = self.read_constant_expr(evaluated && truthy)?;
+        self.lexer.lex_expect(TokenType::TColon, true)?;
+        let rhs = self.read_constant_expr(evaluated && !truthy)?;
+        self.leave_nesting();
+
+        Ok(Constant {
+            value: if truthy { lhs.value } else { rhs.value },
+            unsigned: lhs.unsigned || rhs.unsigned,
+        })
+    }
+
+    fn binary_priority(token_type: TokenType) -> Option<u8> {
+        match token_type {
+            TokenType::TLogOr => Some(1),
+            TokenType::TLogAnd => Some(2),
+            TokenType::TBitOr => Some(3),
+            TokenType::TBitXor => Some(4),
+            TokenType::TAmpersand => Some(5),
+            TokenType::TEq | TokenType::TNoteq => Some(6),
+            TokenType::TLt | TokenType::TGt | TokenType::TLe | TokenType::TGe => Some(7),
+            TokenType::TLshift | TokenType::TRshift => Some(8),
+            TokenType::TPlus | TokenType::TMinus => Some(9),
+            TokenType::TAsterisk | TokenType::TDivide | TokenType::TMod => Some(10),
+            _ => None,
+        }
+    }
+
+    fn read_binary_expr(
+        &mut self,
+        min_priority: u8,
+        evaluated: bool,
+    ) -> Result<Constant, Box<Diagnostic>> {
+        let mut lhs = self.read_unary_expr(evaluated)?;
+
+        loop {
+            let op = self.lexer.current_token_type();
+            let pos = self.lexer.current_token_pos();
+
+            let Some(priority) = Self::binary_priority(op) else {
+                break;
+            };
+
+            if priority < min_priority {
+                break;
+            }
+
+            self.lexer.lex_token(true)?;
+
+            // Short-circuited operand is not evaluated
+            let rhs_evaluated = match op {
+                TokenType::TLogOr => evaluated && lhs.value == 0,
+                TokenType::TLogAnd => evaluated && lhs.value != 0,
+                _ => evaluated,
+            };
+            let rhs = self.read_binary_expr(priority + 1, rhs_evaluated)?;
+
+            lhs = self
+                .eval_binary_expr(op, lhs, rhs, evaluated, pos)?
+                .wrap(self.lexer.target().intmax_width);
+        }
+
+        Ok(lhs)
+    }
+
+    fn eval_binary_expr(
+        &mut self,
+        op: TokenType,
+        lhs: Constant,
+        rhs: Constant,
+        evaluated: bool,
+        pos: usize,
+    ) -> Result<Constant, Box<Diagnostic>> {
+        let unsigned = lhs.unsigned || rhs.unsigned;
+        let arithmetic = |value| Constant { value, unsigned };
+        let less = if unsigned {
+            lhs.value < rhs.value
+        } else {
+            (lhs.value as i64) < (rhs.value as i64)
+        };
+        let greater = if unsigned {
+            lhs.value > rhs.value
+        } else {
+            (lhs.value as i64) > (rhs.value as i64)
+        };
+
+        Ok(match op {
+            TokenType::TLogOr => Constant::truth(lhs.value != 0 || rhs.value != 0),
+            TokenType::TLogAnd => Constant::truth(lhs.value != 0 && rhs.value != 0),
+            TokenType::TBitOr => arithmetic(lhs.value | rhs.value),
+            TokenType::TBitXor => arithmetic(lhs.value ^ rhs.value),
+            TokenType::TAmpersand => arithmetic(lhs.value & rhs.value),
+            TokenType::TEq => Constant::truth(lhs.value == rhs.value),
+            TokenType::TNoteq => Constant::truth(lhs.value != rhs.value),
+            TokenType::TLt => Constant::truth(less),
+            TokenType::TGt => Constant::truth(greater),
+            TokenType::TLe => Constant::truth(!greater),
+            TokenType::TGe => Constant::truth(!less),
+            TokenType::TLshift | TokenType::TRshift => {
+                // Negative shift amount shifts toward the other direction
+                let amount = if rhs.unsigned {
+                    rhs.value.min(64) as i64
+                } else {
+                    (rhs.value as i64).clamp(-64, 64)
+                };
+                let left = (op == TokenType::TLshift) == (amount >= 0);
+                let amount = amount.unsigned_abs() as u32;
+                let value = if left {
+                    lhs.value.checked_shl(amount).unwrap_or(0)
+                } else if lhs.unsigned {
+                    lhs.value.checked_shr(amount).unwrap_or(0)
+                } else {
+                    (lhs.value as i64 >> amount.min(63)) as u64
+                };
+
+                Constant {
+                    value,
+                    unsigned: lhs.unsigned,
+                }
+            }
+            TokenType::TPlus => arithmetic(lhs.value.wrapping_add(rhs.value)),
+            TokenType::TMinus => arithmetic(lhs.value.wrapping_sub(rhs.value)),
+            TokenType::TAsterisk => arithmetic(lhs.value.wrapping_mul(rhs.value)),
+            TokenType::TDivide | TokenType::TMod => {
+                if rhs.value == 0 {
+                    if evaluated {
+                        return Err(self.lexer.error("Division by zero in #if", pos));
+                    }
+
+                    return Ok(arithmetic(0));
+                }
+
+                let divide = op == TokenType::TDivide;
+
+                arithmetic(match (unsigned, divide) {
+                    (true, true) => lhs.value / rhs.value,
+                    (true, false) => lhs.value % rhs.value,
+                    (false, true) => (lhs.value as i64).wrapping_div(rhs.value as i64) as u64,
+                    (false, false) => (lhs.value as i64).wrapping_rem(rhs.value as i64) as u64,
+                })
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn read_unary_expr(&mut self, evaluated: bool) -> Result<Constant, Box<Diagnostic>> {
+        let op = self.lexer.current_token_type();
+
+        if !matches!(
+            op,
+            TokenType::TPlus | TokenType::TMinus | TokenType::TBitNot | TokenType::TLogNot
+        ) {
+            return self.read_primary_expr(evaluated);
+        }
+
+        self.enter_nesting(self.lexer.current_token_pos())?;
+        self.lexer.lex_token(true)?;
+        let operand = self.read_unary_expr(evaluated)?;
+        self.leave_nesting();
+
+        let constant = match op {
+            TokenType::TPlus => operand,
+            TokenType::TMinus => Constant {
+                value: operand.value.wrapping_neg(),
+                unsigned: operand.unsigned,
+            },
+            TokenType::TBitNot => Constant {
+                value: !operand.value,
+                unsigned: operand.unsigned,
+            },
+            _ => Constant::truth(operand.value == 0),
+        };
+
+        Ok(constant.wrap(self.lexer.target().intmax_width))
+    }
+
+    fn read_primary_expr(&mut self, evaluated: bool) -> Result<Constant, Box<Diagnostic>> {
+        if self.lexer.current_token_type() != TokenType::TOpenBracket {
+            return self.read_operand(evaluated);
+        }
+
+        self.lexer.lex_token(true)?;
+        let constant = self.read_constant_expr(evaluated)?;
+        self.lexer.lex_expect(TokenType::TCloseBracket, true)?;
+
+        Ok(constant)
+    }
+
+    /// Reads primary expression other than bracketed one, kept apart so that
+    /// frames of nested brackets stay small.
+    fn read_operand(&mut self, evaluated: bool) -> Result<Constant, Box<Diagnostic>> {
+        let token_str = self.lexer.current_token_str().to_string();
+        let pos = self.lexer.current_token_pos();
+
+        Ok(match self.lexer.current_token_type() {
+            TokenType::TNumeric => {
+                let constant = self.parse_integer(&token_str, pos)?;
+                self.lexer.lex_token(true)?;
+                constant
+            }
+            TokenType::TChar => {
+                let constant = self.parse_char(&token_str, pos)?;
+                self.lexer.lex_token(true)?;
+                constant
+            }
+            TokenType::TIdentifier if token_str == "defined" => {
+                self.lexer.lex_token(false)?;
+                let bracketed = self.lexer.lex_accept(TokenType::TOpenBracket, false)?;
+                let defined = self.lexer.test_defined(self.lexer.current_token_str());
+                self.lexer.lex_expect(TokenType::TIdentifier, !bracketed)?;
+
+                if bracketed {
+                    self.lexer.lex_expect(TokenType::TCloseBracket, true)?;
+                }
+
+                Constant::truth(defined)
+            }
+            TokenType::TIdentifier if token_str == "__has_include" => {
+                self.read_has_include(false, pos)?
+            }
+            TokenType::TIdentifier if token_str == "__has_include_next" => {
+                self.read_has_include(true, pos)?
+            }
+            TokenType::TIdentifier if token_str == "__has_embed" => self.read_has_embed(pos)?,
+            TokenType::TIdentifier if self.lexer.feature_test(&token_str).is_some() => {
+                let test = self.lexer.feature_test(&token_str).unwrap();
+                self.read_feature_test(test, pos)?
+            }
+            TokenType::TIdentifier if self.read_macro_invocation()? => {
+                self.enter_nesting(pos)?;
+                self.lexer.lex_token(true)?;
+                let constant = self.read_unary_expr(evaluated)?;
+                self.leave_nesting();
+                constant
+            }
+            TokenType::TIdentifier
+                if matches!(token_str.as_str(), "true" | "false")
+                    && self.lexer.language().is_keyword(&token_str) =>
+            {
+                self.lexer.lex_token(true)?;
+                Constant::truth(token_str == "true")
+            }
+            _ if token_str.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_') => {
+                // Identifiers remaining after expansion are replaced with 0
+                self.lexer.lex_token(true)?;
+                Constant::signed(0)
+            }
+            TokenType::TNewline | TokenType::TEof => {
+                return Err(self.lexer.error("Expected value in expression", pos))
+            }
+            _ => {
+                return Err(self.lexer.error(
+                    &format!("Token {token_str} is not valid in preprocessor expressions"),
+                    pos,
+                ))
+            }
+        })
+    }
+
+    /// Reads feature-test operator, whose operand is an unexpanded identifier
+    /// or scoped attribute name.
+    fn read_feature_test(
+        &mut self,
+        test: FeatureTest,
+        pos: usize,
+    ) -> Result<Constant, Box<Diagnostic>> {
+        let operator = self.lexer.current_token_str().to_string();
+        self.lexer.lex_token(false)?;
+
+        if !self.lexer.lex_accept(TokenType::TOpenBracket, false)? {
+            return Err(self
+                .lexer
+                .error(&format!("Missing '(' after {operator}"), pos));
+        }
+
+        let mut name = String::new();
+
+        // Attribute names may be keywords
+        while !self.lexer.lex_peek(TokenType::TCloseBracket)
+            && !self.lexer.lex_peek(TokenType::TNewline)
+            && !self.lexer.lex_peek(TokenType::TEof)
+        {
+            name.push_str(self.lexer.current_token_str());
+            self.lexer.lex_token(false)?;
+        }
+
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == b'_' || ch == b':')
+        {
+            return Err(self
+                .lexer
+                .error(&format!("{operator} requires an identifier"), pos));
+        }
+
+        self.lexer.lex_expect(TokenType::TCloseBracket, true)?;
+
+        Ok(Constant::signed(self.lexer.test_feature(test, &name) as i64))
+    }
+
+    fn parse_integer(&self, spelling: &str, pos: usize) -> Result<Constant, Box<Diagnostic>> {
+        let spelling = spelling.to_ascii_lowercase();
+        let digits = spelling.trim_end_matches(['u', 'l']);
+        let suffix = &spelling[digits.len()..];
+
+        if !matches!(suffix, "" | "u" | "l" | "ul" | "lu" | "ll" | "ull" | "llu") {
+            return Err(self
+                .lexer
+                .error(&format!("Invalid integer suffix {suffix}"), pos));
+        }
+
+        let (digits, radix) = if let Some(digits) = digits.strip_prefix("0x") {
+            (digits, 16)
+        } else if let Some(digits) = digits.strip_prefix("0b") {
+            (digits, 2)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            (&digits[1..], 8)
+        } else {
+            (digits, 10)
+        };
+
+        let width = self.lexer.target().intmax_width;
+
+        let Ok(value) = u64::from_str_radix(digits, radix) else {
+            return Err(self
+                .lexer
+                .error("Invalid integer constant in preprocessor expression", pos));
+        };
+
+        if width < 64 && value >> width != 0 {
+            return Err(self
+                .lexer
+                .error("Integer constant is too large for its type", pos));
+        }
+
+        Ok(Constant {
+            value,
+            unsigned: suffix.contains('u') || value >> (width - 1) != 0,
+        })
+    }
+
+    fn parse_char(&self, spelling: &str, pos: usize) -> Result<Constant, Box<Diagnostic>> {
+        let mut chars = spelling[1..spelling.len() - 1].bytes().peekable();
+        let mut value = 0u64;
+        let mut count = 0;
+
+        while let Some(ch) = chars.next() {
+            let ch = if ch != b'\\' {
+                ch as u64
+            } else {
+                match chars.next() {
+                    Some(b'n') => b'\n' as u64,
+                    Some(b't') => b'\t' as u64,
+                    Some(b'r') => b'\r' as u64,
+                    Some(b'a') => 0x07,
+                    Some(b'b') => 0x08,
+                    Some(b'f') => 0x0c,
+                    Some(b'v') => 0x0b,
+                    Some(b'x') => {
+                        let mut value = 0u64;
+
+                        while let Some(digit) =
+                            chars.peek().and_then(|ch| (*ch as char).to_digit(16))
+                        {
+                            value = value.wrapping_mul(16) + digit as u64;
+                            chars.next();
+                        }
+
+                        value
+                    }
+                    Some(ch @ b'0'..=b'7') => {
+                        let mut value = (ch - b'0') as u64;
+
+                        for _ in 0..2 {
+                            match chars.peek() {
+                                Some(ch @ b'0'..=b'7') => {
+                                    value = value * 8 + (ch - b'0') as u64;
+                                    chars.next();
+                                }
+                                _ => break,
+                            }
+                        }
+
+                        value
+                    }
+                    Some(ch) => ch as u64,
+                    None => return Err(self.lexer.error("Invalid escape sequence", pos)),
+                }
+            };
+
+            value = (value << 8) | (ch & 0xff);
+            count += 1;
+        }
+
+        let target = self.lexer.target();
+
+        match count {
+            0 => Err(self.lexer.error("Empty character constant", pos)),
+            1 if target.char_signed => Ok(Constant::signed(value as u8 as i8 as i64)),
+            1 => Ok(Constant::signed(value as u8 as i64)),
+            _ => Ok(Constant::signed(value as i64).wrap(target.int_width)),
+        }
+    }
+}
+
+/// Function-like macro invocation whose arguments are read, waiting for
+/// them to be fully expanded
+struct PendingInvocation {
+    symbol: Symbol,
+    alias: String,
+    /// Whether the macro name is spaced, inherited by the expansion
+    spaced: bool,
+    replacement: Rc<[ReplacementToken]>,
+    arguments: Vec<Vec<Token>>,
+    expanded_arguments: Vec<Vec<Token>>,
+    /// Origins of tokens of expanded arguments, recorded when traced
+    argument_origins: Vec<Vec<Option<NodeId>>>,
+    /// Traced node of the invocation
+    node: Option<NodeId>,
+    /// Argument being expanded, `None` between arguments
+    prescan: Option<Prescan>,
+}
+
+/// Argument being expanded in its isolated region
+struct Prescan {
+    region_floor: usize,
+    /// Traced node of the argument
+    node: Option<NodeId>,
+    tokens: Vec<Token>,
+    origins: Vec<Option<NodeId>>,
+}
+
+/// Origins of substituted tokens, recorded when expansions are traced
+struct SubstitutionTrace<'a> {
+    /// Origins of tokens of expanded arguments
+    argument_origins: &'a [Vec<Option<NodeId>>],
+    /// Origin of each substituted token, `None` for tokens produced by the
+    /// invocation itself
+    origins: Vec<Option<NodeId>>,
+    /// Indices of stringized arguments with their string literals
+    stringized: Vec<(usize, String)>,
+    /// Operands of `##` with their results, as paste steps
+    pastes: Vec<TraceStep>,
+}
+
+/// Whether argument `index` of `arguments` is substituted fully expanded by
+/// `replacement`. The variadic argument, which is the last one, is expanded
+/// to tell whether `__VA_OPT__` keeps its content.
+fn expands_argument(replacement: &[ReplacementToken], index: usize, arguments: usize) -> bool {
+    replacement
+        .iter()
+        .any(|replacement_token| match replacement_token {
+            ReplacementToken::Parameter {
+                index: parameter, ..
+            } => *parameter == index,
+            ReplacementToken::VaOpt { content, .. } => {
+                index + 1 == arguments || expands_argument(content, index, arguments)
+            }
+            _ => false,
+        })
+}
+
+/// Substitutes parameters of replacement list with their arguments, the
+/// variadic argument is the last one, and pastes operands of `##`. Stops once
+/// more than the maximum expansion tokens are substituted.
+fn substitute(
+    lexer: &Lexer,
+    replacement: &[ReplacementToken],
+    arguments: &[Vec<Token>],
+    expanded_arguments: &[Vec<Token>],
+    tokens: &mut Vec<Token>,
+    mut trace: Option<&mut SubstitutionTrace>,
+) -> Result<(), Box<Diagnostic>> {
+    let max_tokens = lexer.limits().max_expansion_tokens;
+    // Set by `##` to whether its left operand left a token, which it does
+    // not if it is a placemarker
+    let mut paste = None;
+    let mut substituted = false;
+
+    for replacement_token in replacement {
+        if max_tokens.is_some_and(|max| tokens.len() > max) {
+            return Ok(());
+        }
+
+        let start = tokens.len();
+        let spaced = match replacement_token {
+            ReplacementToken::Token(token) => {
+                tokens.push(token.clone());
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.origins.push(None);
+                }
+
+                token.spaced
+            }
+            ReplacementToken::Parameter { index, spaced } => {
+                tokens.extend_from_slice(&expanded_arguments[*index]);
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace
+                        .origins
+                        .extend_from_slice(&trace.argument_origins[*index]);
+                }
+
+                *spaced
+            }
+            ReplacementToken::Operand { index, spaced } => {
+                tokens.extend_from_slice(&arguments[*index]);
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.origins.extend(arguments[*index].iter().map(|_| None));
+                }
+
+                *spaced
+            }
+            ReplacementToken::Paste { .. } => {
+                paste = Some(substituted);
+                continue;
+            }
+            ReplacementToken::Stringized { index, spaced } => {
+                let literal = stringify(&spell_tokens(&arguments[*index]));
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.origins.push(None);
+                    trace.stringized.push((*index, literal.clone()));
+                }
+
+                tokens.push(Token {
+                    token_type: TokenType::TString,
+                    token_str: literal.into(),
+                    spaced: false,
+                    no_expand: false,
+                });
+                *spaced
+            }
+            ReplacementToken::VaOpt { content, spaced } => {
+                if expanded_arguments
+                    .last()
+                    .is_some_and(|argument| !argument.is_empty())
+                {
+                    substitute(
+                        lexer,
+                        content,
+                        arguments,
+                        expanded_arguments,
+                        tokens,
+                        trace.as_deref_mut(),
+                    )?;
+                }
+
+                *spaced
+            }
+        };
+
+        // Substituted tokens inherit spacing of the replaced token
+        if let Some(token) = tokens.get_mut(start) {
+            token.spaced = spaced;
+        }
+
+        let present = tokens.len() > start;
+
+        substituted = match paste.take() {
+            Some(true) if present => {
+                let rhs = tokens.remove(start);
+                let lhs = &tokens[start - 1];
+                let pasted = lexer.paste_tokens(lhs, &rhs)?;
+
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.origins.remove(start);
+                    trace.origins[start - 1] = None;
+                    trace.pastes.push(TraceStep::Paste {
+                        left: lhs.token_str.to_string(),
+                        right: rhs.token_str.to_string(),
+                        result: pasted.token_str.to_string(),
+                    });
+                }
+
+                tokens[start - 1] = pasted;
+                true
+            }
+            Some(left) => left || present,
+            None => present,
+        };
+    }
+
+    Ok(())
+}
+
+/// Splits spelling of `"name"` or `<name>` into the name and whether it is
+/// angled.
+fn parse_header_name(spelling: &str) -> Option<(String, bool)> {
+    if spelling.len() < 2 {
+        return None;
+    }
+
+    let angled = match (
+        spelling.as_bytes()[0],
+        spelling.as_bytes()[spelling.len() - 1],
+    ) {
+        (b'"', b'"') => false,
+        (b'<', b'>') => true,
+        _ => return None,
+    };
+
+    Some((spelling[1..spelling.len() - 1].to_string(), angled))
+}
+
+/// Removes quotes of string literal, then unescapes its quotes and backslashes.
+fn destringize(literal: &str) -> String {
+    let literal = literal.strip_prefix('L').unwrap_or(literal);
+    let mut chars = literal[1..literal.len() - 1].chars();
+    let mut builder = String::with_capacity(literal.len());
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => builder.push(escaped),
+                Some(escaped) => {
+                    builder.push(ch);
+                    builder.push(escaped);
+                }
+                None => builder.push(ch),
+            },
+            _ => builder.push(ch),
+        }
+    }
+
+    builder
 }
@@ -0,0 +1,298 @@
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::{spell_tokens, Lexer, Token, TokenType},
+};
+
+/// Pragma read from `#pragma` directive or `_Pragma` operator, the first token
+/// names its namespace.
+#[derive(Debug, Clone)]
+pub struct Pragma {
+    pub tokens: Vec<Token>,
+    pub pos: usize,
+}
+
+impl Pragma {
+    pub fn namespace(&self) -> &str {
+        self.tokens
+            .first()
+            .map_or("", |token| token.token_str.as_str())
+    }
+
+    /// Tokens following the namespace.
+    pub fn operands(&self) -> &[Token] {
+        self.tokens.get(1..).unwrap_or_default()
+    }
+
+    /// Spells pragma tokens as written after `#pragma`.
+    pub fn spelling(&self) -> String {
+        spell_tokens(&self.tokens)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PragmaAction {
+    /// Pragma is consumed by its handler
+    Consume,
+    /// Pragma is written into output as `#pragma` line
+    PassThrough,
+}
+
+/// Preprocessor state available to pragma handlers. Positions are those of
+/// [`Pragma::pos`].
+pub struct PragmaContext<'a> {
+    lexer: &'a mut Lexer,
+}
+
+impl<'a> PragmaContext<'a> {
+    pub(crate) fn new(lexer: &'a mut Lexer) -> Self {
+        Self { lexer }
+    }
+
+    /// Records warning at `pos`.
+    pub fn warning(&mut self, msg: &str, pos: usize) {
+        self.lexer.warning(msg, pos);
+    }
+
+    /// Error at `pos`, which stops preprocessing once returned by the
+    /// handler.
+    pub fn error(&self, msg: &str, pos: usize) -> Diagnostic {
+        *self.lexer.error(msg, pos)
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.lexer.is_defined(name)
+    }
+
+    /// Saves current definition of macro `name`, restored by
+    /// [`PragmaContext::pop_macro`].
+    pub fn push_macro(&mut self, name: &str) {
+        self.lexer.push_macro(name);
+    }
+
+    pub fn pop_macro(&mut self, name: &str) {
+        self.lexer.pop_macro(name);
+    }
+
+    /// Poisons identifier, whose later appearance is an error.
+    pub fn poison(&mut self, name: &str) {
+        self.lexer.poison(name);
+    }
+
+    /// Marks current file to be never included again, returns false if it is
+    /// the main file.
+    pub fn mark_pragma_once(&mut self) -> bool {
+        self.lexer.mark_pragma_once()
+    }
+}
+
+/// Handler of pragmas under a claimed namespace, registered by
+/// [`Preprocessor::register_pragma_handler`](crate::Preprocessor::register_pragma_handler).
+pub trait PragmaHandler {
+    /// Whether operands are macro expanded before being handled.
+    fn expands_operands(&self) -> bool {
+        false
+    }
+
+    /// Handles `pragma`, an error stops preprocessing.
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic>;
+}
+
+/// Concatenates contents of consecutive string literals, optionally enclosed
+/// by brackets. Returns `None` if tokens are not in such form.
+fn read_string_operand(tokens: &[Token]) -> Option<String> {
+    let tokens = match tokens {
+        [open, inner @ .., close]
+            if open.token_type == TokenType::TOpenBracket
+                && close.token_type == TokenType::TCloseBracket =>
+        {
+            inner
+        }
+        _ => tokens,
+    };
+
+    if tokens.is_empty() {
+        return None;
+    }
+
+    tokens
+        .iter()
+        .map(|token| match token.token_type {
+            TokenType::TString => Some(&token.token_str[1..token.token_str.len() - 1]),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `#pragma once`, current file is skipped by later `#include`s
+pub struct OncePragma;
+
+impl PragmaHandler for OncePragma {
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        if !pragma.operands().is_empty() {
+            context.warning("Extra tokens at end of #pragma once", pragma.pos);
+        }
+
+        if !context.mark_pragma_once() {
+            context.warning("#pragma once in main file", pragma.pos);
+        }
+
+        Ok(PragmaAction::Consume)
+    }
+}
+
+/// `#pragma push_macro("name")`, saves current definition of macro
+pub struct PushMacroPragma;
+
+impl PragmaHandler for PushMacroPragma {
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        match read_string_operand(pragma.operands()) {
+            Some(name) => context.push_macro(&name),
+            None => context.warning("#pragma push_macro requires a string literal", pragma.pos),
+        }
+
+        Ok(PragmaAction::Consume)
+    }
+}
+
+/// `#pragma pop_macro("name")`, restores definition saved by `push_macro`
+pub struct PopMacroPragma;
+
+impl PragmaHandler for PopMacroPragma {
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        match read_string_operand(pragma.operands()) {
+            Some(name) => context.pop_macro(&name),
+            None => context.warning("#pragma pop_macro requires a string literal", pragma.pos),
+        }
+
+        Ok(PragmaAction::Consume)
+    }
+}
+
+/// `#pragma message`, reports its string operand as a warning
+pub struct MessagePragma;
+
+impl PragmaHandler for MessagePragma {
+    fn expands_operands(&self) -> bool {
+        true
+    }
+
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        match read_string_operand(pragma.operands()) {
+            Some(message) => context.warning(&format!("#pragma message: {message}"), pragma.pos),
+            None => context.warning("#pragma message requires a string literal", pragma.pos),
+        }
+
+        Ok(PragmaAction::Consume)
+    }
+}
+
+/// `#pragma GCC`, diagnostics are emitted and the rest is passed to compiler
+pub struct GccPragma;
+
+impl PragmaHandler for GccPragma {
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        let Some((command, operands)) = pragma.operands().split_first() else {
+            return Ok(PragmaAction::PassThrough);
+        };
+
+        Ok(match command.token_str.as_str() {
+            "warning" | "error" => {
+                let Some(message) = read_string_operand(operands) else {
+                    context.warning(
+                        &format!(
+                            "#pragma GCC {} requires a string literal",
+                            command.token_str
+                        ),
+                        pragma.pos,
+                    );
+                    return Ok(PragmaAction::Consume);
+                };
+
+                if command.token_str == "error" {
+                    return Err(context.error(&message, pragma.pos));
+                }
+
+                context.warning(&message, pragma.pos);
+                PragmaAction::Consume
+            }
+            "poison" => {
+                for operand in operands {
+                    if operand.token_type != TokenType::TIdentifier {
+                        return Err(
+                            context.error("Invalid #pragma GCC poison directive", pragma.pos)
+                        );
+                    }
+
+                    if context.is_defined(&operand.token_str) {
+                        context.warning(
+                            &format!("Poisoning existing macro \"{}\"", operand.token_str),
+                            pragma.pos,
+                        );
+                    }
+
+                    context.poison(&operand.token_str);
+                }
+
+                PragmaAction::Consume
+            }
+            "system_header" => PragmaAction::Consume,
+            _ => PragmaAction::PassThrough,
+        })
+    }
+}
+
+/// `#pragma STDC`, validates standard pragmas which are then passed to
+/// compiler
+pub struct StdcPragma;
+
+impl PragmaHandler for StdcPragma {
+    fn handle(
+        &mut self,
+        context: &mut PragmaContext,
+        pragma: &Pragma,
+    ) -> Result<PragmaAction, Diagnostic> {
+        let operands = pragma
+            .operands()
+            .iter()
+            .map(|token| token.token_str.as_str())
+            .collect::<Vec<_>>();
+
+        match operands.as_slice() {
+            ["FP_CONTRACT" | "FENV_ACCESS" | "CX_LIMITED_RANGE", "ON" | "OFF" | "DEFAULT"] => {}
+            ["FP_CONTRACT" | "FENV_ACCESS" | "CX_LIMITED_RANGE", ..] => {
+                context.warning("Expected ON, OFF or DEFAULT in #pragma STDC", pragma.pos)
+            }
+            ["FENV_ROUND" | "FENV_DEC_ROUND", _] => {}
+            _ => context.warning(
+                &format!("Unknown #pragma {}", pragma.spelling()),
+                pragma.pos,
+            ),
+        }
+
+        Ok(PragmaAction::PassThrough)
+    }
+}
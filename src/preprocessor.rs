@@ -0,0 +1,136 @@
+use std::{io, path::PathBuf};
+
+use crate::{
+    diagnostic::Diagnostic, feature::Capabilities, language::LanguageOptions, limits::Limits,
+    macros::MacroInfo, observer::PreprocessorObserver, options::PreprocessorOptions,
+    parser::Parser, pragma::PragmaHandler, source::SourceManager, target::Target,
+    trace::ExpansionTrace,
+};
+
+/// Preprocessor of one translation unit, which is configured before
+/// [`Preprocessor::preprocess`] is called once.
+///
+/// ```
+/// use tangle::{Preprocessor, PreprocessorOptions};
+///
+/// let mut preprocessor = Preprocessor::new("#define ADD(a, b) a + b\nint x = ADD(1, N);\n");
+/// preprocessor.set_options(&PreprocessorOptions::new().define("N=2"));
+///
+/// assert_eq!(preprocessor.preprocess().unwrap(), "int x = 1 + 2;");
+/// ```
+pub struct Preprocessor {
+    parser: Parser,
+}
+
+impl Preprocessor {
+    /// Creates preprocessor of nameless source, whose quoted includes are
+    /// searched from working directory.
+    pub fn new(source: &str) -> Self {
+        Self::with_parser(Parser::new(source))
+    }
+
+    /// Creates preprocessor of main file at `path`, which is where quoted
+    /// includes are searched from.
+    pub fn from_file(path: impl Into<PathBuf>) -> io::Result<Self> {
+        Ok(Self::with_parser(Parser::from_file(path)?))
+    }
+
+    /// Creates preprocessor of main file at `path` loaded by
+    /// `source_manager`, whose in-memory files shadow files on disk.
+    pub fn with_source_manager(
+        source_manager: SourceManager,
+        path: impl Into<PathBuf>,
+    ) -> io::Result<Self> {
+        Ok(Self::with_parser(Parser::with_source_manager(
+            source_manager,
+            path,
+        )?))
+    }
+
+    fn with_parser(mut parser: Parser) -> Self {
+        parser.set_token_spacing(true);
+        Self { parser }
+    }
+
+    /// Applies `-D`, `-U`, `-I`, `-include` and `-imacros` style options.
+    pub fn set_options(&mut self, options: &PreprocessorOptions) {
+        self.parser.set_options(options);
+    }
+
+    pub fn add_include_path(&mut self, path: impl Into<PathBuf>) {
+        self.parser.add_include_path(path);
+    }
+
+    /// Writes linemarkers like `cpp`, which are disabled by default.
+    pub fn set_linemarkers(&mut self, enabled: bool) {
+        self.parser.set_linemarkers(enabled);
+    }
+
+    /// Separates output tokens as in source, which is enabled by default.
+    pub fn set_token_spacing(&mut self, enabled: bool) {
+        self.parser.set_token_spacing(enabled);
+    }
+
+    pub fn set_language(&mut self, language: LanguageOptions) {
+        self.parser.set_language(language);
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.parser.set_target(target);
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.parser.set_capabilities(capabilities);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.parser.set_limits(limits);
+    }
+
+    pub fn register_pragma_handler(
+        &mut self,
+        namespace: &str,
+        handler: impl PragmaHandler + 'static,
+    ) {
+        self.parser.register_pragma_handler(namespace, handler);
+    }
+
+    /// Adds observer of preprocessing events such as macro expansions and
+    /// entered files.
+    pub fn add_observer(&mut self, observer: impl PreprocessorObserver + 'static) {
+        self.parser.add_observer(observer);
+    }
+
+    /// Records macro expansions as a tree, together with the invocations
+    /// which produced each output token. Tracing is disabled by default.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.parser.set_tracing(enabled);
+    }
+
+    /// Expansions recorded so far if tracing is enabled.
+    pub fn trace(&self) -> Option<&ExpansionTrace> {
+        self.parser.trace()
+    }
+
+    /// Describes every currently defined macro including builtin macros,
+    /// sorted by name. Once preprocessing is done, these are the macros
+    /// defined at the end of the translation unit.
+    pub fn macros(&self) -> Vec<MacroInfo<'_>> {
+        self.parser.macros()
+    }
+
+    /// Preprocesses the translation unit, returning its output or the error
+    /// which stopped it. Warnings are kept in [`Preprocessor::warnings`].
+    pub fn preprocess(&mut self) -> Result<String, Diagnostic> {
+        self.parser.preprocess()
+    }
+
+    pub fn warnings(&self) -> &[Diagnostic] {
+        self.parser.diagnostics()
+    }
+
+    /// Files loaded so far by the preprocessor.
+    pub fn source_manager(&self) -> &SourceManager {
+        self.parser.source_manager()
+    }
+}
@@ -0,0 +1,144 @@
+/// Class of byte, which selects how the token starting with it is scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    /// Space, horizontal tab, vertical tab and form feed
+    Whitespace,
+    Newline,
+    /// Letters and underscore
+    IdentifierStart,
+    /// `$`, which starts identifier if the language allows it
+    Dollar,
+    Digit,
+    /// `.`, which may start preprocessing number or ellipsis
+    Dot,
+    /// `#`
+    Hash,
+    /// `/`, which may start comment
+    Slash,
+    /// `"` and `'`
+    Quote,
+    Punctuator,
+    /// Null byte, which ends the source
+    Nul,
+    /// Byte which is not part of other tokens, or starts multibyte character
+    Other,
+}
+
+pub const CHAR_CLASSES: [CharClass; 256] = char_classes();
+
+const fn char_classes() -> [CharClass; 256] {
+    let mut classes = [CharClass::Other; 256];
+    let mut ch = 0;
+
+    while ch < classes.len() {
+        classes[ch] = match ch as u8 {
+            b' ' | b'\t' | b'\x0b' | b'\x0c' => CharClass::Whitespace,
+            b'\n' => CharClass::Newline,
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => CharClass::IdentifierStart,
+            b'$' => CharClass::Dollar,
+            b'0'..=b'9' => CharClass::Digit,
+            b'.' => CharClass::Dot,
+            b'#' => CharClass::Hash,
+            b'/' => CharClass::Slash,
+            b'"' | b'\'' => CharClass::Quote,
+            b'(' | b')' | b'{' | b'}' | b'[' | b']' | b',' | b'^' | b'~' | b'*' | b'&' | b'|'
+            | b'<' | b'%' | b'>' | b'!' | b'-' | b'+' | b';' | b'?' | b':' | b'=' | b'\\' => {
+                CharClass::Punctuator
+            }
+            b'\0' => CharClass::Nul,
+            _ => CharClass::Other,
+        };
+        ch += 1;
+    }
+
+    classes
+}
+
+/// Whether byte may continue identifier or preprocessing number.
+pub fn is_alnum(ch: u8) -> bool {
+    matches!(
+        CHAR_CLASSES[ch as usize],
+        CharClass::IdentifierStart | CharClass::Digit
+    )
+}
+
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+/// Marks high bit of each byte of `word` equal to `byte`.
+const fn bytes_equal(word: u64, byte: u8) -> u64 {
+    let word = word ^ (ONES * byte as u64);
+
+    !(((word & !HIGHS) + !HIGHS) | word | !HIGHS)
+}
+
+/// Marks high bit of each byte of `word` not less than `min`. Bytes above a
+/// non-ASCII byte may be marked wrongly, so only the lowest non-ASCII byte is
+/// meaningful.
+const fn bytes_at_least(word: u64, min: u8) -> u64 {
+    word.wrapping_add(ONES * (128 - min as u64)) & HIGHS
+}
+
+const fn bytes_between(word: u64, min: u8, max: u8) -> u64 {
+    bytes_at_least(word, min) & !bytes_at_least(word, max + 1)
+}
+
+/// Marks high bit of each byte of `word` which continues identifier.
+pub fn identifier_bytes(word: u64, dollar: bool) -> u64 {
+    let ascii = !word & HIGHS;
+    let mut bytes = bytes_between(word, b'a', b'z')
+        | bytes_between(word, b'A', b'Z')
+        | bytes_between(word, b'0', b'9')
+        | bytes_equal(word, b'_');
+
+    if dollar {
+        bytes |= bytes_equal(word, b'$');
+    }
+
+    bytes & ascii
+}
+
+/// Marks high bit of each byte of `word` which is whitespace, and newline if
+/// `newline` is set.
+pub fn whitespace_bytes(word: u64, newline: bool) -> u64 {
+    let mut bytes = bytes_equal(word, b' ')
+        | bytes_equal(word, b'\t')
+        | bytes_equal(word, b'\x0b')
+        | bytes_equal(word, b'\x0c');
+
+    if newline {
+        bytes |= bytes_equal(word, b'\n');
+    }
+
+    bytes
+}
+
+/// Counts leading bytes of `bytes` marked by `matches`, eight bytes at a time.
+/// Bytes past the end are read as null bytes, which must not be marked.
+pub fn count_matching(bytes: &[u8], matches: impl Fn(u64) -> u64) -> usize {
+    let mut count = 0;
+
+    while let Some(chunk) = bytes.get(count..count + 8) {
+        let mismatches = !matches(u64::from_le_bytes(chunk.try_into().unwrap())) & HIGHS;
+
+        if mismatches != 0 {
+            return count + (mismatches.trailing_zeros() / 8) as usize;
+        }
+
+        count += chunk.len();
+    }
+
+    let mut chunk = [0; 8];
+    chunk[..bytes.len() - count].copy_from_slice(&bytes[count..]);
+
+    let mismatches = !matches(u64::from_le_bytes(chunk)) & HIGHS;
+    count + (mismatches.trailing_zeros() / 8) as usize
+}
+
+/// Counts bytes preceding the first `byte` or null byte, which ends the
+/// source.
+pub fn count_until(bytes: &[u8], byte: u8) -> usize {
+    count_matching(bytes, |word| {
+        !(bytes_equal(word, byte) | bytes_equal(word, b'\0'))
+    })
+}
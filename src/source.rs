@@ -0,0 +1,154 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    file_system::{FileSystem, OverlayFileSystem, RealFileSystem},
+    globals::{read_binary_file, read_source_file},
+};
+
+/// Index of file loaded by [`SourceManager`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(usize);
+
+/// Identity of source file, the same file reached by different paths shares
+/// its canonical path or its device and inode numbers
+#[derive(Debug, Clone)]
+pub struct FileIdentity {
+    pub path: PathBuf,
+    pub inode: Option<(u64, u64)>,
+}
+
+impl FileIdentity {
+    pub fn new(file_system: &dyn FileSystem, path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: file_system.canonicalize(path)?,
+            inode: file_system.stat(path)?.inode,
+        })
+    }
+
+    pub fn is_same_file(&self, other: &FileIdentity) -> bool {
+        self.path == other.path || self.inode.is_some() && self.inode == other.inode
+    }
+}
+
+/// File loaded by [`SourceManager`], with its most recently read text, which
+/// is empty until it is read
+#[derive(Debug)]
+pub struct LoadedFile {
+    /// Path the file was first loaded by
+    pub path: PathBuf,
+    pub identity: FileIdentity,
+    pub text: Rc<str>,
+}
+
+/// Owner of every file loaded during a preprocessing session, including
+/// in-memory files registered by the embedder. Files are read through a
+/// [`FileSystem`], which is the disk by default. Sessions share no state, so
+/// each thread may create its own.
+pub struct SourceManager {
+    files: Vec<LoadedFile>,
+    /// In-memory files over the file system given by the embedder
+    file_system: OverlayFileSystem,
+}
+
+impl Default for SourceManager {
+    fn default() -> Self {
+        Self::with_file_system(RealFileSystem)
+    }
+}
+
+impl SourceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates source manager reading files through `file_system`.
+    pub fn with_file_system(file_system: impl FileSystem + 'static) -> Self {
+        Self {
+            files: vec![],
+            file_system: OverlayFileSystem::over(file_system),
+        }
+    }
+
+    /// Registers in-memory file at `path`, which shadows file of the same
+    /// path. Paths are compared after `.` and `..` components are resolved.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        self.file_system.add_file(path, contents);
+    }
+
+    pub fn file_system(&self) -> &dyn FileSystem {
+        &self.file_system
+    }
+
+    pub fn is_file(&self, path: &Path) -> bool {
+        self.file_system.is_file(path)
+    }
+
+    pub fn is_directory(&self, path: &Path) -> bool {
+        self.file_system.is_directory(path)
+    }
+
+    /// Looks up file at `path` by its identity without reading it. The same
+    /// file reached by another path keeps its id, new files are registered
+    /// with empty text until [`SourceManager::read`] is called.
+    pub fn identify(&mut self, path: &Path) -> io::Result<FileId> {
+        let identity = FileIdentity::new(&self.file_system, path)?;
+
+        match self
+            .files
+            .iter()
+            .position(|file| file.identity.is_same_file(&identity))
+        {
+            Some(idx) => Ok(FileId(idx)),
+            None => {
+                self.files.push(LoadedFile {
+                    path: path.to_path_buf(),
+                    identity,
+                    text: Rc::default(),
+                });
+                Ok(FileId(self.files.len() - 1))
+            }
+        }
+    }
+
+    /// Reads text of `file`, bytes which are not valid UTF-8 are replaced.
+    /// The text is read again on every call so that changes on disk are
+    /// seen.
+    pub fn read(&mut self, file: FileId) -> io::Result<Rc<str>> {
+        let loaded_file = &mut self.files[file.0];
+        loaded_file.text = read_source_file(&self.file_system, &loaded_file.path)?.into();
+
+        Ok(loaded_file.text.clone())
+    }
+
+    /// Identifies then reads source file at `path`.
+    pub fn load(&mut self, path: &Path) -> io::Result<FileId> {
+        let file = self.identify(path)?;
+        self.read(file)?;
+
+        Ok(file)
+    }
+
+    pub fn file(&self, file: FileId) -> &LoadedFile {
+        &self.files[file.0]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &LoadedFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(idx, file)| (FileId(idx), file))
+    }
+
+    /// Reads at most `limit` bytes of binary file, which is not kept.
+    pub fn read_binary(&self, path: &Path, limit: Option<u64>) -> io::Result<Vec<u8>> {
+        read_binary_file(&self.file_system, path, limit)
+    }
+
+    pub fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.file_system.stat(path)?.size)
+    }
+}
@@ -0,0 +1,223 @@
+/// Byte order of target machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Description of target machine, which supplies predefined macros and the
+/// integer widths used by `#if` evaluation. Widths are in bits.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub name: &'static str,
+    pub endianness: Endianness,
+    pub char_signed: bool,
+    pub wchar_signed: bool,
+    pub short_width: u32,
+    pub int_width: u32,
+    pub long_width: u32,
+    pub long_long_width: u32,
+    pub pointer_width: u32,
+    /// Width of `intmax_t`, which `#if` expressions are evaluated in
+    pub intmax_width: u32,
+    /// Macros naming the architecture
+    pub arch_macros: Vec<(&'static str, String)>,
+}
+
+impl Default for Target {
+    /// Target of the host, falling back to x86-64 on unknown hosts.
+    fn default() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            Self::aarch64()
+        } else if cfg!(target_arch = "arm") {
+            Self::armv7()
+        } else if cfg!(target_arch = "riscv32") {
+            Self::riscv32()
+        } else if cfg!(target_arch = "riscv64") {
+            Self::riscv64()
+        } else {
+            Self::x86_64()
+        }
+    }
+}
+
+impl Target {
+    /// 32-bit target with `int`, `long` and pointers of 32 bits.
+    fn ilp32(name: &'static str, char_signed: bool) -> Self {
+        Self {
+            name,
+            endianness: Endianness::Little,
+            char_signed,
+            wchar_signed: true,
+            short_width: 16,
+            int_width: 32,
+            long_width: 32,
+            long_long_width: 64,
+            pointer_width: 32,
+            intmax_width: 64,
+            arch_macros: vec![],
+        }
+    }
+
+    /// 64-bit target with `long` and pointers of 64 bits.
+    fn lp64(name: &'static str, char_signed: bool) -> Self {
+        Self {
+            long_width: 64,
+            pointer_width: 64,
+            ..Self::ilp32(name, char_signed)
+        }
+    }
+
+    pub fn x86_64() -> Self {
+        let mut target = Self::lp64("x86_64", true);
+        target.arch_macros = arch_macros(&["__amd64", "__amd64__", "__x86_64", "__x86_64__"]);
+        target
+    }
+
+    pub fn aarch64() -> Self {
+        let mut target = Self::lp64("aarch64", false);
+        target.wchar_signed = false;
+        target.arch_macros = arch_macros(&["__aarch64__", "__ARM_64BIT_STATE"]);
+        target.arch_macros.push(("__ARM_ARCH", "8".to_string()));
+        target
+    }
+
+    /// ARMv7-A in ARM state, as targeted by shecc.
+    pub fn armv7() -> Self {
+        let mut target = Self::ilp32("armv7", false);
+        target.wchar_signed = false;
+        target.arch_macros = arch_macros(&[
+            "__arm__",
+            "__ARMEL__",
+            "__ARM_ARCH_7A__",
+            "__ARM_32BIT_STATE",
+        ]);
+        target.arch_macros.push(("__ARM_ARCH", "7".to_string()));
+        target
+    }
+
+    pub fn riscv32() -> Self {
+        let mut target = Self::ilp32("riscv32", false);
+        target.arch_macros = arch_macros(&["__riscv"]);
+        target.arch_macros.push(("__riscv_xlen", "32".to_string()));
+        target
+    }
+
+    pub fn riscv64() -> Self {
+        let mut target = Self::lp64("riscv64", false);
+        target.arch_macros = arch_macros(&["__riscv"]);
+        target.arch_macros.push(("__riscv_xlen", "64".to_string()));
+        target
+    }
+
+    /// Macros predefined for the target, spelled as GCC does.
+    pub fn predefined_macros(&self) -> Vec<(&'static str, String)> {
+        let lp64 = self.long_width == 64 && self.pointer_width == 64;
+        // `intptr_t` is `long` on LP64 targets and `int` on ILP32 targets
+        let pointer_suffix = if lp64 { "L" } else { "" };
+        let intmax_suffix = if self.long_width == self.intmax_width {
+            "L"
+        } else {
+            "LL"
+        };
+        let byte_order = match self.endianness {
+            Endianness::Little => "__ORDER_LITTLE_ENDIAN__",
+            Endianness::Big => "__ORDER_BIG_ENDIAN__",
+        };
+        let wchar_max = if self.wchar_signed {
+            max_value(32, true, "")
+        } else {
+            max_value(32, false, "U")
+        };
+
+        let mut macros = vec![
+            ("__CHAR_BIT__", "8".to_string()),
+            ("__SIZEOF_SHORT__", (self.short_width / 8).to_string()),
+            ("__SIZEOF_INT__", (self.int_width / 8).to_string()),
+            ("__SIZEOF_LONG__", (self.long_width / 8).to_string()),
+            (
+                "__SIZEOF_LONG_LONG__",
+                (self.long_long_width / 8).to_string(),
+            ),
+            ("__SIZEOF_POINTER__", (self.pointer_width / 8).to_string()),
+            ("__SIZEOF_SIZE_T__", (self.pointer_width / 8).to_string()),
+            ("__SIZEOF_PTRDIFF_T__", (self.pointer_width / 8).to_string()),
+            ("__SIZEOF_WCHAR_T__", "4".to_string()),
+            ("__SCHAR_MAX__", max_value(8, true, "")),
+            ("__SHRT_MAX__", max_value(self.short_width, true, "")),
+            ("__INT_MAX__", max_value(self.int_width, true, "")),
+            ("__LONG_MAX__", max_value(self.long_width, true, "L")),
+            (
+                "__LONG_LONG_MAX__",
+                max_value(self.long_long_width, true, "LL"),
+            ),
+            ("__WCHAR_MAX__", wchar_max),
+            (
+                "__INTMAX_MAX__",
+                max_value(self.intmax_width, true, intmax_suffix),
+            ),
+            (
+                "__UINTMAX_MAX__",
+                max_value(self.intmax_width, false, &format!("U{intmax_suffix}")),
+            ),
+            (
+                "__INTPTR_MAX__",
+                max_value(self.pointer_width, true, pointer_suffix),
+            ),
+            (
+                "__UINTPTR_MAX__",
+                max_value(self.pointer_width, false, &format!("U{pointer_suffix}")),
+            ),
+            (
+                "__PTRDIFF_MAX__",
+                max_value(self.pointer_width, true, pointer_suffix),
+            ),
+            (
+                "__SIZE_MAX__",
+                max_value(self.pointer_width, false, &format!("U{pointer_suffix}")),
+            ),
+            ("__SCHAR_WIDTH__", "8".to_string()),
+            ("__SHRT_WIDTH__", self.short_width.to_string()),
+            ("__INT_WIDTH__", self.int_width.to_string()),
+            ("__LONG_WIDTH__", self.long_width.to_string()),
+            ("__LONG_LONG_WIDTH__", self.long_long_width.to_string()),
+            ("__WCHAR_WIDTH__", "32".to_string()),
+            ("__INTMAX_WIDTH__", self.intmax_width.to_string()),
+            ("__INTPTR_WIDTH__", self.pointer_width.to_string()),
+            ("__PTRDIFF_WIDTH__", self.pointer_width.to_string()),
+            ("__SIZE_WIDTH__", self.pointer_width.to_string()),
+            ("__ORDER_LITTLE_ENDIAN__", "1234".to_string()),
+            ("__ORDER_BIG_ENDIAN__", "4321".to_string()),
+            ("__ORDER_PDP_ENDIAN__", "3412".to_string()),
+            ("__BYTE_ORDER__", byte_order.to_string()),
+            ("__FLOAT_WORD_ORDER__", byte_order.to_string()),
+        ];
+
+        if !self.char_signed {
+            macros.push(("__CHAR_UNSIGNED__", "1".to_string()));
+        }
+
+        if lp64 {
+            macros.push(("_LP64", "1".to_string()));
+            macros.push(("__LP64__", "1".to_string()));
+        }
+
+        macros.extend(self.arch_macros.iter().cloned());
+        macros
+    }
+}
+
+fn arch_macros(names: &[&'static str]) -> Vec<(&'static str, String)> {
+    names.iter().map(|name| (*name, "1".to_string())).collect()
+}
+
+/// Spells maximum value of integer type of `width` in hexadecimal.
+fn max_value(width: u32, signed: bool, suffix: &str) -> String {
+    let max = if signed {
+        (1u128 << (width - 1)) - 1
+    } else {
+        (1u128 << width) - 1
+    };
+
+    format!("{max:#x}{suffix}")
+}
@@ -0,0 +1,321 @@
+use std::fmt::Write;
+
+use crate::lexer::PresumedLocation;
+
+/// Index of node of [`ExpansionTrace`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// Step of macro expansion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceStep {
+    /// Invocation of macro `name`, whose children are the steps of its
+    /// expansion. Arguments are given as written for function-like macros.
+    Invocation {
+        name: String,
+        arguments: Option<Vec<String>>,
+        location: PresumedLocation,
+    },
+    /// Argument `index` fully macro expanded in isolation before
+    /// substitution, invocations in the argument are its children
+    Prescan {
+        index: usize,
+        argument: String,
+        expanded: String,
+    },
+    /// Argument `index` spelled as string literal by `#`
+    Stringize { index: usize, result: String },
+    /// Operands of `##` pasted into a single token
+    Paste {
+        left: String,
+        right: String,
+        result: String,
+    },
+    /// Replacement list with arguments substituted
+    Substitution { result: String },
+    /// Substituted tokens rescanned with the tokens following the
+    /// invocation, invocations found by rescanning are its children
+    Rescan,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    pub step: TraceStep,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+}
+
+/// Token written to output, with the node of the expansion producing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputToken {
+    pub spelling: String,
+    /// Rescan of the invocation whose expansion produced the token, `None`
+    /// if it is written as in source
+    pub origin: Option<NodeId>,
+}
+
+/// Tree of macro expansion steps, recorded by
+/// [`Preprocessor::set_tracing`](crate::Preprocessor::set_tracing)
+#[derive(Debug, Clone, Default)]
+pub struct ExpansionTrace {
+    nodes: Vec<TraceNode>,
+    /// Invocations read outside of any expansion
+    roots: Vec<NodeId>,
+    output: Vec<OutputToken>,
+}
+
+impl ExpansionTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds node of `step` as the last child of `parent`, or as root.
+    pub(crate) fn add(&mut self, parent: Option<NodeId>, step: TraceStep) -> NodeId {
+        let id = NodeId(self.nodes.len());
+
+        self.nodes.push(TraceNode {
+            step,
+            parent,
+            children: vec![],
+        });
+
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+
+        id
+    }
+
+    pub(crate) fn step_mut(&mut self, id: NodeId) -> &mut TraceStep {
+        &mut self.nodes[id.0].step
+    }
+
+    pub(crate) fn push_output(&mut self, spelling: &str, origin: Option<NodeId>) {
+        self.output.push(OutputToken {
+            spelling: spelling.to_string(),
+            origin,
+        });
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn node(&self, id: NodeId) -> &TraceNode {
+        &self.nodes[id.0]
+    }
+
+    /// Tokens written to output so far, excluding those of directives.
+    pub fn output(&self) -> &[OutputToken] {
+        &self.output
+    }
+
+    /// Invocations which produced output token at `index`, from the innermost
+    /// to the outermost.
+    pub fn invocations(&self, index: usize) -> Vec<NodeId> {
+        let mut invocations = vec![];
+        let mut node = self.output[index].origin;
+
+        while let Some(id) = node {
+            if matches!(self.node(id).step, TraceStep::Invocation { .. }) {
+                invocations.push(id);
+            }
+
+            node = self.node(id).parent;
+        }
+
+        invocations
+    }
+
+    /// Prints the tree with two spaces of indentation per level.
+    pub fn to_text(&self) -> String {
+        let mut builder = String::new();
+
+        for root in &self.roots {
+            self.write_text(&mut builder, *root, 0);
+        }
+
+        builder
+    }
+
+    fn write_text(&self, builder: &mut String, id: NodeId, depth: usize) {
+        let node = self.node(id);
+        let indent = "  ".repeat(depth);
+
+        match &node.step {
+            TraceStep::Invocation {
+                name,
+                arguments: Some(arguments),
+                location,
+            } => writeln!(
+                builder,
+                "{indent}{name}({}) at {location}",
+                arguments.join(", ")
+            ),
+            TraceStep::Invocation { name, location, .. } => {
+                writeln!(builder, "{indent}{name} at {location}")
+            }
+            TraceStep::Prescan {
+                index,
+                argument,
+                expanded,
+            } => writeln!(
+                builder,
+                "{indent}prescan #{index}: {argument} -> {expanded}"
+            ),
+            TraceStep::Stringize { index, result } => {
+                writeln!(builder, "{indent}stringize #{index}: {result}")
+            }
+            TraceStep::Paste {
+                left,
+                right,
+                result,
+            } => writeln!(builder, "{indent}paste: {left} ## {right} -> {result}"),
+            TraceStep::Substitution { result } => {
+                writeln!(builder, "{indent}substitution: {result}")
+            }
+            TraceStep::Rescan => writeln!(builder, "{indent}rescan"),
+        }
+        .unwrap();
+
+        for child in &node.children {
+            self.write_text(builder, *child, depth + 1);
+        }
+    }
+
+    /// Prints the tree as JSON object of `invocations`, which are nested
+    /// nodes, and `output`, whose tokens refer to ids of the nodes.
+    pub fn to_json(&self) -> String {
+        let mut builder = String::from("{\"invocations\":[");
+
+        for (idx, root) in self.roots.iter().enumerate() {
+            if idx != 0 {
+                builder.push(',');
+            }
+
+            self.write_json(&mut builder, *root);
+        }
+
+        builder.push_str("],\"output\":[");
+
+        for (idx, token) in self.output.iter().enumerate() {
+            if idx != 0 {
+                builder.push(',');
+            }
+
+            let invocations = self
+                .invocations(idx)
+                .iter()
+                .map(|id| id.0.to_string())
+                .collect::<Vec<_>>();
+
+            write!(
+                builder,
+                "{{\"token\":{},\"invocations\":[{}]}}",
+                json_string(&token.spelling),
+                invocations.join(",")
+            )
+            .unwrap();
+        }
+
+        builder.push_str("]}");
+        builder
+    }
+
+    fn write_json(&self, builder: &mut String, id: NodeId) {
+        let node = self.node(id);
+        write!(builder, "{{\"id\":{},", id.0).unwrap();
+
+        match &node.step {
+            TraceStep::Invocation {
+                name,
+                arguments,
+                location,
+            } => {
+                let arguments = match arguments {
+                    Some(arguments) => format!(
+                        "[{}]",
+                        arguments
+                            .iter()
+                            .map(|argument| json_string(argument))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    ),
+                    None => "null".to_string(),
+                };
+
+                write!(
+                    builder,
+                    "\"step\":\"invocation\",\"name\":{},\"arguments\":{arguments},\"location\":{}",
+                    json_string(name),
+                    json_string(&location.to_string())
+                )
+            }
+            TraceStep::Prescan {
+                index,
+                argument,
+                expanded,
+            } => write!(
+                builder,
+                "\"step\":\"prescan\",\"index\":{index},\"argument\":{},\"expanded\":{}",
+                json_string(argument),
+                json_string(expanded)
+            ),
+            TraceStep::Stringize { index, result } => write!(
+                builder,
+                "\"step\":\"stringize\",\"index\":{index},\"result\":{}",
+                json_string(result)
+            ),
+            TraceStep::Paste {
+                left,
+                right,
+                result,
+            } => write!(
+                builder,
+                "\"step\":\"paste\",\"left\":{},\"right\":{},\"result\":{}",
+                json_string(left),
+                json_string(right),
+                json_string(result)
+            ),
+            TraceStep::Substitution { result } => write!(
+                builder,
+                "\"step\":\"substitution\",\"result\":{}",
+                json_string(result)
+            ),
+            TraceStep::Rescan => write!(builder, "\"step\":\"rescan\""),
+        }
+        .unwrap();
+
+        builder.push_str(",\"children\":[");
+
+        for (idx, child) in node.children.iter().enumerate() {
+            if idx != 0 {
+                builder.push(',');
+            }
+
+            self.write_json(builder, *child);
+        }
+
+        builder.push_str("]}");
+    }
+}
+
+/// Spells JSON string of `content`.
+fn json_string(content: &str) -> String {
+    let mut builder = String::with_capacity(content.len() + 2);
+    builder.push('"');
+
+    for ch in content.chars() {
+        match ch {
+            '"' => builder.push_str("\\\""),
+            '\\' => builder.push_str("\\\\"),
+            '\n' => builder.push_str("\\n"),
+            ch if (ch as u32) < 0x20 => write!(builder, "\\u{:04x}", ch as u32).unwrap(),
+            ch => builder.push(ch),
+        }
+    }
+
+    builder.push('"');
+    builder
+}
@@ -0,0 +1,21 @@
+#define F(x) [x]
+F(a
+#define Q 1
+b) c Q
+F(a
+#if 0
+) b
+#endif
+c) d
+F(x
+#ifdef Q
+y
+#else
+z
+#endif
+)
+#define STR(x) #x
+#define A expanded
+STR(
+#undef Q
+A Q)
@@ -0,0 +1,16 @@
+#define ARCH arm
+#if !defined(ARCH)
+#error ARCH must be defined
+#endif
+#ifdef ARCH
+#warning building for   ARCH "target"
+#else
+#warning not reached
+#error not reached
+#endif
+#define MESSAGE "configured " "for arm"
+ARCH;
+#pragma message(MESSAGE)
+  #  pragma message "indented"
+#pragma unknown_pragma
+#warning it's only a warning
@@ -0,0 +1,58 @@
+# define SPACED 1
+#/* comment */define COMMENTED 2
+  #   define INDENTED 3
+#
+#define STR(x) #x
+#define EMPTY
+#define FN(a) a + 1
+#define NOT_FN (a) + 1
+
+SPACED; COMMENTED; INDENTED; STR(4); EMPTY; NOT_FN;
+one # two;
+
+#if 0
+#unknown directive
+it's skipped
+#pragma whatever
+#else
+5;
+#endif
+
+#ifdef SPACED
+6;
+#elif 1 / 0
+#error not reached
+#endif
+
+#ifndef SPACED
+#elif defined COMMENTED && defined(INDENTED) && !defined UNDEFINED
+7;
+#endif
+
+#undef SPACED
+#ifdef SPACED
+8;
+#elif FN(1) == 2 && (1 ? 2 : 1 / 0) == 2
+9;
+#endif
+
+#if 0
+#if 1
+#else
+#endif
+10;
+#elif -1 < 0u
+11;
+#elif 0x10 == 16 && 010 == 8 && 'a' == 97 && '\377' < 0 && (2 || 1 / 0)
+12;
+#else
+13;
+#endif
+
+#if (-1 >> 63) == -1 && (1 << 2) == 4 && 7 % 3 == 1 && ~0 == -1 && 10ULL / 3 == 3
+14;
+#endif
+
+#if UNDEFINED_IDENTIFIER == 0 && !defined(UNDEFINED_IDENTIFIER)
+15;
+#endif
@@ -0,0 +1,25 @@
+const unsigned char data[] = {
+#embed "embed/data.bin"
+};
+
+const unsigned char limited[] = {
+#embed "embed/data.bin" limit(2) prefix(0x10,) suffix(, 0)
+};
+
+#define LIMIT 1
+#define DATA "embed/data.bin"
+const unsigned char expanded[] = {
+#embed DATA __limit__(LIMIT)
+};
+
+const unsigned char empty[] = {
+#embed "embed/empty.bin" if_empty(-1) prefix(0x10,)
+};
+
+#if __has_embed("embed/data.bin") == __STDC_EMBED_FOUND__ && __has_embed(DATA limit(0)) == __STDC_EMBED_EMPTY__
+found;
+#endif
+
+#if __has_embed("embed/empty.bin") == 2 && __has_embed("embed/missing.bin") == 0 && __has_embed("embed/data.bin" gnu::offset(1)) == 0
+not_found;
+#endif
@@ -0,0 +1,35 @@
+#if __has_attribute(packed) && __has_attribute(__packed__) && __has_attribute(gnu::packed)
+has_packed;
+#endif
+
+#if __has_attribute(__gnu__::__always_inline__) && !__has_attribute(bogus)
+has_scoped;
+#endif
+
+#if __has_attribute(nodiscard) == 202003 && __has_attribute(deprecated) == 201904
+has_standard_attribute;
+#endif
+
+#if __has_c_attribute(fallthrough) >= 201904L && __has_c_attribute(gnu::packed)
+has_c_attribute;
+#endif
+
+#if !__has_c_attribute(packed) && !__has_c_attribute(clang::fallthrough)
+has_no_c_attribute;
+#endif
+
+#if __has_builtin(__builtin_expect) && !__has_builtin(__builtin_bogus)
+has_builtin;
+#endif
+
+#if defined(__has_attribute) && defined __has_c_attribute && defined(__has_builtin)
+has_operators;
+#endif
+
+#ifndef __has_feature
+#define __has_feature(x) 0
+#endif
+
+#if !__has_feature(c_alignas)
+no_feature;
+#endif
@@ -0,0 +1,21 @@
+#include "include/guarded.h"
+#include "include/guarded.h"
+#include "include/once.h"
+#include "include/../include/once.h"
+#include "include/plain.h"
+#include "include/plain.h"
+
+#define NESTED "include/nested.h"
+#include NESTED
+
+#include "include/unguarded.h"
+#include "include/unguarded.h"
+
+#undef GUARDED_H
+#include "include/guarded.h"
+
+#if 1
+#include "include/plain.h"
+#endif
+
+end;
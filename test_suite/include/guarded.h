@@ -0,0 +1,7 @@
+/* Comments around the guard keep it detectable */
+#ifndef GUARDED_H
+#define GUARDED_H
+
+int guarded;
+
+#endif /* GUARDED_H */
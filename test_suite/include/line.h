@@ -0,0 +1,2 @@
+const char *header = __FILE__;
+int header_line = __LINE__;
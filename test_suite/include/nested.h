@@ -0,0 +1,3 @@
+#include "guarded.h"
+
+int nested;
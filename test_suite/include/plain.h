@@ -0,0 +1 @@
+plain_count++;
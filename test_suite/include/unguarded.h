@@ -0,0 +1,8 @@
+#ifndef UNGUARDED_H
+#define UNGUARDED_H
+
+int unguarded;
+
+#endif
+
+unguarded_tail;
@@ -0,0 +1,19 @@
+#include <wrapper.h>
+
+#if __has_include(<wrapper.h>) && !__has_include(<missing.h>)
+has_wrapper;
+#endif
+
+#if __has_include("include/guarded.h") && defined(__has_include)
+has_quoted;
+#endif
+
+#ifdef __has_include_next
+has_include_next;
+#endif
+
+#define HEADER <wrapper.h>
+#define QUOTED "include/missing.h"
+#if __has_include(HEADER) && !__has_include(QUOTED)
+has_expanded;
+#endif
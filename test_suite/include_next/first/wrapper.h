@@ -0,0 +1,2 @@
+first_wrapper;
+#include_next <wrapper.h>
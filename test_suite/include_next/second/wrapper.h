@@ -0,0 +1,4 @@
+second_wrapper;
+#if __has_include_next(<wrapper.h>)
+#include_next <wrapper.h>
+#endif
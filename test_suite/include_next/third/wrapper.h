@@ -0,0 +1,4 @@
+third_wrapper;
+#if !__has_include_next(<wrapper.h>)
+last_wrapper;
+#endif
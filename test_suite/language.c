@@ -0,0 +1,19 @@
+%:define LIST(...) <% __VA_ARGS__ %>
+%:define CALL(f, ...) f(0 __VA_OPT__(,) __VA_ARGS__)
+%:define NAME(x) %:x
+%:define NESTED(...) __VA_OPT__(LIST(__VA_ARGS__))
+
+int a<:2:> = LIST(1, 2);
+CALL(g);
+CALL(g, 1, 2);
+NESTED() NESTED(3);
+int $dollar = 1; // comment
+const char *s = NAME(digraph);
+at@sign;
+
+#if __STDC_VERSION__ >= 201710L && __STDC__ && __STDC_HOSTED__
+gnu17;
+#endif
+#ifndef __STRICT_ANSI__
+gnu;
+#endif
@@ -0,0 +1,24 @@
+int first = __LINE__;
+const char *file = __FILE__;
+
+#line 100
+int hundred = __LINE__;
+
+#define GENERATED "generated.y"
+#define LINE 200
+#line LINE GENERATED
+int two_hundred = __LINE__;
+const char *generated = __FILE__;
+
+#if __LINE__ == 203 && defined(__FILE__) && defined __LINE__
+int remapped;
+#endif
+
+#define LINE_OF(x) __LINE__ x
+int expanded = LINE_OF(+ 1);
+int spliced = \
+    __LINE__;
+
+#include "include/line.h"
+int after = __LINE__;
+const char *after_file = __FILE__;
@@ -18,3 +18,15 @@ ADD_MULTILINE(1, 2);
 ADD_MULTILINE(1 + 1, 2);
 ADD_MULTILINE(1, 2 + 2);
 ADD_MULTILINE(1 + 1, 2 + 2);
+
+#define STR(a) #a
+#define XSTR(a) STR(a)
+#define TWICE(a) a a
+#define APPLY(f, a) f(a)
+#define SELF(a) SELF(a) a
+
+STR(  1   +  "two" );
+XSTR(ADD(1, 2));
+TWICE(ADD(1, 2));
+APPLY(IDENTITY, ADD(1, TWICE(3)));
+SELF(SELF(1));
@@ -0,0 +1,12 @@
+int answer = ANSWER;
+int size = SIZE;
+int twice = TWICE(SIZE);
+int prelude = FROM_PRELUDE;
+int imacros = FROM_IMACROS;
+const char *file = __FILE__;
+#ifdef REMOVED
+removed;
+#endif
+#if defined(EMPTY) && !defined(UNDEFINED)
+empty = EMPTY;
+#endif
@@ -0,0 +1,3 @@
+discarded_tokens;
+#pragma message("discarded")
+#define FROM_IMACROS TWICE(SIZE)
@@ -0,0 +1,2 @@
+prelude_tokens;
+#define FROM_PRELUDE (ANSWER + SIZE)
@@ -0,0 +1,27 @@
+#define CAT(a, b) a ## b
+#define XCAT(a, b) CAT(a, b)
+#define PREFIX(name) my_ ## name ## _t
+#define ONE 1
+#define GLUE x ## y ## 1
+#define HASH_HASH # ## #
+#define MKSTR(x) # x
+#define IN_BETWEEN(x) MKSTR(x)
+#define JOIN(c, d) IN_BETWEEN(c HASH_HASH d)
+#define t(x,y,z) x ## y ## z
+#define r(x,y) x ## y
+#define OPT(a, ...) a ## __VA_OPT__(_args)
+#define LIST(...) list ## __VA_ARGS__
+
+CAT(foo, bar);
+CAT(ONE, 2);
+XCAT(ONE, 2);
+PREFIX(size);
+CAT(, x) CAT(x, ) CAT(, );
+GLUE;
+char p[] = JOIN(x, y);
+int j[] = { t(1,2,3), t(,4,5), t(6,,7), t(8,9,),
+            t(10,,), t(,11,), t(,,12), t(,,) };
+int i[] = { r(2,3), r(4,), r(,5), r(,) };
+CAT(-, >) CAT(+, =) CAT(., 5) CAT(0x, 1p3);
+OPT(f) OPT(f, 1);
+LIST() LIST(_tail);
@@ -0,0 +1,16 @@
+#define SAFE_GETS(buffer) gets(buffer)
+#define OLD_COPY strcpy
+#pragma GCC poison gets strcpy
+#pragma GCC poison gets
+
+void read_line(char *line) {
+    SAFE_GETS(line);
+    OLD_COPY(line, "");
+}
+
+#define LATE SAFE_GETS(late)
+LATE;
+
+#if 0
+gets(skipped);
+#endif
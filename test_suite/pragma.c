@@ -0,0 +1,15 @@
+#define DO_PRAGMA(x) _Pragma(#x)
+#define IDENTITY(x) x
+
+a _Pragma("foo \"bar\"") b;
+DO_PRAGMA(weak sym)
+IDENTITY(_Pragma("in argument") 1);
+#pragma GCC warning "consumed"
+#pragma GCC system_header
+#pragma GCC diagnostic push
+#pragma STDC FP_CONTRACT ON
+#pragma
+#pragma custom thing(1)
+#if 0
+#pragma custom skipped
+#endif
@@ -0,0 +1,31 @@
+#define min(a, b) ((a) < (b) ? (a) : (b))
+#define LIMIT 10
+
+#pragma push_macro("min")
+#pragma push_macro("LIMIT")
+#pragma push_macro("UNDEFINED")
+#undef min
+#undef LIMIT
+#define LIMIT 20
+#define UNDEFINED 1
+int min(int, int);
+int limit = LIMIT;
+
+#pragma push_macro("LIMIT")
+#undef LIMIT
+#define LIMIT 30
+int nested = LIMIT;
+#pragma pop_macro("LIMIT")
+int outer = LIMIT;
+
+#pragma pop_macro("min")
+#pragma pop_macro("LIMIT")
+#pragma pop_macro("UNDEFINED")
+int smaller = min(1, 2);
+int restored = LIMIT;
+#ifndef UNDEFINED
+undefined;
+#endif
+
+#pragma pop_macro("LIMIT")
+int unchanged = LIMIT;
@@ -0,0 +1,28 @@
+#define x x + 1
+#define F(a) a
+#define G(a) F(a) G
+
+F(x);
+F(F(x));
+G(G(1));
+
+#undef x
+#define x 3
+#define f(a) f(x * (a))
+#undef x
+#define x 2
+#define g f
+#define z z[0]
+#define h g(~
+#define m(a) a(w)
+#define w 0,1
+#define t(a) a
+#define p() int
+#define q(x) x
+#define str(s) # s
+
+f(y+1) + f(f(z)) % t(t(g)(0) + t)(1);
+g(x+(3,4)-w) | h 5) & m
+    (f)^m(m);
+p() i[q()] = { q(1) };
+char c[2][6] = { str(hello), str() };
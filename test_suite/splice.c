@@ -0,0 +1,18 @@
+﻿#define CONSTANT_CRLF 1 + \
+    2
+#define CONSTANT_CR 3#define STR(x) # x
+#define SPLICED_IDENTIFIER 4
+
+CONSTANT_CRLF;
+CONSTANT_CR;SPLICED_\
+IDENTIFIER;
+SPLICED_\
+IDENTIFIER;
+"spliced \
+string";
+/* spliced *\
+/ CONSTANT_CR;
+// spliced line comment \
+CONSTANT_CR;
+STR(a "\\" '"');
+non_macro_identifier(1);
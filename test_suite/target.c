@@ -0,0 +1,29 @@
+int pointer_size = __SIZEOF_POINTER__;
+long long_max = __LONG_MAX__;
+unsigned long long uintmax_max = __UINTMAX_MAX__;
+unsigned long size_max = __SIZE_MAX__;
+int char_bit = __CHAR_BIT__;
+
+#if __BYTE_ORDER__ == __ORDER_LITTLE_ENDIAN__
+little_endian;
+#elif __BYTE_ORDER__ == __ORDER_BIG_ENDIAN__
+big_endian;
+#endif
+
+#if '\xff' < 0
+signed_char;
+#else
+unsigned_char;
+#endif
+
+#if __INT_MAX__ + 1 > 0 && -1 < 0u
+intmax_arithmetic;
+#endif
+
+#if defined(__x86_64__) || defined(__aarch64__) || defined(__arm__) || defined(__riscv)
+known_architecture;
+#endif
+
+#ifdef __LP64__
+lp64 = __LONG_WIDTH__;
+#endif
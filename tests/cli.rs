@@ -0,0 +1,107 @@
+use std::{
+    env, fs,
+    process::{Command, Output},
+};
+
+fn tangle(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tangle"))
+        .args(args)
+        .output()
+        .expect("Failed to execute tangle")
+}
+
+#[test]
+fn test_preprocess_files() {
+    let output_path = env::temp_dir().join(format!("tangle-{}.i", std::process::id()));
+    let output = tangle(&[
+        "-E",
+        "-P",
+        "-DLIMIT=10",
+        "-D",
+        "DEBUG",
+        "-UDEBUG",
+        "-I",
+        "test_suite/include",
+        "-o",
+        output_path.to_str().unwrap(),
+        "test_suite/include.c",
+    ]);
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let preprocessed = fs::read_to_string(&output_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+
+    assert!(!preprocessed.contains("#include"));
+    assert!(preprocessed.trim_end().ends_with("end;"));
+    assert_eq!(preprocessed.matches("int guarded;").count(), 2);
+    assert_eq!(preprocessed.matches("plain_count++;").count(), 3);
+}
+
+#[test]
+fn test_language_and_target() {
+    let input_path = env::temp_dir().join(format!("tangle-{}.c", std::process::id()));
+    fs::write(
+        &input_path,
+        "__STDC_VERSION__ __SIZEOF_POINTER__ __riscv_xlen\n\
+         #if true\nc23\n#endif\n",
+    )
+    .unwrap();
+    let input = input_path.to_str().unwrap();
+
+    let output = tangle(&["-P", "-std=c2x", "--target=riscv32", input]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "202311L 4 32 c23\n"
+    );
+
+    let output = tangle(&["-P", "-std=gnu99", "--target=x86_64-linux-gnu", input]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "199901L 8 __riscv_xlen\n"
+    );
+
+    let output = tangle(&["-std=c3", input]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = tangle(&["--target=mips", input]);
+    assert_eq!(output.status.code(), Some(2));
+    fs::remove_file(input_path).unwrap();
+}
+
+#[test]
+fn test_exit_codes() {
+    let output = tangle(&["test_suite/missing.c"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = tangle(&["--unknown"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = tangle(&[
+        "-DMESSAGE=1",
+        "-imacros",
+        "test_suite/missing.h",
+        "example.c",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("<command line>:"));
+}
+
+#[test]
+fn test_dump_macros() {
+    let output = tangle(&["-dM", "-DTWICE(x)=((x) * 2)", "-UNDEBUG", "example.c"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert!(stdout.lines().all(|line| line.starts_with("#define ")));
+    assert!(stdout
+        .lines()
+        .any(|line| line == "#define TWICE(x) ((x) * 2)"));
+    assert!(stdout.lines().any(|line| line == "#define STR(x) #x"));
+    assert!(!stdout.contains("#define __LINE__"));
+}